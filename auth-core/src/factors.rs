@@ -62,7 +62,10 @@ pub enum FlowType {
 
 /// Defines the security level provided by an authentication factor.
 /// Higher levels indicate stronger security guarantees.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
 pub enum SecurityLevel {
     /// A password or similar knowledge-based factor.
     /// Vulnerable to phishing, guessing, and social engineering.
@@ -121,6 +121,16 @@ impl MailService {
         )
         .await
     }
+
+    pub async fn send_refresh_token_reuse(&self, to: &str, app_name: &str) -> Result<()> {
+        let html = templates::refresh_token_reuse(app_name);
+        self.send(
+            to,
+            &format!("Suspicious activity on your {} account", self.app_name),
+            html,
+        )
+        .await
+    }
 }
 
 // ── Standalone render helpers (no SMTP needed, useful for previews/tests) ──
@@ -148,3 +158,7 @@ pub fn render_factor_added(factor_name: &str) -> String {
 pub fn render_factor_removed(factor_name: &str) -> String {
     templates::factor_removed(factor_name)
 }
+
+pub fn render_refresh_token_reuse(app_name: &str) -> String {
+    templates::refresh_token_reuse(app_name)
+}
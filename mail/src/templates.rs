@@ -194,3 +194,30 @@ pub fn factor_removed(factor_name: &str) -> String {
 
     email_shell("Security method removed", &preheader, inner)
 }
+
+pub fn refresh_token_reuse(app_name: &str) -> String {
+    let inner = rsx! {
+        <h1 style="margin:0 0 12px;font-size:20px;font-weight:600;color:#09090b;line-height:1.3;">"Suspicious sign-in activity"</h1>
+        <p style="margin:0 0 20px;font-size:14px;color:#52525b;line-height:1.6;">
+            "An old session token for the following application was used again, which can mean it was stolen:"
+        </p>
+        <div style="padding:12px 16px;background:#f4f4f5;border-radius:6px;font-size:14px;font-weight:500;color:#09090b;margin-bottom:28px;">
+            (app_name)
+        </div>
+        <p style="margin:0 0 0;font-size:14px;color:#52525b;line-height:1.6;">
+            "We signed you out of this application to be safe. "
+            "If you don't recognize this activity, please change your password and review your account security."
+        </p>
+        <p style="margin:32px 0 0;font-size:12px;color:#a1a1aa;line-height:1.6;">
+            "You are receiving this email because a reused token was detected on your account."
+        </p>
+    }
+    .memoize();
+
+    let preheader = format!(
+        "A reused token for {app_name} was detected and you were signed out of it. Review your account security.{}",
+        preheader_padding()
+    );
+
+    email_shell("Suspicious sign-in activity", &preheader, inner)
+}
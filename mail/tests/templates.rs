@@ -52,6 +52,13 @@ mod tests {
         assert!(html.contains("Security method removed"));
     }
 
+    #[test]
+    fn refresh_token_reuse_contains_app_name() {
+        let html = render_refresh_token_reuse("Grafana");
+        assert!(html.contains("Grafana"));
+        assert!(html.contains("Suspicious sign-in activity"));
+    }
+
     #[test]
     fn all_templates_have_logo() {
         let templates = [
@@ -61,6 +68,7 @@ mod tests {
             render_password_changed(),
             render_factor_added("Test Factor"),
             render_factor_removed("Test Factor"),
+            render_refresh_token_reuse("Test App"),
         ];
 
         for html in &templates {
@@ -80,6 +88,7 @@ mod tests {
             render_password_changed(),
             render_factor_added("Test Factor"),
             render_factor_removed("Test Factor"),
+            render_refresh_token_reuse("Test App"),
         ];
 
         for html in &templates {
@@ -1,6 +1,8 @@
 use std::time::Duration as StdDuration;
 
+use auth_core::SecurityLevel;
 use color_eyre::eyre::{Context, Result};
+use futures::TryStreamExt;
 use mongodb::{
     Client, Database, IndexModel,
     bson::{self, Bson, doc, oid::ObjectId},
@@ -10,13 +12,14 @@ use partial_struct::Partial;
 use serde::de::Deserializer;
 use serde::{Deserialize, Serialize};
 use tower_sessions::{
-    Expiry, SessionManagerLayer,
+    Expiry, Session, SessionManagerLayer,
     cookie::{SameSite, time::Duration},
 };
 use tower_sessions_redis_store::{
     RedisStore,
     fred::prelude::{ClientLike, Config, Pool},
 };
+use tracing::warn;
 use utoipa::ToSchema;
 use uuid::Uuid;
 use validator::Validate;
@@ -25,8 +28,21 @@ use visible::StructFields;
 use crate::{
     axum_error::AxumResult,
     mongo_id::{object_id_as_string_required, vec_oid_to_vec_string},
+    oidc::{
+        claims::{ClaimMapping, CustomScope},
+        client_auth::TokenEndpointAuthMethod,
+        client_secrets::{ClientSecret, stored_client_secret},
+        encryption::EncryptionKey,
+        keys::SigningAlgorithm,
+        lifetimes::TokenLifetimeOverrides,
+        subject::SubjectType,
+        token_exchange::TokenExchangePolicy,
+    },
     settings::Settings,
-    validators::slug_validator,
+    validators::{
+        claim_mappings_validator, custom_scopes_validator, https_url_validator,
+        https_urls_validator, jwks_validator, redirect_uris_validator, slug_validator,
+    },
 };
 
 macro_rules! database_object {
@@ -40,14 +56,56 @@ macro_rules! database_object {
     };
 }
 
-pub async fn init_database(settings: &Settings) -> Result<Database> {
+pub async fn init_database(
+    settings: &Settings,
+    encryption_key: &EncryptionKey,
+) -> Result<Database> {
     let client = Client::with_uri_str(&settings.db.connection_string).await?;
     let database = client.database(&settings.db.database_name);
     ensure_database_indexes(&database).await?;
+    migrate_client_secrets(&database, encryption_key).await?;
 
     Ok(database)
 }
 
+/// Hashes client secrets that earlier versions stored in plaintext, see [`ClientSecret`].
+async fn migrate_client_secrets(database: &Database, encryption_key: &EncryptionKey) -> Result<()> {
+    let applications = database.collection::<bson::Document>("applications");
+
+    let mut cursor = applications
+        .find(doc! { "client_secret": { "$type": "string" } })
+        .await
+        .wrap_err("Failed to fetch applications with plaintext secrets")?;
+
+    while let Some(application) = cursor
+        .try_next()
+        .await
+        .wrap_err("Failed to fetch applications with plaintext secrets")?
+    {
+        let (Ok(id), Ok(secret)) = (
+            application.get_object_id("_id"),
+            application.get_str("client_secret"),
+        ) else {
+            continue;
+        };
+
+        let client_secret = stored_client_secret(encryption_key, None, secret, None)?;
+
+        applications
+            .update_one(
+                doc! { "_id": id },
+                doc! {
+                    "$push": { "client_secrets": bson::to_bson(&client_secret)? },
+                    "$unset": { "client_secret": "" },
+                },
+            )
+            .await
+            .wrap_err("Failed to hash client secret")?;
+    }
+
+    Ok(())
+}
+
 async fn ensure_database_indexes(database: &Database) -> Result<()> {
     let sessions = database.collection::<bson::Document>("sessions");
 
@@ -96,6 +154,261 @@ async fn ensure_database_indexes(database: &Database) -> Result<()> {
         .await
         .wrap_err("Failed to create sessions_last_active_ttl_idx")?;
 
+    let client_sessions = database.collection::<bson::Document>("oidc_client_sessions");
+
+    client_sessions
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "sid": 1_i32, "client_id": 1_i32 })
+                .options(
+                    IndexOptions::builder()
+                        .name(Some(
+                            "oidc_client_sessions_sid_client_unique_idx".to_string(),
+                        ))
+                        .unique(true)
+                        .build(),
+                )
+                .build(),
+        )
+        .await
+        .wrap_err("Failed to create oidc_client_sessions_sid_client_unique_idx")?;
+
+    client_sessions
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "session_id": 1_i32 })
+                .options(
+                    IndexOptions::builder()
+                        .name(Some("oidc_client_sessions_session_id_idx".to_string()))
+                        .build(),
+                )
+                .build(),
+        )
+        .await
+        .wrap_err("Failed to create oidc_client_sessions_session_id_idx")?;
+
+    client_sessions
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "user_id": 1_i32 })
+                .options(
+                    IndexOptions::builder()
+                        .name(Some("oidc_client_sessions_user_id_idx".to_string()))
+                        .build(),
+                )
+                .build(),
+        )
+        .await
+        .wrap_err("Failed to create oidc_client_sessions_user_id_idx")?;
+
+    database
+        .collection::<bson::Document>("backchannel_logout_deliveries")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "created_at": 1_i32 })
+                .options(
+                    IndexOptions::builder()
+                        .name(Some("backchannel_logout_deliveries_ttl_idx".to_string()))
+                        .expire_after(StdDuration::from_secs(60 * 60 * 24 * 30))
+                        .build(),
+                )
+                .build(),
+        )
+        .await
+        .wrap_err("Failed to create backchannel_logout_deliveries_ttl_idx")?;
+
+    let device_authorizations = database.collection::<bson::Document>("device_authorizations");
+
+    device_authorizations
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "device_code_hash": 1_i32 })
+                .options(
+                    IndexOptions::builder()
+                        .name(Some(
+                            "device_authorizations_device_code_unique_idx".to_string(),
+                        ))
+                        .unique(true)
+                        .build(),
+                )
+                .build(),
+        )
+        .await
+        .wrap_err("Failed to create device_authorizations_device_code_unique_idx")?;
+
+    device_authorizations
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "user_code": 1_i32 })
+                .options(
+                    IndexOptions::builder()
+                        .name(Some(
+                            "device_authorizations_user_code_unique_idx".to_string(),
+                        ))
+                        .unique(true)
+                        .build(),
+                )
+                .build(),
+        )
+        .await
+        .wrap_err("Failed to create device_authorizations_user_code_unique_idx")?;
+
+    device_authorizations
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "expires_at": 1_i32 })
+                .options(
+                    IndexOptions::builder()
+                        .name(Some("device_authorizations_ttl_idx".to_string()))
+                        .expire_after(StdDuration::ZERO)
+                        .build(),
+                )
+                .build(),
+        )
+        .await
+        .wrap_err("Failed to create device_authorizations_ttl_idx")?;
+
+    database
+        .collection::<bson::Document>("groups")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "slug": 1_i32 })
+                .options(
+                    IndexOptions::builder()
+                        .name(Some("groups_slug_unique_idx".to_string()))
+                        .unique(true)
+                        .build(),
+                )
+                .build(),
+        )
+        .await
+        .wrap_err("Failed to create groups_slug_unique_idx")?;
+
+    database
+        .collection::<bson::Document>("oidc_consents")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "user_id": 1_i32, "client_id": 1_i32 })
+                .options(
+                    IndexOptions::builder()
+                        .name(Some("oidc_consents_user_client_unique_idx".to_string()))
+                        .unique(true)
+                        .build(),
+                )
+                .build(),
+        )
+        .await
+        .wrap_err("Failed to create oidc_consents_user_client_unique_idx")?;
+
+    let initial_access_tokens = database.collection::<bson::Document>("initial_access_tokens");
+
+    initial_access_tokens
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "token_hash": 1_i32 })
+                .options(
+                    IndexOptions::builder()
+                        .name(Some(
+                            "initial_access_tokens_token_hash_unique_idx".to_string(),
+                        ))
+                        .unique(true)
+                        .build(),
+                )
+                .build(),
+        )
+        .await
+        .wrap_err("Failed to create initial_access_tokens_token_hash_unique_idx")?;
+
+    // Tokens without an expiry have no `expires_at` and are never removed
+    initial_access_tokens
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "expires_at": 1_i32 })
+                .options(
+                    IndexOptions::builder()
+                        .name(Some("initial_access_tokens_ttl_idx".to_string()))
+                        .expire_after(StdDuration::ZERO)
+                        .build(),
+                )
+                .build(),
+        )
+        .await
+        .wrap_err("Failed to create initial_access_tokens_ttl_idx")?;
+
+    let pushed_authorization_requests =
+        database.collection::<bson::Document>("pushed_authorization_requests");
+
+    pushed_authorization_requests
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "request_uri_hash": 1_i32 })
+                .options(
+                    IndexOptions::builder()
+                        .name(Some(
+                            "pushed_authorization_requests_request_uri_unique_idx".to_string(),
+                        ))
+                        .unique(true)
+                        .build(),
+                )
+                .build(),
+        )
+        .await
+        .wrap_err("Failed to create pushed_authorization_requests_request_uri_unique_idx")?;
+
+    pushed_authorization_requests
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "expires_at": 1_i32 })
+                .options(
+                    IndexOptions::builder()
+                        .name(Some("pushed_authorization_requests_ttl_idx".to_string()))
+                        .expire_after(StdDuration::ZERO)
+                        .build(),
+                )
+                .build(),
+        )
+        .await
+        .wrap_err("Failed to create pushed_authorization_requests_ttl_idx")?;
+
+    database
+        .collection::<bson::Document>("pairwise_subjects")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "subject": 1_i32 })
+                .options(
+                    IndexOptions::builder()
+                        .name(Some("pairwise_subjects_subject_unique_idx".to_string()))
+                        .unique(true)
+                        .build(),
+                )
+                .build(),
+        )
+        .await
+        .wrap_err("Failed to create pairwise_subjects_subject_unique_idx")?;
+
+    // Only one key per algorithm may be active and one upcoming, even with several instances
+    let signing_keys = database.collection::<bson::Document>("oidc_signing_keys");
+
+    for status in ["active", "next"] {
+        let name = format!("oidc_signing_keys_algorithm_{status}_unique_idx");
+
+        signing_keys
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! { "algorithm": 1_i32, "status": 1_i32 })
+                    .options(
+                        IndexOptions::builder()
+                            .name(Some(name.clone()))
+                            .unique(true)
+                            .partial_filter_expression(doc! { "status": status })
+                            .build(),
+                    )
+                    .build(),
+            )
+            .await
+            .wrap_err_with(|| format!("Failed to create {name}"))?;
+    }
+
     Ok(())
 }
 
@@ -180,6 +493,25 @@ pub async fn record_session(
     Ok(())
 }
 
+/// Destroys the given session and removes its `SessionRecord`.
+/// Returns the ID the session had, if it was ever saved.
+pub async fn destroy_session(database: &Database, session: &Session) -> AxumResult<Option<String>> {
+    let session_id = session.id().map(|id| id.to_string());
+
+    session.flush().await?;
+
+    if let Some(session_id) = &session_id
+        && let Err(error) = database
+            .collection::<SessionRecord>("sessions")
+            .delete_one(doc! { "_id": session_id })
+            .await
+    {
+        warn!(error = ?error, "Failed to clean up session record after logout");
+    }
+
+    Ok(session_id)
+}
+
 #[derive(Debug, Serialize, Deserialize, ToSchema, Clone)]
 pub struct TOTPFactor {
     pub secret: String,
@@ -414,6 +746,15 @@ database_object!(User {
     groups: Vec<ObjectId>,
 });
 
+database_object!(Group {
+    #[serde(rename = "_id", with = "object_id_as_string_required")]
+    #[schema(value_type = String)]
+    id: ObjectId,
+    name: String,
+    /// Identifier of the group in the `groups` claim.
+    slug: String,
+});
+
 #[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
 #[serde(rename_all = "lowercase")]
 pub enum ClientType {
@@ -430,12 +771,120 @@ database_object!(Application {
     icon: Option<String>,
     client_type: ClientType,
     client_id: String,
-    client_secret: Option<String>,
+
+    /// Secrets a confidential application authenticates with. Several are valid at once during a rotation.
+    #[serde(default)]
+    client_secrets: Vec<ClientSecret>,
+
     redirect_uris: Vec<String>,
 
     #[serde(with = "vec_oid_to_vec_string")]
     #[schema(value_type = Vec<String>)]
     allowed_groups: Vec<ObjectId>,
+
+    /// Reject authorization requests that don't carry a PKCE code challenge.
+    #[serde(default)]
+    require_pkce: bool,
+
+    /// Allowed `post_logout_redirect_uri` values for RP-initiated logout.
+    #[serde(default)]
+    post_logout_redirect_uris: Vec<String>,
+
+    /// Endpoint that receives back-channel logout tokens.
+    #[serde(default)]
+    backchannel_logout_uri: Option<String>,
+
+    /// Page loaded in an iframe to log the user out of the application.
+    #[serde(default)]
+    frontchannel_logout_uri: Option<String>,
+
+    /// Whether `iss` and `sid` are appended to the front-channel logout URI.
+    #[serde(default)]
+    frontchannel_logout_session_required: bool,
+
+    /// Algorithm for this application's ID tokens. Falls back to the server default.
+    #[serde(default)]
+    id_token_signed_response_alg: Option<SigningAlgorithm>,
+
+    /// Whether a confidential application may obtain tokens for itself with the `client_credentials` grant.
+    #[serde(default)]
+    allow_client_credentials: bool,
+
+    /// Scopes that may be requested with the `client_credentials` grant.
+    #[serde(default)]
+    client_credentials_scopes: Vec<String>,
+
+    /// Audiences that may be requested with the `client_credentials` grant.
+    #[serde(default)]
+    client_credentials_audiences: Vec<String>,
+
+    /// Whether the application may sign users in with the device authorization grant.
+    #[serde(default)]
+    allow_device_authorization: bool,
+
+    /// Groups that may appear in this application's `groups` claim. Defaults to `allowed_groups`,
+    /// and to all of the user's groups when both are empty.
+    #[serde(with = "vec_oid_to_vec_string", default)]
+    #[schema(value_type = Vec<String>)]
+    exposed_groups: Vec<ObjectId>,
+
+    /// First-party application that signs users in without asking for consent.
+    #[serde(default)]
+    trusted: bool,
+
+    /// Scopes this application accepts on top of the standard ones.
+    #[serde(default)]
+    custom_scopes: Vec<CustomScope>,
+
+    /// Claims added to tokens and userinfo from user attributes and groups.
+    #[serde(default)]
+    claim_mappings: Vec<ClaimMapping>,
+
+    /// Users have to sign in with a factor of at least this level, stepping up if needed.
+    #[serde(default)]
+    min_security_level: Option<SecurityLevel>,
+
+    /// Authorization requests have to be pushed to the PAR endpoint first (RFC 9126).
+    #[serde(default)]
+    require_pushed_authorization_requests: bool,
+
+    /// JWK Set with the keys the application signs request objects with.
+    #[serde(default)]
+    #[schema(value_type = Option<Object>)]
+    jwks: Option<serde_json::Value>,
+
+    /// Where to fetch the application's JWK Set from, when `jwks` isn't set.
+    #[serde(default)]
+    jwks_uri: Option<String>,
+
+    /// URLs the application may pass as `request_uri` to have its request object fetched.
+    #[serde(default)]
+    request_uris: Vec<String>,
+
+    /// The only way the application may authenticate. Any method it has credentials for when not set.
+    #[serde(default)]
+    token_endpoint_auth_method: Option<TokenEndpointAuthMethod>,
+
+    /// Audiences the application may exchange user tokens for (RFC 8693).
+    #[serde(default)]
+    token_exchange_policies: Vec<TokenExchangePolicy>,
+
+    /// Whether the application sees the user's UUID or a per-sector `sub`.
+    #[serde(default)]
+    subject_type: SubjectType,
+
+    /// JSON array of the redirect URIs of every application in the sector. Its host is the
+    /// sector of pairwise subjects, instead of the host of the redirect URIs.
+    #[serde(default)]
+    sector_identifier_uri: Option<String>,
+
+    /// Lifetimes of this application's codes and tokens that differ from the server settings.
+    #[serde(default)]
+    token_lifetimes: TokenLifetimeOverrides,
+
+    /// Hash of the token used to manage a dynamically registered client (RFC 7592).
+    #[serde(default)]
+    registration_access_token_hash: Option<String>,
 });
 
 #[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Partial, Validate)]
@@ -462,11 +911,122 @@ pub struct PublicApplication {
 
     pub client_id: String,
 
+    #[validate(custom(function = "redirect_uris_validator"))]
     pub redirect_uris: Vec<String>,
 
     #[serde(with = "vec_oid_to_vec_string")]
     #[schema(value_type = Vec<String>)]
     pub allowed_groups: Vec<ObjectId>,
+
+    /// Reject authorization requests that don't carry a PKCE code challenge.
+    #[serde(default)]
+    pub require_pkce: bool,
+
+    /// Allowed `post_logout_redirect_uri` values for RP-initiated logout.
+    #[validate(custom(function = "redirect_uris_validator"))]
+    #[serde(default)]
+    pub post_logout_redirect_uris: Vec<String>,
+
+    /// Endpoint that receives back-channel logout tokens. Has to use `https`.
+    #[validate(custom(function = "https_url_validator"))]
+    #[serde(default)]
+    pub backchannel_logout_uri: Option<String>,
+
+    /// Page loaded in an iframe to log the user out of the application. Has to use `https`.
+    #[validate(custom(function = "https_url_validator"))]
+    #[serde(default)]
+    pub frontchannel_logout_uri: Option<String>,
+
+    /// Whether `iss` and `sid` are appended to the front-channel logout URI.
+    #[serde(default)]
+    pub frontchannel_logout_session_required: bool,
+
+    /// Algorithm for this application's ID tokens. Falls back to the server default.
+    #[serde(default)]
+    pub id_token_signed_response_alg: Option<SigningAlgorithm>,
+
+    /// Whether a confidential application may obtain tokens for itself with the `client_credentials` grant.
+    #[serde(default)]
+    pub allow_client_credentials: bool,
+
+    /// Scopes that may be requested with the `client_credentials` grant.
+    #[serde(default)]
+    pub client_credentials_scopes: Vec<String>,
+
+    /// Audiences that may be requested with the `client_credentials` grant.
+    #[serde(default)]
+    pub client_credentials_audiences: Vec<String>,
+
+    /// Whether the application may sign users in with the device authorization grant.
+    #[serde(default)]
+    pub allow_device_authorization: bool,
+
+    /// Groups that may appear in this application's `groups` claim. Defaults to `allowed_groups`,
+    /// and to all of the user's groups when both are empty.
+    #[serde(with = "vec_oid_to_vec_string", default)]
+    #[schema(value_type = Vec<String>)]
+    pub exposed_groups: Vec<ObjectId>,
+
+    /// First-party application that signs users in without asking for consent.
+    #[serde(default)]
+    pub trusted: bool,
+
+    /// Scopes this application accepts on top of the standard ones.
+    #[validate(custom(function = "custom_scopes_validator"))]
+    #[serde(default)]
+    pub custom_scopes: Vec<CustomScope>,
+
+    /// Claims added to tokens and userinfo from user attributes and groups.
+    #[validate(custom(function = "claim_mappings_validator"))]
+    #[serde(default)]
+    pub claim_mappings: Vec<ClaimMapping>,
+
+    /// Users have to sign in with a factor of at least this level, stepping up if needed.
+    #[serde(default)]
+    pub min_security_level: Option<SecurityLevel>,
+
+    /// Authorization requests have to be pushed to the PAR endpoint first (RFC 9126).
+    #[serde(default)]
+    pub require_pushed_authorization_requests: bool,
+
+    /// JWK Set with the keys the application signs request objects with.
+    #[validate(custom(function = "jwks_validator"))]
+    #[serde(default)]
+    #[schema(value_type = Option<Object>)]
+    pub jwks: Option<serde_json::Value>,
+
+    /// Where to fetch the application's JWK Set from, when `jwks` isn't set. Has to use `https`.
+    #[validate(custom(function = "https_url_validator"))]
+    #[serde(default)]
+    pub jwks_uri: Option<String>,
+
+    /// URLs the application may pass as `request_uri` to have its request object fetched. Have to use `https`.
+    #[validate(custom(function = "https_urls_validator"))]
+    #[serde(default)]
+    pub request_uris: Vec<String>,
+
+    /// The only way the application may authenticate. Any method it has credentials for when not set.
+    #[serde(default)]
+    pub token_endpoint_auth_method: Option<TokenEndpointAuthMethod>,
+
+    /// Audiences the application may exchange user tokens for (RFC 8693).
+    #[serde(default)]
+    pub token_exchange_policies: Vec<TokenExchangePolicy>,
+
+    /// Whether the application sees the user's UUID or a per-sector `sub`.
+    #[serde(default)]
+    pub subject_type: SubjectType,
+
+    /// JSON array of the redirect URIs of every application in the sector. Its host is the
+    /// sector of pairwise subjects, instead of the host of the redirect URIs. Has to use `https`.
+    #[validate(custom(function = "https_url_validator"))]
+    #[serde(default)]
+    pub sector_identifier_uri: Option<String>,
+
+    /// Lifetimes of this application's codes and tokens that differ from the server settings.
+    #[validate(nested)]
+    #[serde(default)]
+    pub token_lifetimes: TokenLifetimeOverrides,
 }
 
 impl Application {
@@ -480,6 +1040,30 @@ impl Application {
             client_id: self.client_id.clone(),
             redirect_uris: self.redirect_uris.clone(),
             allowed_groups: self.allowed_groups.clone(),
+            require_pkce: self.require_pkce,
+            post_logout_redirect_uris: self.post_logout_redirect_uris.clone(),
+            backchannel_logout_uri: self.backchannel_logout_uri.clone(),
+            frontchannel_logout_uri: self.frontchannel_logout_uri.clone(),
+            frontchannel_logout_session_required: self.frontchannel_logout_session_required,
+            id_token_signed_response_alg: self.id_token_signed_response_alg,
+            allow_client_credentials: self.allow_client_credentials,
+            client_credentials_scopes: self.client_credentials_scopes.clone(),
+            client_credentials_audiences: self.client_credentials_audiences.clone(),
+            allow_device_authorization: self.allow_device_authorization,
+            exposed_groups: self.exposed_groups.clone(),
+            trusted: self.trusted,
+            custom_scopes: self.custom_scopes.clone(),
+            claim_mappings: self.claim_mappings.clone(),
+            min_security_level: self.min_security_level,
+            require_pushed_authorization_requests: self.require_pushed_authorization_requests,
+            jwks: self.jwks.clone(),
+            jwks_uri: self.jwks_uri.clone(),
+            request_uris: self.request_uris.clone(),
+            token_endpoint_auth_method: self.token_endpoint_auth_method,
+            token_exchange_policies: self.token_exchange_policies.clone(),
+            subject_type: self.subject_type,
+            sector_identifier_uri: self.sector_identifier_uri.clone(),
+            token_lifetimes: self.token_lifetimes.clone(),
         }
     }
 }
@@ -24,7 +24,12 @@ use utoipa::OpenApi;
 use crate::{
     database::{init_database, init_session_store},
     init::{init_axum, init_listener, init_tracing},
-    oidc::init_oidc_keys,
+    oidc::{
+        encryption::init_encryption_key,
+        init_http_client,
+        keys::{init_oidc_keys, spawn_key_ring_refresh},
+        subject::init_pairwise_salt,
+    },
     settings::Settings,
     state::AppState,
     webauthn::init_webauthn,
@@ -49,7 +54,9 @@ async fn main() -> Result<()> {
 
     let settings = Arc::new(Settings::try_load()?);
 
-    let database = init_database(&settings).await?;
+    let encryption_key = init_encryption_key(&settings.oidc)?;
+
+    let database = init_database(&settings, &encryption_key).await?;
 
     let webauthn = init_webauthn(&settings)?;
 
@@ -61,7 +68,10 @@ async fn main() -> Result<()> {
         ))
     });
 
-    let oidc_keys = init_oidc_keys(&settings.oidc.signing_key_file)?;
+    let oidc_keys = init_oidc_keys(&database, &settings.oidc, &encryption_key).await?;
+    let pairwise_salt = init_pairwise_salt(&database).await?;
+
+    let http_client = init_http_client()?;
 
     let (session_layer, redis_pool) = init_session_store(&settings).await?;
 
@@ -72,8 +82,13 @@ async fn main() -> Result<()> {
         mail_service,
         oidc_keys,
         redis_pool,
+        http_client,
+        pairwise_salt,
+        encryption_key,
     };
 
+    spawn_key_ring_refresh(app_state.clone());
+
     let app = init_axum(app_state, session_layer).await?;
     let listener = init_listener(&settings).await?;
 
@@ -11,6 +11,7 @@ use utoipa::ToSchema;
 use crate::{
     axum_error::{AxumError, AxumResult},
     database::record_session,
+    oidc::authentication::step_up_pending,
     routes::api::AuthState,
     state::AppState,
 };
@@ -97,7 +98,8 @@ pub async fn require_first_factor(
         return Err(AxumError::unauthorized(eyre::eyre!("Unauthorized")));
     }
 
-    if auth_state == AuthState::Authenticated {
+    // Signed-in users may only add a factor when an application asks them to step up
+    if auth_state == AuthState::Authenticated && !step_up_pending(&session).await? {
         return Err(AxumError::forbidden(eyre::eyre!("Already logged in")));
     }
 
@@ -1,39 +1,115 @@
-use std::sync::Arc;
+pub mod authentication;
+pub mod claims;
+pub mod client_auth;
+pub mod client_keys;
+pub mod client_secrets;
+pub mod consent;
+pub mod device;
+pub mod dpop;
+pub mod encryption;
+pub mod keys;
+pub mod lifetimes;
+pub mod logout;
+pub mod par;
+pub mod pkce;
+pub mod registration;
+pub mod request_object;
+pub mod revocation;
+pub mod subject;
+pub mod token_exchange;
+
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
-use color_eyre::eyre::{Context, Result};
-use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header};
+use color_eyre::eyre::{Context, Result, bail, eyre};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
 use openidconnect::{
-    AuthUrl, EmptyAdditionalProviderMetadata, IssuerUrl, JsonWebKeyId, JsonWebKeySetUrl,
-    PrivateSigningKey, ResponseTypes, Scope, TokenUrl, UserInfoUrl,
+    AdditionalClaims, AdditionalProviderMetadata, AuthUrl, AuthenticationContextClass, IssuerUrl,
+    JsonWebKeyId, JsonWebKeySetUrl, PkceCodeChallengeMethod, PrivateSigningKey, RegistrationUrl,
+    ResponseTypes, Scope, SigningError, TokenUrl, UserInfoUrl,
     core::{
-        CoreClaimName, CoreJsonWebKey, CoreJsonWebKeySet, CoreJwsSigningAlgorithm,
-        CoreProviderMetadata, CoreResponseType, CoreRsaPrivateSigningKey,
-        CoreSubjectIdentifierType,
+        CoreAuthDisplay, CoreClaimName, CoreClaimType, CoreClientAuthMethod, CoreGenderClaim,
+        CoreGrantType, CoreJsonCurveType, CoreJsonWebKey, CoreJsonWebKeySet,
+        CoreJweContentEncryptionAlgorithm, CoreJweKeyManagementAlgorithm, CoreJwsSigningAlgorithm,
+        CoreResponseMode, CoreResponseType, CoreSubjectIdentifierType,
     },
 };
+use ring::{
+    rand::SystemRandom,
+    signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, Ed25519KeyPair, KeyPair},
+};
 use rsa::pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey, EncodeRsaPublicKey};
-use rsa::pkcs8::LineEnding;
-use rsa::{RsaPrivateKey, RsaPublicKey};
+use rsa::pkcs8::{LineEnding, SecretDocument};
+use rsa::{RsaPrivateKey, RsaPublicKey, traits::PublicKeyParts};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use tracing::info;
 
+use crate::{
+    oidc::{
+        authentication::{ACR_VALUES, SessionAuthentication},
+        claims::GROUPS_SCOPE,
+        client_auth::TokenEndpointAuthMethod,
+        client_keys::{CLIENT_SECRET_SIGNING_ALGORITHMS, CLIENT_SIGNING_ALGORITHMS, jws_algorithm},
+        dpop::ConfirmationClaim,
+        keys::{KeyStatus, SigningAlgorithm},
+        logout::LogoutTokenClaims,
+        pkce::CodeChallengeMethod,
+        token_exchange::{ActorClaim, TOKEN_EXCHANGE_GRANT_TYPE},
+    },
+    settings::Oidc,
+};
+
 /// RSA key size for OIDC signing
 const RSA_KEY_BITS: usize = 2048;
 
-/// OIDC signing key data stored in AppState
-pub struct OidcKeys {
-    /// RSA private signing key (from `openidconnect` crate) — used for ID token signing.
-    pub signing_key: CoreRsaPrivateSigningKey,
-    /// JWKS for the `/jwks` endpoint.
-    pub jwks: CoreJsonWebKeySet,
-    /// jsonwebtoken encoding key — used for access token JWTs.
+/// `typ` header of access tokens (RFC 9068, section 2.1).
+const ACCESS_TOKEN_JWT_TYPE: &str = "at+jwt";
+
+/// Kinds of JWTs this server signs, told apart by their `typ` header so one can't be passed
+/// off as another.
+#[derive(Debug, Clone, Copy)]
+enum JwtType {
+    Access,
+    Id,
+}
+
+impl JwtType {
+    fn matches(self, typ: Option<&str>) -> bool {
+        match self {
+            JwtType::Access => typ.is_some_and(|typ| {
+                typ.eq_ignore_ascii_case(ACCESS_TOKEN_JWT_TYPE)
+                    || typ.eq_ignore_ascii_case("application/at+jwt")
+            }),
+            // The openidconnect crate leaves `typ` out of ID tokens
+            JwtType::Id => typ.is_none_or(|typ| typ.eq_ignore_ascii_case("JWT")),
+        }
+    }
+}
+
+/// A key of the signing key ring, ready for signing and verification.
+pub struct SigningKey {
+    /// Key ID used for JWT headers.
+    pub kid: String,
+    pub algorithm: SigningAlgorithm,
+    pub status: KeyStatus,
+    /// Public key published in the JWKS.
+    pub jwk: CoreJsonWebKey,
+    /// jsonwebtoken encoding key — used for ID token and access token JWTs.
     pub encoding_key: EncodingKey,
     /// jsonwebtoken decoding key — used for access token verification (e.g. userinfo).
     pub decoding_key: DecodingKey,
-    /// Key ID used for JWT headers.
-    pub kid: String,
+}
+
+/// OIDC signing key ring stored in AppState, with an active key for every [`SigningAlgorithm`].
+/// The keys are replaced as a whole whenever the ring is reloaded from the database.
+pub struct OidcKeys {
+    keys: RwLock<Vec<Arc<SigningKey>>>,
+    /// Algorithm used unless an application asks for another one.
+    default_algorithm: SigningAlgorithm,
 }
 
 /// Access token claims (not covered by the openidconnect crate — access tokens are opaque there).
@@ -46,6 +122,86 @@ pub struct AccessTokenClaims {
     pub iat: usize,
     pub scope: String,
     pub client_id: String,
+    /// Unique token ID, used to revoke individual access tokens.
+    #[serde(default)]
+    pub jti: String,
+    /// Set when the token was obtained through delegation, see [`token_exchange`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<ActorClaim>,
+    /// Set when the token is bound to a DPoP key, see [`dpop`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<ConfirmationClaim>,
+    /// Claims granted by scopes, see [`claims::extra_claims`].
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// ID token claims not covered by `CoreIdTokenClaims`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ExtraIdTokenClaims {
+    /// SSO session ID, used by relying parties to match logout notifications.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// Claims granted by scopes, see [`claims::extra_claims`].
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl AdditionalClaims for ExtraIdTokenClaims {}
+
+pub type IdTokenClaims = openidconnect::IdTokenClaims<ExtraIdTokenClaims, CoreGenderClaim>;
+
+pub type IdToken = openidconnect::IdToken<
+    ExtraIdTokenClaims,
+    CoreGenderClaim,
+    CoreJweContentEncryptionAlgorithm,
+    CoreJwsSigningAlgorithm,
+>;
+
+/// `aud` claim, which may be a single string or an array.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Audiences {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl Audiences {
+    pub fn contains(&self, audience: &str) -> bool {
+        match self {
+            Audiences::Single(aud) => aud == audience,
+            Audiences::Multiple(auds) => auds.iter().any(|aud| aud == audience),
+        }
+    }
+
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            Audiences::Single(aud) => vec![aud],
+            Audiences::Multiple(auds) => auds,
+        }
+    }
+
+    /// The audience to treat as the client the token was issued to.
+    pub fn primary(&self) -> Option<&str> {
+        match self {
+            Audiences::Single(aud) => Some(aud),
+            Audiences::Multiple(auds) => auds.first().map(String::as_str),
+        }
+    }
+}
+
+/// Claims read from an `id_token_hint`.
+#[derive(Debug, Deserialize)]
+pub struct IdTokenHintClaims {
+    pub sub: String,
+    pub aud: Audiences,
+    pub exp: usize,
+    /// Not set by this server, but checked like an access token's if present.
+    #[serde(default)]
+    pub jti: Option<String>,
+    /// Not set by this server, but checked like an access token's if present.
+    #[serde(default)]
+    pub cnf: Option<ConfirmationClaim>,
 }
 
 /// Authorization code stored in MongoDB.
@@ -57,7 +213,22 @@ pub struct AuthorizationCode {
     pub redirect_uri: String,
     pub scope: String,
     pub nonce: Option<String>,
+    /// SSO session the code was issued from.
+    #[serde(default)]
+    pub sid: Option<String>,
+    #[serde(default)]
+    pub session_id: Option<String>,
+    #[serde(default)]
+    pub code_challenge: Option<String>,
+    #[serde(default)]
+    pub code_challenge_method: Option<CodeChallengeMethod>,
+    /// How the user signed in, for the `auth_time`, `acr` and `amr` claims.
+    #[serde(default)]
+    pub authentication: Option<SessionAuthentication>,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Set from the application's code lifetime. Codes issued before it was stored last 10 minutes.
+    #[serde(default)]
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
     pub used: bool,
 }
 
@@ -68,8 +239,72 @@ pub struct RefreshToken {
     pub client_id: String,
     pub user_id: String,
     pub scope: String,
+    /// Shared by every token rotated from the same authorization grant.
+    #[serde(default)]
+    pub grant_id: Option<String>,
+    /// When the grant was authorized, for absolute lifetimes. Stays the same when rotating.
+    #[serde(default)]
+    pub grant_created_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// SSO session the grant was authorized in, for tokens bound to it.
+    #[serde(default)]
+    pub session_id: Option<String>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub revoked: bool,
+    /// Set once the token has been exchanged for a new one. Presenting it again means it leaked.
+    #[serde(default)]
+    pub rotated: bool,
+    /// Thumbprint of the DPoP key the token is bound to. Only tokens of public clients are bound.
+    #[serde(default)]
+    pub dpop_jkt: Option<String>,
+}
+
+/// Generate a new private key for `algorithm`, encoded as PKCS#1 PEM for RSA and PKCS#8 PEM otherwise.
+pub fn generate_private_key_pem(algorithm: SigningAlgorithm) -> Result<String> {
+    let rng = SystemRandom::new();
+
+    match algorithm {
+        SigningAlgorithm::Rs256 => {
+            info!("Generating new OIDC signing key (RSA {RSA_KEY_BITS} bits)...");
+            let mut rng = rsa::rand_core::OsRng;
+            let private_key = RsaPrivateKey::new(&mut rng, RSA_KEY_BITS)
+                .wrap_err("Failed to generate RSA key")?;
+
+            let pem = private_key
+                .to_pkcs1_pem(LineEnding::LF)
+                .wrap_err("Failed to encode RSA key to PEM")?;
+
+            Ok(pem.to_string())
+        }
+        SigningAlgorithm::Es256 => {
+            info!("Generating new OIDC signing key (P-256)...");
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                .map_err(|_| eyre!("Failed to generate P-256 key"))?;
+
+            encode_pkcs8_pem(pkcs8.as_ref())
+        }
+        SigningAlgorithm::EdDsa => {
+            info!("Generating new OIDC signing key (Ed25519)...");
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng)
+                .map_err(|_| eyre!("Failed to generate Ed25519 key"))?;
+
+            encode_pkcs8_pem(pkcs8.as_ref())
+        }
+    }
+}
+
+fn encode_pkcs8_pem(der: &[u8]) -> Result<String> {
+    let document = SecretDocument::try_from(der).wrap_err("Invalid PKCS#8 key")?;
+    let pem = document
+        .to_pem("PRIVATE KEY", LineEnding::LF)
+        .wrap_err("Failed to encode key to PEM")?;
+
+    Ok(pem.to_string())
+}
+
+fn decode_pkcs8_pem(pem: &str) -> Result<SecretDocument> {
+    let (_, document) = SecretDocument::from_pem(pem).wrap_err("Failed to parse PEM key")?;
+
+    Ok(document)
 }
 
 /// Generate an OIDC signing key file at the default path.
@@ -79,117 +314,357 @@ pub fn generate_oidc_key_file(key_file: &str) -> Result<()> {
         return Ok(());
     }
 
-    info!("Generating new OIDC signing key ({RSA_KEY_BITS} bits)...");
-    let mut rng = rsa::rand_core::OsRng;
-    let private_key =
-        RsaPrivateKey::new(&mut rng, RSA_KEY_BITS).wrap_err("Failed to generate RSA key")?;
-
-    let pem = private_key
-        .to_pkcs1_pem(LineEnding::LF)
-        .wrap_err("Failed to encode RSA key to PEM")?;
-    std::fs::write(key_file, pem.as_str()).wrap_err("Failed to write OIDC signing key")?;
+    let pem = generate_private_key_pem(SigningAlgorithm::Rs256)?;
+    std::fs::write(key_file, pem).wrap_err("Failed to write OIDC signing key")?;
     info!("OIDC signing key saved to {key_file}");
 
     Ok(())
 }
 
-/// Initialize OIDC keys — load from file specified in config, or generate new if missing.
-pub fn init_oidc_keys(key_file: &str) -> Result<Arc<OidcKeys>> {
-    let (private_key, public_key) = if std::path::Path::new(key_file).exists() {
-        info!("Loading OIDC signing key from {key_file}");
-        let pem = std::fs::read_to_string(key_file).wrap_err("Failed to read OIDC signing key")?;
-        let private_key =
-            RsaPrivateKey::from_pkcs1_pem(&pem).wrap_err("Failed to parse OIDC signing key")?;
-        let public_key = RsaPublicKey::from(&private_key);
-        (private_key, public_key)
-    } else {
-        info!("Generating new OIDC signing key ({RSA_KEY_BITS} bits)...");
-        let mut rng = rsa::rand_core::OsRng;
-        let private_key =
-            RsaPrivateKey::new(&mut rng, RSA_KEY_BITS).wrap_err("Failed to generate RSA key")?;
-        let public_key = RsaPublicKey::from(&private_key);
-
-        // Save for persistence across restarts
-        let pem = private_key
-            .to_pkcs1_pem(LineEnding::LF)
-            .wrap_err("Failed to encode RSA key to PEM")?;
-        std::fs::write(key_file, pem.as_str()).wrap_err("Failed to write OIDC signing key")?;
-        info!("OIDC signing key saved to {key_file}");
-
-        (private_key, public_key)
-    };
+impl SigningKey {
+    /// Build a ring key from a PEM private key generated by [`generate_private_key_pem`].
+    pub fn from_pem(pem: &str, algorithm: SigningAlgorithm, status: KeyStatus) -> Result<Self> {
+        match algorithm {
+            SigningAlgorithm::Rs256 => {
+                let private_key = RsaPrivateKey::from_pkcs1_pem(pem)
+                    .wrap_err("Failed to parse OIDC signing key")?;
+                let public_key = RsaPublicKey::from(&private_key);
 
-    // Derive a stable kid from the public key hash
-    let kid = {
-        let der = public_key
-            .to_pkcs1_der()
-            .wrap_err("Failed to encode public key DER")?;
-        let hash = Sha256::digest(der.as_bytes());
-        URL_SAFE_NO_PAD.encode(&hash[..8])
-    };
+                let der = public_key
+                    .to_pkcs1_der()
+                    .wrap_err("Failed to encode public key DER")?;
+                let kid = key_id(der.as_bytes());
 
-    // Build the openidconnect signing key + JWKS
-    let private_pem = private_key
-        .to_pkcs1_pem(LineEnding::LF)
-        .wrap_err("Failed to encode private key")?;
+                let n = public_key.n().to_bytes_be();
+                let e = public_key.e().to_bytes_be();
 
-    let signing_key = CoreRsaPrivateSigningKey::from_pem(
-        private_pem.as_str(),
-        Some(JsonWebKeyId::new(kid.clone())),
-    )
-    .map_err(|e| color_eyre::eyre::eyre!("Failed to create CoreRsaPrivateSigningKey: {e}"))?;
+                Ok(Self {
+                    jwk: CoreJsonWebKey::new_rsa(
+                        n.clone(),
+                        e.clone(),
+                        Some(JsonWebKeyId::new(kid.clone())),
+                    ),
+                    kid,
+                    algorithm,
+                    status,
+                    encoding_key: EncodingKey::from_rsa_pem(pem.as_bytes())
+                        .wrap_err("Failed to create encoding key")?,
+                    decoding_key: DecodingKey::from_rsa_raw_components(&n, &e),
+                })
+            }
+            SigningAlgorithm::Es256 => {
+                let der = decode_pkcs8_pem(pem)?;
+                let key_pair = EcdsaKeyPair::from_pkcs8(
+                    &ECDSA_P256_SHA256_FIXED_SIGNING,
+                    der.as_bytes(),
+                    &SystemRandom::new(),
+                )
+                .map_err(|e| eyre!("Failed to parse OIDC signing key: {e}"))?;
+
+                // Uncompressed SEC1 point: 0x04 || x || y
+                let point = key_pair.public_key().as_ref();
+                let (x, y) = point[1..].split_at(32);
+                let kid = key_id(point);
+
+                Ok(Self {
+                    jwk: CoreJsonWebKey::new_ec(
+                        x.to_vec(),
+                        y.to_vec(),
+                        CoreJsonCurveType::P256,
+                        Some(JsonWebKeyId::new(kid.clone())),
+                    ),
+                    kid,
+                    algorithm,
+                    status,
+                    encoding_key: EncodingKey::from_ec_der(der.as_bytes()),
+                    decoding_key: DecodingKey::from_ec_der(point),
+                })
+            }
+            SigningAlgorithm::EdDsa => {
+                let der = decode_pkcs8_pem(pem)?;
+                let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der.as_bytes())
+                    .map_err(|e| eyre!("Failed to parse OIDC signing key: {e}"))?;
+
+                let public_key = key_pair.public_key().as_ref();
+                let kid = key_id(public_key);
+
+                Ok(Self {
+                    jwk: CoreJsonWebKey::new_okp(
+                        public_key.to_vec(),
+                        CoreJsonCurveType::Ed25519,
+                        Some(JsonWebKeyId::new(kid.clone())),
+                    ),
+                    kid,
+                    algorithm,
+                    status,
+                    encoding_key: EncodingKey::from_ed_der(der.as_bytes()),
+                    decoding_key: DecodingKey::from_ed_der(public_key),
+                })
+            }
+        }
+    }
+}
+
+/// Derive a stable kid from the public key hash.
+fn key_id(public_key: &[u8]) -> String {
+    let hash = Sha256::digest(public_key);
+
+    URL_SAFE_NO_PAD.encode(&hash[..8])
+}
 
-    let verification_key: CoreJsonWebKey = signing_key.as_verification_key();
-    let jwks = CoreJsonWebKeySet::new(vec![verification_key]);
+/// Lets `openidconnect` sign ID tokens with any ring key.
+impl PrivateSigningKey for SigningKey {
+    type VerificationKey = CoreJsonWebKey;
 
-    // Build jsonwebtoken keys for access token signing/verification
-    let encoding_key = EncodingKey::from_rsa_pem(private_pem.as_bytes())
-        .wrap_err("Failed to create encoding key")?;
+    fn sign(
+        &self,
+        signature_alg: &CoreJwsSigningAlgorithm,
+        message: &[u8],
+    ) -> Result<Vec<u8>, SigningError> {
+        if *signature_alg != self.algorithm.jws_algorithm() {
+            return Err(SigningError::UnsupportedAlg(format!("{signature_alg:?}")));
+        }
 
-    let public_pem = public_key
-        .to_pkcs1_pem(LineEnding::LF)
-        .wrap_err("Failed to encode public key")?;
-    let decoding_key = DecodingKey::from_rsa_pem(public_pem.as_bytes())
-        .wrap_err("Failed to create decoding key")?;
+        let signature =
+            jsonwebtoken::crypto::sign(message, &self.encoding_key, self.algorithm.jwt_algorithm())
+                .map_err(|_| SigningError::CryptoError)?;
 
-    info!("OIDC provider initialized with kid={kid}");
+        URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| SigningError::CryptoError)
+    }
 
-    Ok(Arc::new(OidcKeys {
-        signing_key,
-        jwks,
-        encoding_key,
-        decoding_key,
-        kid,
-    }))
+    fn as_verification_key(&self) -> CoreJsonWebKey {
+        self.jwk.clone()
+    }
 }
 
 impl OidcKeys {
+    /// Build a key ring. It must contain an active key for every algorithm.
+    pub fn new(keys: Vec<SigningKey>, default_algorithm: SigningAlgorithm) -> Result<Self> {
+        let ring = Self {
+            keys: RwLock::new(Vec::new()),
+            default_algorithm,
+        };
+        ring.replace(keys)?;
+
+        Ok(ring)
+    }
+
+    /// Swap in a freshly loaded set of keys. It must contain an active key for every algorithm.
+    pub fn replace(&self, keys: Vec<SigningKey>) -> Result<()> {
+        for algorithm in SigningAlgorithm::ALL {
+            if !keys
+                .iter()
+                .any(|key| key.algorithm == algorithm && key.status == KeyStatus::Active)
+            {
+                bail!(
+                    "OIDC key ring has no active {} signing key",
+                    algorithm.as_str()
+                );
+            }
+        }
+
+        *self.keys.write().expect("OIDC key ring lock poisoned") =
+            keys.into_iter().map(Arc::new).collect();
+
+        Ok(())
+    }
+
+    fn keys(&self) -> Vec<Arc<SigningKey>> {
+        self.keys
+            .read()
+            .expect("OIDC key ring lock poisoned")
+            .clone()
+    }
+
+    /// The key new tokens are signed with, for the given algorithm or the configured default.
+    pub fn active_key(&self, algorithm: Option<SigningAlgorithm>) -> Arc<SigningKey> {
+        let algorithm = algorithm.unwrap_or(self.default_algorithm);
+
+        self.keys()
+            .into_iter()
+            .find(|key| key.algorithm == algorithm && key.status == KeyStatus::Active)
+            .expect("OIDC key ring always has an active key for every algorithm")
+    }
+
+    /// Public keys of the whole ring for the `/jwks` endpoint.
+    /// Upcoming and retired keys are published too, so clients can verify tokens across a rotation.
+    pub fn jwks(&self) -> CoreJsonWebKeySet {
+        CoreJsonWebKeySet::new(self.keys().iter().map(|key| key.jwk.clone()).collect())
+    }
+
+    /// Pick the key a JWT was signed with by its `kid` header, falling back to the active key.
+    /// Fails unless its `typ` header matches `jwt_type`.
+    fn verification_key(
+        &self,
+        token: &str,
+        jwt_type: JwtType,
+    ) -> jsonwebtoken::errors::Result<Arc<SigningKey>> {
+        let header = jsonwebtoken::decode_header(token)?;
+        if !jwt_type.matches(header.typ.as_deref()) {
+            return Err(jsonwebtoken::errors::ErrorKind::InvalidToken.into());
+        }
+
+        match header.kid {
+            Some(kid) => self.keys().into_iter().find(|key| key.kid == kid),
+            None => Some(self.active_key(None)),
+        }
+        .ok_or_else(|| jsonwebtoken::errors::ErrorKind::InvalidToken.into())
+    }
+
     /// Sign an access token JWT (not covered by the openidconnect crate).
     pub fn sign_access_token(&self, claims: &AccessTokenClaims) -> Result<String> {
-        let mut header = Header::new(Algorithm::RS256);
-        header.kid = Some(self.kid.clone());
+        let key = self.active_key(None);
+        let mut header = Header::new(key.algorithm.jwt_algorithm());
+        header.kid = Some(key.kid.clone());
+        header.typ = Some(ACCESS_TOKEN_JWT_TYPE.to_string());
 
-        jsonwebtoken::encode(&header, claims, &self.encoding_key)
+        jsonwebtoken::encode(&header, claims, &key.encoding_key)
             .wrap_err("Failed to sign access token")
     }
+
+    /// Verify the signature and expiry of an access token JWT issued by this server.
+    /// Audience is not checked, as access tokens are presented to several endpoints.
+    pub fn verify_access_token(
+        &self,
+        token: &str,
+    ) -> jsonwebtoken::errors::Result<AccessTokenClaims> {
+        let key = self.verification_key(token, JwtType::Access)?;
+        let mut validation = Validation::new(key.algorithm.jwt_algorithm());
+        validation.validate_aud = false;
+
+        jsonwebtoken::decode::<AccessTokenClaims>(token, &key.decoding_key, &validation)
+            .map(|data| data.claims)
+    }
+
+    /// Sign a back-channel logout token.
+    pub fn sign_logout_token(&self, claims: &LogoutTokenClaims) -> Result<String> {
+        let key = self.active_key(None);
+        let mut header = Header::new(key.algorithm.jwt_algorithm());
+        header.kid = Some(key.kid.clone());
+        header.typ = Some("logout+jwt".to_string());
+
+        jsonwebtoken::encode(&header, claims, &key.encoding_key)
+            .wrap_err("Failed to sign logout token")
+    }
+
+    /// Verify the signature of an ID token previously issued by this server.
+    /// Expired tokens are accepted, as hints are commonly sent long after issuance, but access
+    /// and logout tokens aren't, as their `aud` doesn't name the client to return to.
+    pub fn verify_id_token_hint(
+        &self,
+        token: &str,
+    ) -> jsonwebtoken::errors::Result<IdTokenHintClaims> {
+        let key = self.verification_key(token, JwtType::Id)?;
+        let mut validation = Validation::new(key.algorithm.jwt_algorithm());
+        validation.validate_aud = false;
+        validation.validate_exp = false;
+
+        jsonwebtoken::decode::<IdTokenHintClaims>(token, &key.decoding_key, &validation)
+            .map(|data| data.claims)
+    }
+
+    /// Like [`Self::verify_id_token_hint`], but the ID token has to be unexpired.
+    pub fn verify_id_token(&self, token: &str) -> jsonwebtoken::errors::Result<IdTokenHintClaims> {
+        let key = self.verification_key(token, JwtType::Id)?;
+        let mut validation = Validation::new(key.algorithm.jwt_algorithm());
+        validation.validate_aud = false;
+
+        jsonwebtoken::decode::<IdTokenHintClaims>(token, &key.decoding_key, &validation)
+            .map(|data| data.claims)
+    }
+}
+
+/// HTTP client for outgoing requests to relying parties.
+/// Redirects are not followed, so a client can't point us at internal services through one.
+pub fn init_http_client() -> Result<openidconnect::reqwest::Client> {
+    openidconnect::reqwest::Client::builder()
+        .redirect(openidconnect::reqwest::redirect::Policy::none())
+        .timeout(Duration::from_secs(10))
+        .build()
+        .wrap_err("Failed to build HTTP client")
 }
 
-/// Build the OIDC discovery document using `CoreProviderMetadata`.
-pub fn build_provider_metadata(issuer: &str) -> Result<CoreProviderMetadata> {
+/// Discovery fields that `CoreProviderMetadata` doesn't know about.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExtraProviderMetadata {
+    pub code_challenge_methods_supported: Vec<PkceCodeChallengeMethod>,
+    pub revocation_endpoint: String,
+    pub introspection_endpoint: String,
+    pub device_authorization_endpoint: String,
+    pub end_session_endpoint: String,
+    pub pushed_authorization_request_endpoint: String,
+    /// Whether every client has to use PAR. Applications can still require it for themselves.
+    pub require_pushed_authorization_requests: bool,
+    pub backchannel_logout_supported: bool,
+    pub backchannel_logout_session_supported: bool,
+    pub frontchannel_logout_supported: bool,
+    pub frontchannel_logout_session_supported: bool,
+    pub dpop_signing_alg_values_supported: Vec<CoreJwsSigningAlgorithm>,
+}
+
+impl AdditionalProviderMetadata for ExtraProviderMetadata {}
+
+/// `CoreProviderMetadata` extended with [`ExtraProviderMetadata`].
+pub type ProviderMetadata = openidconnect::ProviderMetadata<
+    ExtraProviderMetadata,
+    CoreAuthDisplay,
+    CoreClientAuthMethod,
+    CoreClaimName,
+    CoreClaimType,
+    CoreGrantType,
+    CoreJweContentEncryptionAlgorithm,
+    CoreJweKeyManagementAlgorithm,
+    CoreJsonWebKey,
+    CoreResponseMode,
+    CoreResponseType,
+    CoreSubjectIdentifierType,
+>;
+
+/// Build the OIDC discovery document.
+pub fn build_provider_metadata(issuer: &str, settings: &Oidc) -> Result<ProviderMetadata> {
     let issuer_url = IssuerUrl::new(issuer.to_string()).wrap_err("Invalid issuer URL")?;
     let auth_url = AuthUrl::new(format!("{issuer}/api/oidc/authorize"))
         .wrap_err("Invalid authorization URL")?;
     let jwks_url =
         JsonWebKeySetUrl::new(format!("{issuer}/api/oidc/jwks")).wrap_err("Invalid JWKS URL")?;
 
-    let provider_metadata = CoreProviderMetadata::new(
+    let extra_metadata = ExtraProviderMetadata {
+        code_challenge_methods_supported: CodeChallengeMethod::SUPPORTED
+            .iter()
+            .map(|m| PkceCodeChallengeMethod::new(m.as_str().to_string()))
+            .collect(),
+        revocation_endpoint: format!("{issuer}/api/oidc/revoke"),
+        introspection_endpoint: format!("{issuer}/api/oidc/introspect"),
+        device_authorization_endpoint: format!("{issuer}/api/oidc/device_authorization"),
+        end_session_endpoint: format!("{issuer}/api/oidc/end_session"),
+        pushed_authorization_request_endpoint: format!("{issuer}/api/oidc/par"),
+        require_pushed_authorization_requests: false,
+        backchannel_logout_supported: true,
+        backchannel_logout_session_supported: true,
+        frontchannel_logout_supported: true,
+        frontchannel_logout_session_supported: true,
+        dpop_signing_alg_values_supported: CLIENT_SIGNING_ALGORITHMS
+            .iter()
+            .map(|algorithm| jws_algorithm(*algorithm))
+            .collect(),
+    };
+
+    let provider_metadata = ProviderMetadata::new(
         issuer_url,
         auth_url,
         jwks_url,
         vec![ResponseTypes::new(vec![CoreResponseType::Code])],
-        vec![CoreSubjectIdentifierType::Public],
-        vec![CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256],
-        EmptyAdditionalProviderMetadata {},
+        vec![
+            CoreSubjectIdentifierType::Public,
+            CoreSubjectIdentifierType::Pairwise,
+        ],
+        SigningAlgorithm::ALL
+            .iter()
+            .map(|algorithm| algorithm.jws_algorithm())
+            .collect(),
+        extra_metadata,
     )
     .set_token_endpoint(Some(
         TokenUrl::new(format!("{issuer}/api/oidc/token")).wrap_err("Invalid token URL")?,
@@ -197,11 +672,23 @@ pub fn build_provider_metadata(issuer: &str) -> Result<CoreProviderMetadata> {
     .set_userinfo_endpoint(Some(
         UserInfoUrl::new(format!("{issuer}/api/oidc/userinfo")).wrap_err("Invalid userinfo URL")?,
     ))
+    .set_registration_endpoint(Some(
+        RegistrationUrl::new(format!("{issuer}/api/oidc/register"))
+            .wrap_err("Invalid registration URL")?,
+    ))
+    .set_grant_types_supported(Some(vec![
+        CoreGrantType::AuthorizationCode,
+        CoreGrantType::RefreshToken,
+        CoreGrantType::ClientCredentials,
+        CoreGrantType::DeviceCode,
+        CoreGrantType::Extension(TOKEN_EXCHANGE_GRANT_TYPE.to_string()),
+    ]))
     .set_scopes_supported(Some(vec![
         Scope::new("openid".to_string()),
         Scope::new("profile".to_string()),
         Scope::new("email".to_string()),
         Scope::new("offline_access".to_string()),
+        Scope::new(GROUPS_SCOPE.to_string()),
     ]))
     .set_claims_supported(Some(vec![
         CoreClaimName::new("sub".to_string()),
@@ -215,7 +702,105 @@ pub fn build_provider_metadata(issuer: &str) -> Result<CoreProviderMetadata> {
         CoreClaimName::new("preferred_username".to_string()),
         CoreClaimName::new("email".to_string()),
         CoreClaimName::new("email_verified".to_string()),
-    ]));
+        CoreClaimName::new("auth_time".to_string()),
+        CoreClaimName::new("acr".to_string()),
+        CoreClaimName::new("amr".to_string()),
+        CoreClaimName::new(settings.groups_claim.clone()),
+    ]))
+    .set_acr_values_supported(Some(
+        ACR_VALUES
+            .iter()
+            .map(|acr| AuthenticationContextClass::new(acr.to_string()))
+            .collect(),
+    ))
+    .set_token_endpoint_auth_methods_supported(Some(
+        TokenEndpointAuthMethod::ALL
+            .iter()
+            .map(|method| method.client_auth_method())
+            .collect(),
+    ))
+    .set_token_endpoint_auth_signing_alg_values_supported(Some(
+        CLIENT_SECRET_SIGNING_ALGORITHMS
+            .iter()
+            .chain(CLIENT_SIGNING_ALGORITHMS)
+            .map(|algorithm| jws_algorithm(*algorithm))
+            .collect(),
+    ))
+    .set_request_parameter_supported(Some(true))
+    .set_request_uri_parameter_supported(Some(true))
+    .set_require_request_uri_registration(Some(true))
+    .set_request_object_signing_alg_values_supported(Some(
+        CLIENT_SIGNING_ALGORITHMS
+            .iter()
+            .map(|algorithm| jws_algorithm(*algorithm))
+            .collect(),
+    ));
 
     Ok(provider_metadata)
 }
+
+#[cfg(test)]
+mod tests {
+    use openidconnect::JsonWebKey;
+
+    use super::*;
+
+    #[test]
+    fn generated_keys_sign_verifiable_jwts() {
+        let pems = SigningAlgorithm::ALL
+            .map(|algorithm| (algorithm, generate_private_key_pem(algorithm).unwrap()));
+        let ring = |default_algorithm| {
+            let keys = pems
+                .iter()
+                .map(|(algorithm, pem)| {
+                    SigningKey::from_pem(pem, *algorithm, KeyStatus::Active).unwrap()
+                })
+                .collect();
+            OidcKeys::new(keys, default_algorithm).unwrap()
+        };
+
+        for algorithm in SigningAlgorithm::ALL {
+            let keys = ring(algorithm);
+            let key = keys.active_key(None);
+            assert_eq!(key.algorithm, algorithm);
+
+            let message = b"header.payload";
+            let signature = key.sign(&algorithm.jws_algorithm(), message).unwrap();
+            key.as_verification_key()
+                .verify_signature(&algorithm.jws_algorithm(), message, &signature)
+                .unwrap();
+
+            let now = chrono::Utc::now().timestamp() as usize;
+            let claims = AccessTokenClaims {
+                iss: "https://auth.example.com".to_string(),
+                sub: "user".to_string(),
+                aud: "client".to_string(),
+                exp: now + 60,
+                iat: now,
+                scope: "openid".to_string(),
+                client_id: "client".to_string(),
+                jti: "jti".to_string(),
+                act: None,
+                cnf: None,
+                extra: serde_json::Map::new(),
+            };
+            let token = keys.sign_access_token(&claims).unwrap();
+            let header = jsonwebtoken::decode_header(&token).unwrap();
+
+            assert_eq!(header.alg, algorithm.jwt_algorithm());
+            assert_eq!(header.kid.as_deref(), Some(key.kid.as_str()));
+            assert_eq!(header.typ.as_deref(), Some("at+jwt"));
+            assert_eq!(keys.verify_access_token(&token).unwrap().sub, "user");
+
+            // Access tokens can't pass for ID tokens, nor the other way round
+            assert!(keys.verify_id_token(&token).is_err());
+            assert!(keys.verify_id_token_hint(&token).is_err());
+            let mut id_header = Header::new(algorithm.jwt_algorithm());
+            id_header.kid = Some(key.kid.clone());
+            let id_token = jsonwebtoken::encode(&id_header, &claims, &key.encoding_key).unwrap();
+            assert!(keys.verify_id_token(&id_token).is_ok());
+            assert!(keys.verify_access_token(&id_token).is_err());
+            assert!(keys.verify_id_token_hint(&id_token).is_ok());
+        }
+    }
+}
@@ -0,0 +1,479 @@
+use auth_core::SecurityLevel;
+use std::collections::HashMap;
+
+use chrono::Utc;
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use tower_sessions::Session;
+
+use crate::{
+    axum_error::AxumResult,
+    database::{AnyFactor, FirstFactor, SecondFactor, destroy_session},
+    oidc::logout::notify_session_logout,
+    state::AppState,
+};
+
+/// Session key holding the [`SessionAuthentication`].
+const AUTHENTICATION_SESSION_KEY: &str = "authentication";
+
+/// Session key holding when each authorization request asked the user to sign in again,
+/// see [`logged_in_since_request`].
+const LOGIN_REQUESTS_SESSION_KEY: &str = "login_requests";
+
+/// How long a request to sign in again waits for the user, in seconds. A fresh request starts after that.
+const LOGIN_REQUEST_TIMEOUT_SECS: i64 = 600;
+
+/// Session key holding the level each loaded authorization request requires, see [`remember_required_level`].
+const REQUIRED_LEVELS_SESSION_KEY: &str = "required_levels";
+
+/// How long the user has to approve an authorization request after loading it, in seconds.
+const AUTHORIZATION_APPROVAL_TIMEOUT_SECS: i64 = 3600;
+
+/// Session key holding the level a pending step-up has to reach, see [`request_step_up`].
+const STEP_UP_SESSION_KEY: &str = "step_up";
+
+/// How long a requested step-up waits for the user, in seconds. Abandoned ones lapse after that.
+const STEP_UP_TIMEOUT_SECS: i64 = 600;
+
+/// `acr` values, from the weakest to the strongest [`SecurityLevel`].
+pub const ACR_VALUES: &[&str] = &["knowledge", "out_of_band", "possession", "hardware"];
+
+/// When and how the user signed in to the current session.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SessionAuthentication {
+    /// When the last factor was completed, in seconds since the epoch.
+    pub auth_time: i64,
+    /// Factors completed since the first factor of the current login.
+    pub factors: Vec<AnyFactor>,
+}
+
+impl SessionAuthentication {
+    /// Strongest level among the completed factors.
+    pub fn security_level(&self) -> Option<SecurityLevel> {
+        self.factors.iter().map(factor_security_level).max()
+    }
+
+    /// `acr` claim, see [`ACR_VALUES`].
+    pub fn acr(&self) -> Option<&'static str> {
+        self.security_level().map(acr_value)
+    }
+
+    /// `amr` claim (RFC 8176).
+    pub fn amr(&self) -> Vec<String> {
+        let mut amr: Vec<String> = Vec::new();
+
+        for method in self.factors.iter().map(factor_method_reference) {
+            if !amr.iter().any(|m| m == method) {
+                amr.push(method.to_string());
+            }
+        }
+
+        if self.factors.len() > 1 {
+            amr.push("mfa".to_string());
+        }
+
+        amr
+    }
+
+    /// Whether the user signed in within the last `max_age` seconds.
+    pub fn is_fresh(&self, max_age: i64) -> bool {
+        Utc::now().timestamp() - self.auth_time <= max_age
+    }
+}
+
+/// `prompt` parameter of an authorization request (OpenID Connect Core 1.0, section 3.1.2.1).
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Prompt {
+    /// Don't show any page, fail if the user would have to interact.
+    pub none: bool,
+    pub login: bool,
+    pub consent: bool,
+    pub select_account: bool,
+}
+
+impl Prompt {
+    /// Parses the space-separated values, ignoring unknown ones.
+    /// Returns `None` if `none` is combined with another value.
+    pub fn parse(prompt: &str) -> Option<Self> {
+        let mut parsed = Prompt::default();
+
+        for value in prompt.split_whitespace() {
+            match value {
+                "none" => parsed.none = true,
+                "login" => parsed.login = true,
+                "consent" => parsed.consent = true,
+                "select_account" => parsed.select_account = true,
+                _ => {}
+            }
+        }
+
+        if parsed.none && (parsed.login || parsed.consent || parsed.select_account) {
+            return None;
+        }
+
+        Some(parsed)
+    }
+}
+
+pub fn factor_security_level(factor: &AnyFactor) -> SecurityLevel {
+    match factor {
+        AnyFactor::First(FirstFactor::Password) => SecurityLevel::Knowledge,
+        AnyFactor::First(FirstFactor::Pgp) => SecurityLevel::Possession,
+        AnyFactor::First(FirstFactor::WebAuthnPasswordless) => SecurityLevel::Hardware,
+        AnyFactor::Second(SecondFactor::Totp) => SecurityLevel::Possession,
+        AnyFactor::Second(SecondFactor::WebAuthn) => SecurityLevel::Hardware,
+        AnyFactor::Second(SecondFactor::RecoveryCode) => SecurityLevel::Knowledge,
+    }
+}
+
+fn factor_method_reference(factor: &AnyFactor) -> &'static str {
+    match factor {
+        AnyFactor::First(FirstFactor::Password) => "pwd",
+        AnyFactor::First(FirstFactor::Pgp) => "swk",
+        AnyFactor::First(FirstFactor::WebAuthnPasswordless) => "hwk",
+        AnyFactor::Second(SecondFactor::Totp) => "otp",
+        AnyFactor::Second(SecondFactor::WebAuthn) => "hwk",
+        AnyFactor::Second(SecondFactor::RecoveryCode) => "otp",
+    }
+}
+
+pub fn acr_value(level: SecurityLevel) -> &'static str {
+    match level {
+        SecurityLevel::Knowledge => ACR_VALUES[0],
+        SecurityLevel::OutOfBand => ACR_VALUES[1],
+        SecurityLevel::Possession => ACR_VALUES[2],
+        SecurityLevel::Hardware => ACR_VALUES[3],
+    }
+}
+
+/// Inverse of [`acr_value`].
+pub fn parse_acr(acr: &str) -> Option<SecurityLevel> {
+    match acr {
+        "knowledge" => Some(SecurityLevel::Knowledge),
+        "out_of_band" => Some(SecurityLevel::OutOfBand),
+        "possession" => Some(SecurityLevel::Possession),
+        "hardware" => Some(SecurityLevel::Hardware),
+        _ => None,
+    }
+}
+
+/// Level asked for with the space-separated `acr_values`, ignoring unknown values.
+/// The weakest one is enough, as the client accepts any of them.
+pub fn requested_security_level(acr_values: &str) -> Option<SecurityLevel> {
+    acr_values.split_whitespace().filter_map(parse_acr).min()
+}
+
+/// Signs `user_id` in to the session with a first factor, which cancels a pending step-up.
+///
+/// Signing in as someone else logs the previous user out first, so the session gets a new ID
+/// and nothing of theirs, like the `sid`, carries over.
+pub async fn start_login(state: &AppState, session: &Session, user_id: ObjectId) -> AxumResult<()> {
+    let previous = session.get::<ObjectId>("user_id").await?;
+
+    if previous.is_some_and(|previous| previous != user_id) {
+        if let Some(session_id) = destroy_session(&state.database, session).await? {
+            notify_session_logout(state, session_id);
+        }
+    } else {
+        clear_step_up(session).await?;
+    }
+
+    session.insert("user_id", user_id).await?;
+
+    Ok(())
+}
+
+/// Records a completed factor. A first factor starts a new login, dropping earlier factors.
+pub async fn record_factor(session: &Session, factor: impl Into<AnyFactor>) -> AxumResult<()> {
+    let factor = factor.into();
+
+    let mut authentication = match factor {
+        AnyFactor::First(_) => SessionAuthentication::default(),
+        AnyFactor::Second(_) => session_authentication(session).await?.unwrap_or_default(),
+    };
+    authentication.auth_time = Utc::now().timestamp();
+    authentication.factors.push(factor);
+
+    session
+        .insert(AUTHENTICATION_SESSION_KEY, authentication)
+        .await?;
+
+    Ok(())
+}
+
+/// How the current session signed in. `None` for sessions created before this was recorded.
+pub async fn session_authentication(
+    session: &Session,
+) -> AxumResult<Option<SessionAuthentication>> {
+    Ok(session
+        .get::<SessionAuthentication>(AUTHENTICATION_SESSION_KEY)
+        .await?)
+}
+
+/// Whether the user signed in again since the authorization request identified by `request_key`
+/// asked for it (`prompt=login` or an exceeded `max_age`).
+///
+/// The first call remembers when the request was made, so the user isn't asked again after
+/// coming back from the login page. Other requests, even from the same client, start over.
+pub async fn logged_in_since_request(
+    session: &Session,
+    request_key: &str,
+    authentication: Option<&SessionAuthentication>,
+) -> AxumResult<bool> {
+    let now = Utc::now().timestamp();
+    let mut requests = session
+        .get::<HashMap<String, i64>>(LOGIN_REQUESTS_SESSION_KEY)
+        .await?
+        .unwrap_or_default();
+    requests.retain(|_, requested_at| now - *requested_at <= LOGIN_REQUEST_TIMEOUT_SECS);
+
+    let logged_in = match requests.get(request_key) {
+        Some(requested_at) => {
+            authentication.is_some_and(|authentication| authentication.auth_time >= *requested_at)
+        }
+        None => {
+            requests.insert(request_key.to_string(), now);
+            false
+        }
+    };
+    if logged_in {
+        requests.remove(request_key);
+    }
+
+    session.insert(LOGIN_REQUESTS_SESSION_KEY, requests).await?;
+
+    Ok(logged_in)
+}
+
+/// Drops the sign-in request of `request_key` once the authorization goes ahead without it,
+/// so it can't let a later request through.
+pub async fn forget_login_request(session: &Session, request_key: &str) -> AxumResult<()> {
+    let Some(mut requests) = session
+        .get::<HashMap<String, i64>>(LOGIN_REQUESTS_SESSION_KEY)
+        .await?
+    else {
+        return Ok(());
+    };
+
+    if requests.remove(request_key).is_some() {
+        session.insert(LOGIN_REQUESTS_SESSION_KEY, requests).await?;
+    }
+
+    Ok(())
+}
+
+/// Level an authorization request requires, remembered from when it was loaded.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct RequiredLevel {
+    /// `None` when the application and client don't require any.
+    pub level: Option<SecurityLevel>,
+    loaded_at: i64,
+}
+
+/// Remembers the level the authorization request identified by `request_key` requires, so approving
+/// it can't skip the `acr_values` the client asked for. Loading the request again never lowers it.
+pub async fn remember_required_level(
+    session: &Session,
+    request_key: &str,
+    level: Option<SecurityLevel>,
+) -> AxumResult<()> {
+    let now = Utc::now().timestamp();
+    let mut levels = session
+        .get::<HashMap<String, RequiredLevel>>(REQUIRED_LEVELS_SESSION_KEY)
+        .await?
+        .unwrap_or_default();
+    levels.retain(|_, required| now - required.loaded_at <= AUTHORIZATION_APPROVAL_TIMEOUT_SECS);
+
+    let required = levels
+        .entry(request_key.to_string())
+        .or_insert(RequiredLevel {
+            level,
+            loaded_at: now,
+        });
+    required.level = required.level.max(level);
+
+    session.insert(REQUIRED_LEVELS_SESSION_KEY, levels).await?;
+
+    Ok(())
+}
+
+/// Takes the level remembered for `request_key`. `None` if the request wasn't loaded in this
+/// session or too long ago.
+pub async fn take_required_level(
+    session: &Session,
+    request_key: &str,
+) -> AxumResult<Option<RequiredLevel>> {
+    let Some(mut levels) = session
+        .get::<HashMap<String, RequiredLevel>>(REQUIRED_LEVELS_SESSION_KEY)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let required = levels.remove(request_key);
+    session.insert(REQUIRED_LEVELS_SESSION_KEY, levels).await?;
+
+    let now = Utc::now().timestamp();
+    Ok(required.filter(|required| now - required.loaded_at <= AUTHORIZATION_APPROVAL_TIMEOUT_SECS))
+}
+
+/// Step-up the signed-in user was asked for, see [`request_step_up`].
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+struct PendingStepUp {
+    level: SecurityLevel,
+    requested_at: i64,
+}
+
+/// Lets the signed-in user complete another second factor to reach `level`, for
+/// [`STEP_UP_TIMEOUT_SECS`].
+pub async fn request_step_up(session: &Session, level: SecurityLevel) -> AxumResult<()> {
+    let step_up = PendingStepUp {
+        level,
+        requested_at: Utc::now().timestamp(),
+    };
+    session.insert(STEP_UP_SESSION_KEY, step_up).await?;
+
+    Ok(())
+}
+
+pub async fn clear_step_up(session: &Session) -> AxumResult<()> {
+    session.remove::<PendingStepUp>(STEP_UP_SESSION_KEY).await?;
+
+    Ok(())
+}
+
+/// Whether the session is waiting for a step-up factor, see [`request_step_up`].
+/// Clears a step-up the user abandoned.
+pub async fn step_up_pending(session: &Session) -> AxumResult<bool> {
+    let Some(step_up) = session.get::<PendingStepUp>(STEP_UP_SESSION_KEY).await? else {
+        return Ok(false);
+    };
+
+    if Utc::now().timestamp() - step_up.requested_at > STEP_UP_TIMEOUT_SECS {
+        clear_step_up(session).await?;
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acr_and_amr_follow_the_completed_factors() {
+        let password_only = SessionAuthentication {
+            auth_time: 0,
+            factors: vec![FirstFactor::Password.into()],
+        };
+        assert_eq!(password_only.acr(), Some("knowledge"));
+        assert_eq!(password_only.amr(), ["pwd"]);
+
+        let with_security_key = SessionAuthentication {
+            auth_time: 0,
+            factors: vec![FirstFactor::Password.into(), SecondFactor::WebAuthn.into()],
+        };
+        assert_eq!(with_security_key.acr(), Some("hardware"));
+        assert_eq!(with_security_key.amr(), ["pwd", "hwk", "mfa"]);
+
+        assert_eq!(SessionAuthentication::default().acr(), None);
+    }
+
+    #[test]
+    fn weakest_known_acr_value_is_required() {
+        assert_eq!(
+            requested_security_level("hardware urn:unknown possession"),
+            Some(SecurityLevel::Possession)
+        );
+        assert_eq!(requested_security_level("urn:unknown"), None);
+
+        for level in [
+            SecurityLevel::Knowledge,
+            SecurityLevel::OutOfBand,
+            SecurityLevel::Possession,
+            SecurityLevel::Hardware,
+        ] {
+            assert_eq!(parse_acr(acr_value(level)), Some(level));
+        }
+    }
+
+    #[tokio::test]
+    async fn sign_in_requests_are_kept_per_authorization_request() {
+        let session = Session::new(
+            None,
+            std::sync::Arc::new(tower_sessions::MemoryStore::default()),
+            None,
+        );
+        let signed_in_now = SessionAuthentication {
+            auth_time: Utc::now().timestamp(),
+            factors: vec![FirstFactor::Password.into()],
+        };
+
+        // Asks to sign in first, then accepts a sign-in from after the request
+        assert!(
+            !logged_in_since_request(&session, "max_age", None)
+                .await
+                .unwrap()
+        );
+        assert!(
+            logged_in_since_request(&session, "max_age", Some(&signed_in_now))
+                .await
+                .unwrap()
+        );
+
+        // A different request waits for its own sign-in
+        forget_login_request(&session, "max_age").await.unwrap();
+        assert!(
+            !logged_in_since_request(&session, "prompt_login", Some(&signed_in_now))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn abandoned_step_ups_lapse() {
+        let session = Session::new(
+            None,
+            std::sync::Arc::new(tower_sessions::MemoryStore::default()),
+            None,
+        );
+
+        request_step_up(&session, SecurityLevel::Possession)
+            .await
+            .unwrap();
+        assert!(step_up_pending(&session).await.unwrap());
+
+        let abandoned = PendingStepUp {
+            level: SecurityLevel::Possession,
+            requested_at: Utc::now().timestamp() - STEP_UP_TIMEOUT_SECS - 1,
+        };
+        session
+            .insert(STEP_UP_SESSION_KEY, abandoned)
+            .await
+            .unwrap();
+        assert!(!step_up_pending(&session).await.unwrap());
+        assert!(
+            session
+                .get::<PendingStepUp>(STEP_UP_SESSION_KEY)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn prompt_none_stands_alone() {
+        let prompt = Prompt::parse("login consent unknown").unwrap();
+        assert!(prompt.login && prompt.consent && !prompt.none);
+
+        assert_eq!(
+            Prompt::parse("none"),
+            Some(Prompt {
+                none: true,
+                ..Prompt::default()
+            })
+        );
+        assert_eq!(Prompt::parse("none login"), None);
+    }
+}
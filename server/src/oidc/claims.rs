@@ -0,0 +1,350 @@
+use color_eyre::eyre::{Context, Result};
+use futures::TryStreamExt;
+use mongodb::{
+    Database,
+    bson::{doc, oid::ObjectId},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+
+use crate::{
+    database::{Application, Group, User},
+    mongo_id::object_id_as_string_required,
+    settings::Oidc,
+};
+
+/// Scope that adds the user's groups to tokens and userinfo.
+pub const GROUPS_SCOPE: &str = "groups";
+
+/// Scopes every application supports.
+pub const STANDARD_SCOPES: &[&str] =
+    &["openid", "profile", "email", "offline_access", GROUPS_SCOPE];
+
+/// Claims set by the server itself, which claim mappings can't override.
+pub const RESERVED_CLAIMS: &[&str] = &[
+    "iss",
+    "sub",
+    "aud",
+    "exp",
+    "iat",
+    "nbf",
+    "jti",
+    "scope",
+    "client_id",
+    "azp",
+    "nonce",
+    "sid",
+    "auth_time",
+    "acr",
+    "amr",
+    "act",
+    "cnf",
+    "at_hash",
+    "c_hash",
+    "name",
+    "given_name",
+    "family_name",
+    "preferred_username",
+    "email",
+    "email_verified",
+];
+
+/// Application-specific scope, shown on the consent screen.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone)]
+pub struct CustomScope {
+    /// Scope value requested by the client, e.g. `api:read`.
+    pub name: String,
+    /// What the scope grants access to, in words the user understands.
+    pub description: String,
+}
+
+/// User attribute that can be mapped into a claim.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UserAttribute {
+    Uuid,
+    Email,
+    EmailVerified,
+    PreferredUsername,
+    DisplayName,
+    FirstName,
+    LastName,
+}
+
+/// Where the value of a mapped claim comes from.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClaimSource {
+    Attribute {
+        attribute: UserAttribute,
+    },
+    /// Slugs of the user's groups visible to the application.
+    Groups,
+    /// `value` if the user is a member of `group`, nothing otherwise. Useful for roles.
+    GroupMembership {
+        #[serde(with = "object_id_as_string_required")]
+        #[schema(value_type = String)]
+        group: ObjectId,
+        #[schema(value_type = Object)]
+        value: Value,
+    },
+    Static {
+        #[schema(value_type = Object)]
+        value: Value,
+    },
+}
+
+/// Adds a claim to tokens and userinfo when `scope` is granted.
+/// Mappings that produce the same claim are combined into an array.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone)]
+pub struct ClaimMapping {
+    pub claim: String,
+    pub scope: String,
+    pub source: ClaimSource,
+}
+
+impl ClaimSource {
+    fn resolve(&self, user: &User, groups: &[String]) -> Option<Value> {
+        match self {
+            ClaimSource::Attribute { attribute } => Some(match attribute {
+                UserAttribute::Uuid => Value::from(user.uuid.to_string()),
+                UserAttribute::Email => Value::from(user.email.clone()),
+                UserAttribute::EmailVerified => Value::from(user.email_confirmed),
+                UserAttribute::PreferredUsername => Value::from(user.preferred_username.clone()),
+                UserAttribute::DisplayName => Value::from(user.display_name.clone()),
+                UserAttribute::FirstName => Value::from(user.first_name.clone()),
+                UserAttribute::LastName => Value::from(user.last_name.clone()),
+            }),
+            ClaimSource::Groups => Some(Value::from(groups.to_vec())),
+            ClaimSource::GroupMembership { group, value } => {
+                user.groups.contains(group).then(|| value.clone())
+            }
+            ClaimSource::Static { value } => Some(value.clone()),
+        }
+    }
+}
+
+/// Adds `value` to `claim`, turning it into an array if it's already set.
+fn merge_claim(claims: &mut Map<String, Value>, claim: &str, value: Value) {
+    match claims.get_mut(claim) {
+        None => {
+            claims.insert(claim.to_string(), value);
+        }
+        Some(Value::Array(values)) => match value {
+            Value::Array(more) => values.extend(more),
+            value => values.push(value),
+        },
+        Some(existing) => {
+            let first = existing.take();
+            *existing = Value::Array(vec![first]);
+            merge_claim(claims, claim, value);
+        }
+    }
+}
+
+/// Applies the mappings whose scope was granted. `groups` are the user's visible group slugs.
+fn mapped_claims(
+    mappings: &[ClaimMapping],
+    user: &User,
+    groups: &[String],
+    scopes: &[&str],
+) -> Map<String, Value> {
+    let mut claims = Map::new();
+
+    for mapping in mappings {
+        if !scopes.contains(&mapping.scope.as_str())
+            || RESERVED_CLAIMS.contains(&mapping.claim.as_str())
+        {
+            continue;
+        }
+
+        if let Some(value) = mapping.source.resolve(user, groups) {
+            merge_claim(&mut claims, &mapping.claim, value);
+        }
+    }
+
+    claims
+}
+
+/// Claims granted by `scopes` beyond the standard profile and email ones.
+/// Shared by ID tokens, access tokens and userinfo so they always agree.
+pub async fn extra_claims(
+    database: &Database,
+    settings: &Oidc,
+    app: &Application,
+    user: &User,
+    scopes: &[&str],
+) -> Result<Map<String, Value>> {
+    let wants_groups = scopes.contains(&GROUPS_SCOPE)
+        || app.claim_mappings.iter().any(|mapping| {
+            matches!(mapping.source, ClaimSource::Groups)
+                && scopes.contains(&mapping.scope.as_str())
+        });
+
+    let groups = if wants_groups {
+        visible_groups(database, app, user).await?
+    } else {
+        Vec::new()
+    };
+
+    let mut claims = Map::new();
+
+    if scopes.contains(&GROUPS_SCOPE) {
+        claims.insert(settings.groups_claim.clone(), Value::from(groups.clone()));
+    }
+
+    for (claim, value) in mapped_claims(&app.claim_mappings, user, &groups, scopes) {
+        merge_claim(&mut claims, &claim, value);
+    }
+
+    Ok(claims)
+}
+
+/// Slugs of the user's groups that the application is allowed to see, see [`Application::exposed_groups`].
+async fn visible_groups(
+    database: &Database,
+    app: &Application,
+    user: &User,
+) -> Result<Vec<String>> {
+    let exposed = if app.exposed_groups.is_empty() {
+        &app.allowed_groups
+    } else {
+        &app.exposed_groups
+    };
+
+    let group_ids = user
+        .groups
+        .iter()
+        .filter(|group| exposed.is_empty() || exposed.contains(group))
+        .copied()
+        .collect::<Vec<ObjectId>>();
+
+    if group_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let groups: Vec<Group> = database
+        .collection::<Group>("groups")
+        .find(doc! { "_id": { "$in": group_ids } })
+        .sort(doc! { "slug": 1_i32 })
+        .await
+        .wrap_err("Failed to fetch groups")?
+        .try_collect()
+        .await
+        .wrap_err("Failed to fetch groups")?;
+
+    Ok(groups.into_iter().map(|group| group.slug).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::database::AuthFactors;
+
+    fn user(groups: Vec<ObjectId>) -> User {
+        User {
+            id: ObjectId::new(),
+            uuid: Uuid::new_v4(),
+            first_name: "Ada".to_string(),
+            last_name: "Lovelace".to_string(),
+            display_name: "Ada Lovelace".to_string(),
+            preferred_username: "ada".to_string(),
+            email: "ada@example.com".to_string(),
+            email_confirmed: true,
+            auth_factors: AuthFactors::default(),
+            groups,
+        }
+    }
+
+    fn mapping(claim: &str, scope: &str, source: ClaimSource) -> ClaimMapping {
+        ClaimMapping {
+            claim: claim.to_string(),
+            scope: scope.to_string(),
+            source,
+        }
+    }
+
+    #[test]
+    fn mappings_require_their_scope() {
+        let mappings = [mapping(
+            "username",
+            "api:read",
+            ClaimSource::Attribute {
+                attribute: UserAttribute::PreferredUsername,
+            },
+        )];
+        let user = user(Vec::new());
+
+        assert!(mapped_claims(&mappings, &user, &[], &["openid"]).is_empty());
+        assert_eq!(
+            mapped_claims(&mappings, &user, &[], &["openid", "api:read"])["username"],
+            "ada"
+        );
+    }
+
+    #[test]
+    fn mappings_cant_set_token_binding_or_actor() {
+        let mappings = ["cnf", "act"].map(|claim| {
+            mapping(
+                claim,
+                "api:read",
+                ClaimSource::Attribute {
+                    attribute: UserAttribute::PreferredUsername,
+                },
+            )
+        });
+
+        assert!(mapped_claims(&mappings, &user(Vec::new()), &[], &["api:read"]).is_empty());
+    }
+
+    #[test]
+    fn group_memberships_combine_into_array() {
+        let admins = ObjectId::new();
+        let editors = ObjectId::new();
+        let mappings = [
+            mapping(
+                "roles",
+                "api:read",
+                ClaimSource::GroupMembership {
+                    group: admins,
+                    value: Value::from("admin"),
+                },
+            ),
+            mapping(
+                "roles",
+                "api:read",
+                ClaimSource::GroupMembership {
+                    group: editors,
+                    value: Value::from("editor"),
+                },
+            ),
+            mapping(
+                "roles",
+                "api:read",
+                ClaimSource::GroupMembership {
+                    group: ObjectId::new(),
+                    value: Value::from("viewer"),
+                },
+            ),
+        ];
+
+        let claims = mapped_claims(&mappings, &user(vec![admins, editors]), &[], &["api:read"]);
+        assert_eq!(claims["roles"], serde_json::json!(["admin", "editor"]));
+    }
+
+    #[test]
+    fn reserved_claims_are_not_overridden() {
+        let mappings = [mapping(
+            "sub",
+            "openid",
+            ClaimSource::Static {
+                value: Value::from("someone-else"),
+            },
+        )];
+
+        assert!(mapped_claims(&mappings, &user(Vec::new()), &[], &["openid"]).is_empty());
+    }
+}
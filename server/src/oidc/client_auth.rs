@@ -0,0 +1,281 @@
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::Utc;
+use color_eyre::eyre::{self, Context, Result, bail};
+use fred::prelude::{Expiration, KeysInterface, Pool, SetOptions};
+use openidconnect::core::CoreClientAuthMethod;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    database::{Application, ClientType},
+    state::AppState,
+};
+
+use super::{
+    client_keys::{
+        CLIENT_SECRET_SIGNING_ALGORITHMS, client_jwks, verify_client_jwt, verify_client_secret_jwt,
+    },
+    client_secrets::{decrypt_client_secrets, verify_client_secret},
+};
+
+/// `client_assertion_type` of a JWT client assertion (RFC 7523, section 2.2).
+pub const CLIENT_ASSERTION_TYPE: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+
+/// Endpoints a client assertion may name as its audience, besides the issuer itself.
+const ASSERTION_AUDIENCE_ENDPOINTS: &[&str] = &[
+    "token",
+    "par",
+    "introspect",
+    "revoke",
+    "device_authorization",
+];
+
+/// Redis key prefix for the IDs of client assertions that were already used.
+const USED_ASSERTION_PREFIX: &str = "oidc:used_client_assertion:";
+
+/// How a client authenticates at the token endpoint and the other endpoints for clients.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenEndpointAuthMethod {
+    ClientSecretBasic,
+    ClientSecretPost,
+    /// JWT signed with the client secret.
+    ClientSecretJwt,
+    /// JWT signed with one of the client's registered keys.
+    PrivateKeyJwt,
+    /// Public client, only identified by its client ID.
+    None,
+}
+
+impl TokenEndpointAuthMethod {
+    pub const ALL: [TokenEndpointAuthMethod; 5] = [
+        TokenEndpointAuthMethod::ClientSecretBasic,
+        TokenEndpointAuthMethod::ClientSecretPost,
+        TokenEndpointAuthMethod::ClientSecretJwt,
+        TokenEndpointAuthMethod::PrivateKeyJwt,
+        TokenEndpointAuthMethod::None,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TokenEndpointAuthMethod::ClientSecretBasic => "client_secret_basic",
+            TokenEndpointAuthMethod::ClientSecretPost => "client_secret_post",
+            TokenEndpointAuthMethod::ClientSecretJwt => "client_secret_jwt",
+            TokenEndpointAuthMethod::PrivateKeyJwt => "private_key_jwt",
+            TokenEndpointAuthMethod::None => "none",
+        }
+    }
+
+    pub fn parse(method: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.as_str() == method)
+    }
+
+    pub fn client_auth_method(self) -> CoreClientAuthMethod {
+        match self {
+            TokenEndpointAuthMethod::ClientSecretBasic => CoreClientAuthMethod::ClientSecretBasic,
+            TokenEndpointAuthMethod::ClientSecretPost => CoreClientAuthMethod::ClientSecretPost,
+            TokenEndpointAuthMethod::ClientSecretJwt => CoreClientAuthMethod::ClientSecretJwt,
+            TokenEndpointAuthMethod::PrivateKeyJwt => CoreClientAuthMethod::PrivateKeyJwt,
+            TokenEndpointAuthMethod::None => CoreClientAuthMethod::None,
+        }
+    }
+
+    /// Whether the client has to hold a secret for this method.
+    pub fn uses_secret(self) -> bool {
+        matches!(
+            self,
+            TokenEndpointAuthMethod::ClientSecretBasic
+                | TokenEndpointAuthMethod::ClientSecretPost
+                | TokenEndpointAuthMethod::ClientSecretJwt
+        )
+    }
+}
+
+/// What a client presented to prove its identity.
+#[derive(Debug, Clone)]
+pub enum ClientCredential {
+    None,
+    Secret {
+        secret: String,
+        method: TokenEndpointAuthMethod,
+    },
+    Assertion {
+        assertion_type: String,
+        assertion: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientAssertionClaims {
+    sub: String,
+    jti: String,
+    exp: i64,
+}
+
+/// Client ID a client assertion is issued for, read without verifying it.
+/// Lets clients leave out `client_id` when authenticating with an assertion.
+pub fn client_assertion_subject(assertion: &str) -> Option<String> {
+    let payload = assertion.split('.').nth(1)?;
+    let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+
+    claims.get("sub")?.as_str().map(str::to_string)
+}
+
+/// Checks what the client presented against the application and returns the method it used.
+/// Applications with a registered `token_endpoint_auth_method` have to use exactly that one.
+pub async fn verify_client_credential(
+    state: &AppState,
+    app: &Application,
+    credential: &ClientCredential,
+) -> Result<TokenEndpointAuthMethod> {
+    let method = match credential {
+        ClientCredential::None => {
+            if matches!(app.client_type, ClientType::Confidential) {
+                bail!("Client authentication required");
+            }
+            TokenEndpointAuthMethod::None
+        }
+        ClientCredential::Secret { secret, method } => {
+            if !verify_client_secret(&app.client_secrets, secret) {
+                bail!("Invalid client secret");
+            }
+            *method
+        }
+        ClientCredential::Assertion {
+            assertion_type,
+            assertion,
+        } => {
+            if assertion_type != CLIENT_ASSERTION_TYPE {
+                bail!("Unsupported client_assertion_type");
+            }
+            verify_client_assertion(state, app, assertion).await?
+        }
+    };
+
+    if let Some(expected) = app.token_endpoint_auth_method
+        && expected != method
+    {
+        bail!("The client has to authenticate with {}", expected.as_str());
+    }
+
+    Ok(method)
+}
+
+/// Verifies a `client_secret_jwt` or `private_key_jwt` assertion (RFC 7523) and records its `jti`,
+/// so it can't be replayed.
+async fn verify_client_assertion(
+    state: &AppState,
+    app: &Application,
+    assertion: &str,
+) -> Result<TokenEndpointAuthMethod> {
+    let public_url = state.settings.general.public_url.to_string();
+    let issuer = public_url.trim_end_matches('/');
+    let endpoints = ASSERTION_AUDIENCE_ENDPOINTS
+        .iter()
+        .map(|endpoint| format!("{issuer}/api/oidc/{endpoint}"))
+        .collect::<Vec<_>>();
+    let audiences = std::iter::once(issuer)
+        .chain(endpoints.iter().map(String::as_str))
+        .collect::<Vec<_>>();
+
+    let header = jsonwebtoken::decode_header(assertion).wrap_err("Malformed client assertion")?;
+    let (claims, method) = if CLIENT_SECRET_SIGNING_ALGORITHMS.contains(&header.alg) {
+        let claims = decrypt_client_secrets(&state.encryption_key, &app.client_secrets)
+            .iter()
+            .find_map(|secret| {
+                verify_client_secret_jwt::<ClientAssertionClaims>(
+                    secret,
+                    assertion,
+                    &app.client_id,
+                    &audiences,
+                )
+                .ok()
+            })
+            .ok_or_else(|| eyre::eyre!("Invalid client assertion"))?;
+        (claims, TokenEndpointAuthMethod::ClientSecretJwt)
+    } else {
+        let jwks = client_jwks(state, app).await?;
+        let claims: ClientAssertionClaims =
+            verify_client_jwt(&jwks, assertion, &app.client_id, &audiences)?;
+        (claims, TokenEndpointAuthMethod::PrivateKeyJwt)
+    };
+
+    if claims.sub != app.client_id {
+        bail!("The client assertion isn't about the client");
+    }
+
+    if !record_assertion_use(&state.redis_pool, &app.client_id, &claims.jti, claims.exp).await? {
+        bail!("The client assertion was already used");
+    }
+
+    Ok(method)
+}
+
+/// Remembers an assertion ID until the assertion expires. Returns `false` if it was already used.
+async fn record_assertion_use(pool: &Pool, client_id: &str, jti: &str, exp: i64) -> Result<bool> {
+    if jti.is_empty() {
+        bail!("The client assertion has no jti");
+    }
+
+    let ttl = (exp - Utc::now().timestamp()).max(1);
+    let stored: Option<String> = pool
+        .set(
+            format!("{USED_ASSERTION_PREFIX}{client_id}:{jti}"),
+            1,
+            Some(Expiration::EX(ttl)),
+            Some(SetOptions::NX),
+            false,
+        )
+        .await
+        .map_err(|e| eyre::eyre!("Failed to record client assertion: {e}"))?;
+
+    Ok(stored.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn client_secret_jwt_assertions_name_their_client() {
+        let claims = json!({
+            "iss": "app",
+            "sub": "app",
+            "aud": "https://auth.example.com/api/oidc/token",
+            "exp": Utc::now().timestamp() + 60,
+            "jti": "assertion-1",
+        });
+        let assertion = jsonwebtoken::encode(
+            &Header::new(jsonwebtoken::Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+
+        assert_eq!(client_assertion_subject(&assertion).as_deref(), Some("app"));
+        assert_eq!(client_assertion_subject("not-a-jwt"), None);
+
+        let audiences = ["https://auth.example.com/api/oidc/token"];
+        let verified: ClientAssertionClaims =
+            verify_client_secret_jwt("secret", &assertion, "app", &audiences).unwrap();
+        assert_eq!(verified.jti, "assertion-1");
+        assert!(
+            verify_client_secret_jwt::<ClientAssertionClaims>(
+                "other", &assertion, "app", &audiences
+            )
+            .is_err()
+        );
+
+        for method in TokenEndpointAuthMethod::ALL {
+            assert_eq!(
+                TokenEndpointAuthMethod::parse(method.as_str()),
+                Some(method)
+            );
+        }
+        assert_eq!(TokenEndpointAuthMethod::parse("client_secret_bearer"), None);
+    }
+}
@@ -0,0 +1,209 @@
+use color_eyre::eyre::{Context, ContextCompat, Result, bail};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Validation,
+    jwk::{AlgorithmParameters, Jwk, JwkSet},
+};
+use openidconnect::core::CoreJwsSigningAlgorithm;
+use serde::de::DeserializeOwned;
+
+use crate::{database::Application, state::AppState, validators::is_https_url};
+
+/// Algorithms accepted for JWTs signed with a client secret.
+pub const CLIENT_SECRET_SIGNING_ALGORITHMS: &[Algorithm] =
+    &[Algorithm::HS256, Algorithm::HS384, Algorithm::HS512];
+
+/// Algorithms accepted for JWTs signed with the keys of a client.
+pub const CLIENT_SIGNING_ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// Keys the application signs its JWTs with, from `jwks` or fetched from `jwks_uri`.
+pub async fn client_jwks(state: &AppState, app: &Application) -> Result<JwkSet> {
+    if let Some(jwks) = &app.jwks {
+        return serde_json::from_value(jwks.clone()).wrap_err("Invalid application JWKS");
+    }
+
+    let Some(jwks_uri) = &app.jwks_uri else {
+        bail!("The application has no keys registered");
+    };
+    if !is_https_url(jwks_uri) {
+        bail!("The application's jwks_uri doesn't use https");
+    }
+
+    let body = state
+        .http_client
+        .get(jwks_uri)
+        .send()
+        .await
+        .wrap_err("Failed to fetch application JWKS")?
+        .error_for_status()
+        .wrap_err("Failed to fetch application JWKS")?
+        .text()
+        .await
+        .wrap_err("Failed to read application JWKS")?;
+
+    serde_json::from_str(&body).wrap_err("Invalid application JWKS")
+}
+
+/// Verifies a JWT signed by a client with one of its `jwks`. `iss` has to be the client ID and
+/// `aud` one of `audiences`.
+pub fn verify_client_jwt<T: DeserializeOwned>(
+    jwks: &JwkSet,
+    token: &str,
+    client_id: &str,
+    audiences: &[&str],
+) -> Result<T> {
+    let header = jsonwebtoken::decode_header(token).wrap_err("Malformed JWT")?;
+
+    // Without a key ID, only a set with a single key is unambiguous
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .wrap_err("No matching key in the application JWKS")?;
+
+    if !key_supports(jwk, header.alg) {
+        bail!("The JWT algorithm doesn't match the key");
+    }
+
+    let key = DecodingKey::from_jwk(jwk).wrap_err("Unsupported key in the application JWKS")?;
+
+    decode_client_jwt(token, &key, header.alg, client_id, audiences)
+}
+
+/// Verifies a JWT a client signed with its secret using HMAC (`client_secret_jwt`).
+pub fn verify_client_secret_jwt<T: DeserializeOwned>(
+    secret: &str,
+    token: &str,
+    client_id: &str,
+    audiences: &[&str],
+) -> Result<T> {
+    let header = jsonwebtoken::decode_header(token).wrap_err("Malformed JWT")?;
+    if !CLIENT_SECRET_SIGNING_ALGORITHMS.contains(&header.alg) {
+        bail!("The JWT isn't signed with the client secret");
+    }
+
+    let key = DecodingKey::from_secret(secret.as_bytes());
+
+    decode_client_jwt(token, &key, header.alg, client_id, audiences)
+}
+
+fn decode_client_jwt<T: DeserializeOwned>(
+    token: &str,
+    key: &DecodingKey,
+    algorithm: Algorithm,
+    client_id: &str,
+    audiences: &[&str],
+) -> Result<T> {
+    let mut validation = Validation::new(algorithm);
+    validation.set_issuer(&[client_id]);
+    validation.set_audience(audiences);
+    validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+
+    jsonwebtoken::decode::<T>(token, key, &validation)
+        .map(|data| data.claims)
+        .wrap_err("Invalid JWT")
+}
+
+/// Name of `algorithm` in discovery metadata.
+pub fn jws_algorithm(algorithm: Algorithm) -> CoreJwsSigningAlgorithm {
+    match algorithm {
+        Algorithm::HS256 => CoreJwsSigningAlgorithm::HmacSha256,
+        Algorithm::HS384 => CoreJwsSigningAlgorithm::HmacSha384,
+        Algorithm::HS512 => CoreJwsSigningAlgorithm::HmacSha512,
+        Algorithm::RS256 => CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256,
+        Algorithm::RS384 => CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha384,
+        Algorithm::RS512 => CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha512,
+        Algorithm::PS256 => CoreJwsSigningAlgorithm::RsaSsaPssSha256,
+        Algorithm::PS384 => CoreJwsSigningAlgorithm::RsaSsaPssSha384,
+        Algorithm::PS512 => CoreJwsSigningAlgorithm::RsaSsaPssSha512,
+        Algorithm::ES256 => CoreJwsSigningAlgorithm::EcdsaP256Sha256,
+        Algorithm::ES384 => CoreJwsSigningAlgorithm::EcdsaP384Sha384,
+        Algorithm::EdDSA => CoreJwsSigningAlgorithm::EdDsa,
+    }
+}
+
+/// Whether `algorithm` is one of [`CLIENT_SIGNING_ALGORITHMS`] and fits the type of `jwk`.
+pub fn key_supports(jwk: &Jwk, algorithm: Algorithm) -> bool {
+    use Algorithm::*;
+
+    match jwk.algorithm {
+        AlgorithmParameters::RSA(_) => {
+            matches!(algorithm, RS256 | RS384 | RS512 | PS256 | PS384 | PS512)
+        }
+        AlgorithmParameters::EllipticCurve(_) => matches!(algorithm, ES256 | ES384),
+        AlgorithmParameters::OctetKeyPair(_) => algorithm == EdDSA,
+        // Symmetric keys would let anyone who knows the JWKS sign
+        AlgorithmParameters::OctetKey(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::Header;
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::oidc::{
+        SigningKey, generate_private_key_pem,
+        keys::{KeyStatus, SigningAlgorithm},
+    };
+
+    /// Stand-in for the keys of a client, published like the server's own.
+    fn client_key(algorithm: SigningAlgorithm) -> (SigningKey, JwkSet) {
+        let pem = generate_private_key_pem(algorithm).unwrap();
+        let key = SigningKey::from_pem(&pem, algorithm, KeyStatus::Active).unwrap();
+        let jwks = json!({ "keys": [serde_json::to_value(&key.jwk).unwrap()] });
+
+        (key, serde_json::from_value(jwks).unwrap())
+    }
+
+    fn sign(key: &SigningKey, claims: &Value) -> String {
+        let mut header = Header::new(key.algorithm.jwt_algorithm());
+        header.kid = Some(key.kid.clone());
+
+        jsonwebtoken::encode(&header, claims, &key.encoding_key).unwrap()
+    }
+
+    #[test]
+    fn client_jwts_are_verified_with_the_client_keys() {
+        let claims = json!({
+            "iss": "app",
+            "aud": "https://auth.example.com",
+            "exp": chrono::Utc::now().timestamp() + 60,
+            "scope": "openid",
+        });
+
+        for algorithm in SigningAlgorithm::ALL {
+            let (key, jwks) = client_key(algorithm);
+            let token = sign(&key, &claims);
+
+            let verified: Value =
+                verify_client_jwt(&jwks, &token, "app", &["https://auth.example.com"]).unwrap();
+            assert_eq!(verified["scope"], "openid");
+
+            assert!(
+                verify_client_jwt::<Value>(&jwks, &token, "other", &["https://auth.example.com"])
+                    .is_err()
+            );
+            assert!(verify_client_jwt::<Value>(&jwks, &token, "app", &["https://other"]).is_err());
+        }
+
+        let (_, other_jwks) = client_key(SigningAlgorithm::Es256);
+        let (key, _) = client_key(SigningAlgorithm::Es256);
+        let token = sign(&key, &claims);
+        assert!(
+            verify_client_jwt::<Value>(&other_jwks, &token, "app", &["https://auth.example.com"])
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use color_eyre::eyre::Result;
+use mongodb::bson;
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::utils::{generate_reset_token, hash_token};
+
+use super::{client_auth::TokenEndpointAuthMethod, encryption::EncryptionKey};
+
+/// Secret a confidential application authenticates with. It is shown once when generated, then
+/// only stored hashed, see [`keeps_encrypted_secrets`] for the exception.
+///
+/// An application can have several secrets at once, so a new one can be rolled out before the
+/// old one expires.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone)]
+pub struct ClientSecret {
+    pub id: String,
+    /// SHA-256 of the secret. Secrets are random and long, so a slow hash adds nothing.
+    pub secret_hash: String,
+    /// The secret encrypted with the server's [`EncryptionKey`], as verifying `client_secret_jwt`
+    /// assertions needs the secret itself. Only set for applications that authenticate that way.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_secret: Option<String>,
+    #[schema(value_type = String)]
+    pub created_at: bson::DateTime,
+    /// The secret is rejected from then on. Never expires when not set.
+    #[schema(value_type = Option<String>)]
+    pub expires_at: Option<bson::DateTime>,
+}
+
+impl ClientSecret {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= bson::DateTime::now())
+    }
+}
+
+/// Whether the secrets of an application that authenticates with `method` are stored encrypted
+/// too. Only `client_secret_jwt` needs the secret itself, the hash is enough for the other methods,
+/// so it has to be set explicitly.
+pub fn keeps_encrypted_secrets(method: Option<TokenEndpointAuthMethod>) -> bool {
+    method == Some(TokenEndpointAuthMethod::ClientSecretJwt)
+}
+
+/// Generates a secret for an application that authenticates with `method`, returning it together
+/// with what gets stored.
+pub fn generate_client_secret(
+    key: &EncryptionKey,
+    method: Option<TokenEndpointAuthMethod>,
+    expires_at: Option<bson::DateTime>,
+) -> Result<(String, ClientSecret)> {
+    let secret = generate_reset_token();
+    let client_secret = stored_client_secret(key, method, &secret, expires_at)?;
+
+    Ok((secret, client_secret))
+}
+
+/// What gets stored for `secret` of an application that authenticates with `method`.
+pub fn stored_client_secret(
+    key: &EncryptionKey,
+    method: Option<TokenEndpointAuthMethod>,
+    secret: &str,
+    expires_at: Option<bson::DateTime>,
+) -> Result<ClientSecret> {
+    let encrypted_secret = if keeps_encrypted_secrets(method) {
+        Some(key.encrypt(secret.as_bytes())?)
+    } else {
+        None
+    };
+
+    Ok(ClientSecret {
+        id: Uuid::new_v4().to_string(),
+        secret_hash: hash_token(secret),
+        encrypted_secret,
+        created_at: bson::DateTime::now(),
+        expires_at,
+    })
+}
+
+/// Whether `secret` matches one of the unexpired `secrets`. Compares in constant time.
+pub fn verify_client_secret(secrets: &[ClientSecret], secret: &str) -> bool {
+    let hash = hash_token(secret);
+
+    // Every secret is compared, so the timing doesn't tell which one matched
+    secrets.iter().fold(false, |matched, client_secret| {
+        let equal = bool::from(client_secret.secret_hash.as_bytes().ct_eq(hash.as_bytes()));
+        matched | (equal && !client_secret.is_expired())
+    })
+}
+
+/// The unexpired `secrets` in plaintext, to verify `client_secret_jwt` assertions with.
+pub fn decrypt_client_secrets(key: &EncryptionKey, secrets: &[ClientSecret]) -> Vec<String> {
+    secrets
+        .iter()
+        .filter(|secret| !secret.is_expired())
+        .filter_map(|secret| secret.encrypted_secret.as_deref())
+        .filter_map(|encrypted| key.decrypt_string(encrypted).ok())
+        .collect()
+}
+
+/// `client_secret_expires_at` of RFC 7591: when the last unexpired secret expires, in seconds
+/// since the epoch, or `0` if one of them doesn't. `None` without unexpired secrets.
+pub fn client_secret_expires_at(secrets: &[ClientSecret]) -> Option<i64> {
+    let active = secrets.iter().filter(|secret| !secret.is_expired());
+
+    active
+        .map(|secret| {
+            secret
+                .expires_at
+                .map_or(0, |expires_at| expires_at.timestamp_millis() / 1000)
+        })
+        .reduce(|latest, expires_at| {
+            if latest == 0 || expires_at == 0 {
+                0
+            } else {
+                latest.max(expires_at)
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oidc::encryption::test_encryption_key;
+
+    fn expiring_in(secs: i64) -> Option<bson::DateTime> {
+        Some(bson::DateTime::from_millis(
+            bson::DateTime::now().timestamp_millis() + secs * 1000,
+        ))
+    }
+
+    #[test]
+    fn rotated_secrets_stay_valid_until_they_expire() {
+        let key = test_encryption_key();
+        let (old, mut old_secret) =
+            generate_client_secret(&key, Some(TokenEndpointAuthMethod::ClientSecretJwt), None)
+                .unwrap();
+        let (new, new_secret) =
+            generate_client_secret(&key, Some(TokenEndpointAuthMethod::ClientSecretJwt), None)
+                .unwrap();
+
+        old_secret.expires_at = expiring_in(3600);
+        let secrets = [old_secret.clone(), new_secret.clone()];
+        assert!(verify_client_secret(&secrets, &old));
+        assert!(verify_client_secret(&secrets, &new));
+        assert!(!verify_client_secret(&secrets, "guess"));
+        assert_eq!(client_secret_expires_at(&secrets), Some(0));
+        assert_eq!(
+            decrypt_client_secrets(&key, &secrets),
+            [old.clone(), new.clone()]
+        );
+
+        old_secret.expires_at = expiring_in(-1);
+        let secrets = [old_secret, new_secret];
+        assert!(!verify_client_secret(&secrets, &old));
+        assert!(verify_client_secret(&secrets, &new));
+        assert_eq!(decrypt_client_secrets(&key, &secrets), [new]);
+    }
+
+    #[test]
+    fn only_client_secret_jwt_keeps_secrets_encrypted() {
+        let key = test_encryption_key();
+
+        for method in [
+            None,
+            Some(TokenEndpointAuthMethod::ClientSecretBasic),
+            Some(TokenEndpointAuthMethod::ClientSecretPost),
+        ] {
+            let (secret, stored) = generate_client_secret(&key, method, None).unwrap();
+            assert!(stored.encrypted_secret.is_none());
+            assert!(verify_client_secret(&[stored], &secret));
+        }
+    }
+
+    #[test]
+    fn secrets_expire_with_the_last_one() {
+        let key = test_encryption_key();
+        let (_, mut first) = generate_client_secret(&key, None, expiring_in(60)).unwrap();
+        let (_, second) = generate_client_secret(&key, None, expiring_in(3600)).unwrap();
+        let second_expiry = second.expires_at.unwrap().timestamp_millis() / 1000;
+
+        assert_eq!(
+            client_secret_expires_at(&[first.clone(), second]),
+            Some(second_expiry)
+        );
+
+        first.expires_at = expiring_in(-1);
+        assert_eq!(client_secret_expires_at(&[first]), None);
+    }
+}
@@ -0,0 +1,86 @@
+use color_eyre::eyre::{Context, Result};
+use mongodb::{
+    Database,
+    bson::{self, doc, oid::ObjectId},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::oidc::RefreshToken;
+
+/// Scopes a user has allowed an application to access, stored in MongoDB.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConsentGrant {
+    pub user_id: ObjectId,
+    pub client_id: String,
+    pub scopes: Vec<String>,
+    pub created_at: bson::DateTime,
+    pub updated_at: bson::DateTime,
+}
+
+/// Whether the user already allowed every one of `scopes` for the application.
+pub async fn has_consent(
+    database: &Database,
+    user_id: &ObjectId,
+    client_id: &str,
+    scopes: &[String],
+) -> Result<bool> {
+    let grant = database
+        .collection::<ConsentGrant>("oidc_consents")
+        .find_one(doc! { "user_id": user_id, "client_id": client_id })
+        .await
+        .wrap_err("Failed to fetch consent grant")?;
+
+    Ok(grant.is_some_and(|grant| scopes.iter().all(|scope| grant.scopes.contains(scope))))
+}
+
+/// Adds `scopes` to the user's grant for the application, creating it on first consent.
+pub async fn record_consent(
+    database: &Database,
+    user_id: &ObjectId,
+    client_id: &str,
+    scopes: &[String],
+) -> Result<()> {
+    let now = bson::DateTime::now();
+
+    database
+        .collection::<ConsentGrant>("oidc_consents")
+        .update_one(
+            doc! { "user_id": user_id, "client_id": client_id },
+            doc! {
+                "$addToSet": { "scopes": { "$each": scopes } },
+                "$set": { "updated_at": now },
+                "$setOnInsert": { "created_at": now },
+            },
+        )
+        .upsert(true)
+        .await
+        .wrap_err("Failed to record consent")?;
+
+    Ok(())
+}
+
+/// Removes the user's grant for the application and revokes the refresh tokens issued under it.
+///
+/// Returns `false` if there was no grant to revoke.
+pub async fn revoke_consent(
+    database: &Database,
+    user_id: &ObjectId,
+    client_id: &str,
+) -> Result<bool> {
+    let result = database
+        .collection::<ConsentGrant>("oidc_consents")
+        .delete_one(doc! { "user_id": user_id, "client_id": client_id })
+        .await
+        .wrap_err("Failed to revoke consent")?;
+
+    database
+        .collection::<RefreshToken>("refresh_tokens")
+        .update_many(
+            doc! { "user_id": user_id.to_hex(), "client_id": client_id },
+            doc! { "$set": { "revoked": true } },
+        )
+        .await
+        .wrap_err("Failed to revoke refresh tokens")?;
+
+    Ok(result.deleted_count > 0)
+}
@@ -0,0 +1,78 @@
+use mongodb::bson;
+use rand::{RngExt, rngs::ThreadRng};
+use serde::{Deserialize, Serialize};
+
+use crate::oidc::authentication::SessionAuthentication;
+
+/// `grant_type` used to redeem a device code at the token endpoint.
+pub const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// How long a device code can be redeemed, in seconds.
+pub const DEVICE_CODE_LIFETIME_SECS: i64 = 600;
+
+/// Minimum time between token requests for a device code, in seconds.
+pub const DEVICE_POLL_INTERVAL_SECS: i64 = 5;
+
+/// Added to the polling interval every time a client polls too fast (RFC 8628, section 3.5).
+pub const SLOW_DOWN_INCREMENT_SECS: i64 = 5;
+
+/// Consonants only, so codes don't spell words and are easy to type (RFC 8628, section 6.1).
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+
+const USER_CODE_LENGTH: usize = 8;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceAuthorizationStatus {
+    Pending,
+    Approved,
+    Denied,
+}
+
+/// Device authorization request stored in MongoDB.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeviceAuthorization {
+    pub device_code_hash: String,
+    /// Normalized user code, see [`normalize_user_code`].
+    pub user_code: String,
+    pub client_id: String,
+    pub scope: String,
+    pub status: DeviceAuthorizationStatus,
+    /// User who approved the request.
+    pub user_id: Option<String>,
+    /// SSO session the request was approved from.
+    pub sid: Option<String>,
+    pub session_id: Option<String>,
+    /// How the approving user signed in, for the `auth_time`, `acr` and `amr` claims.
+    #[serde(default)]
+    pub authentication: Option<SessionAuthentication>,
+    /// Current polling interval in seconds, raised on every `slow_down`.
+    pub interval: i64,
+    pub last_polled_at: Option<bson::DateTime>,
+    pub expires_at: bson::DateTime,
+}
+
+/// Generate a user code, normalized for storage.
+pub fn generate_user_code() -> String {
+    let mut rng = ThreadRng::default();
+
+    (0..USER_CODE_LENGTH)
+        .map(|_| char::from(USER_CODE_ALPHABET[rng.random_range(0..USER_CODE_ALPHABET.len())]))
+        .collect()
+}
+
+/// Uppercase the code and drop separators, so `bcdf-ghjk` matches `BCDFGHJK`.
+pub fn normalize_user_code(user_code: &str) -> String {
+    user_code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Format a normalized user code for display, e.g. `BCDF-GHJK`.
+pub fn format_user_code(user_code: &str) -> String {
+    let (first, second) = user_code.split_at(user_code.len() / 2);
+
+    format!("{first}-{second}")
+}
@@ -0,0 +1,209 @@
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::Utc;
+use color_eyre::eyre::{self, Context, ContextCompat, Result, bail};
+use fred::prelude::{Expiration, KeysInterface, Pool, SetOptions};
+use jsonwebtoken::{
+    DecodingKey, Validation,
+    jwk::{AlgorithmParameters, Jwk},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use super::client_keys::key_supports;
+
+/// `typ` header of a DPoP proof (RFC 9449, section 4.2).
+const DPOP_PROOF_TYPE: &str = "dpop+jwt";
+
+/// How old a proof may be, and how far its `iat` may lie in the future to allow for clock skew.
+const MAX_PROOF_AGE_SECS: i64 = 300;
+const PROOF_CLOCK_SKEW_SECS: i64 = 60;
+
+/// Redis key prefix for the IDs of DPoP proofs that were already used.
+const USED_PROOF_PREFIX: &str = "oidc:used_dpop_proof:";
+
+/// `cnf` claim of a DPoP-bound access token (RFC 9449, section 6.1).
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
+pub struct ConfirmationClaim {
+    /// JWK SHA-256 thumbprint of the key the token is bound to.
+    pub jkt: String,
+}
+
+impl ConfirmationClaim {
+    pub fn for_key(jkt: Option<&str>) -> Option<Self> {
+        jkt.map(|jkt| ConfirmationClaim {
+            jkt: jkt.to_string(),
+        })
+    }
+}
+
+/// `token_type` of an access token: `DPoP` when it is bound to a key, `Bearer` otherwise.
+pub fn access_token_type(cnf: Option<&ConfirmationClaim>) -> &'static str {
+    if cnf.is_some() { "DPoP" } else { "Bearer" }
+}
+
+#[derive(Debug, Deserialize)]
+struct DpopProofClaims {
+    jti: String,
+    htm: String,
+    htu: String,
+    iat: i64,
+    ath: Option<String>,
+}
+
+/// Verifies the DPoP proof sent with a request to `method` `url` and returns the thumbprint of
+/// the key it was signed with. Proofs sent with an access token have to carry its hash.
+///
+/// Every proof can only be used once.
+pub async fn verify_dpop_proof(
+    pool: &Pool,
+    proof: &str,
+    method: &str,
+    url: &str,
+    access_token: Option<&str>,
+) -> Result<String> {
+    let header = jsonwebtoken::decode_header(proof).wrap_err("Malformed DPoP proof")?;
+
+    if header.typ.as_deref() != Some(DPOP_PROOF_TYPE) {
+        bail!("The DPoP proof has the wrong type");
+    }
+    if has_private_key(proof) {
+        bail!("The DPoP proof contains a private key");
+    }
+
+    let jwk = header.jwk.wrap_err("The DPoP proof has no key")?;
+    if !key_supports(&jwk, header.alg) {
+        bail!("The DPoP proof algorithm doesn't match the key");
+    }
+
+    let key = DecodingKey::from_jwk(&jwk).wrap_err("Unsupported DPoP key")?;
+    let mut validation = Validation::new(header.alg);
+    validation.validate_exp = false;
+    validation.set_required_spec_claims::<&str>(&[]);
+
+    let claims = jsonwebtoken::decode::<DpopProofClaims>(proof, &key, &validation)
+        .wrap_err("Invalid DPoP proof")?
+        .claims;
+
+    let now = Utc::now().timestamp();
+    if claims.iat < now - MAX_PROOF_AGE_SECS || claims.iat > now + PROOF_CLOCK_SKEW_SECS {
+        bail!("The DPoP proof is expired or not yet valid");
+    }
+    if !claims.htm.eq_ignore_ascii_case(method) || strip_query(&claims.htu) != strip_query(url) {
+        bail!("The DPoP proof is for a different request");
+    }
+    if let Some(access_token) = access_token
+        && claims.ath.as_deref() != Some(access_token_hash(access_token).as_str())
+    {
+        bail!("The DPoP proof is for a different access token");
+    }
+
+    let jkt = jwk_thumbprint(&jwk)?;
+    if !record_proof_use(pool, &jkt, &claims.jti).await? {
+        bail!("The DPoP proof was already used");
+    }
+
+    Ok(jkt)
+}
+
+/// JWK SHA-256 thumbprint (RFC 7638): the hash of the required members in lexicographic order.
+pub fn jwk_thumbprint(jwk: &Jwk) -> Result<String> {
+    let json = |value: &str| serde_json::Value::from(value).to_string();
+
+    let canonical = match &jwk.algorithm {
+        AlgorithmParameters::EllipticCurve(params) => format!(
+            r#"{{"crv":{},"kty":"EC","x":{},"y":{}}}"#,
+            serde_json::to_string(&params.curve)?,
+            json(&params.x),
+            json(&params.y)
+        ),
+        AlgorithmParameters::RSA(params) => format!(
+            r#"{{"e":{},"kty":"RSA","n":{}}}"#,
+            json(&params.e),
+            json(&params.n)
+        ),
+        AlgorithmParameters::OctetKeyPair(params) => format!(
+            r#"{{"crv":{},"kty":"OKP","x":{}}}"#,
+            serde_json::to_string(&params.curve)?,
+            json(&params.x)
+        ),
+        AlgorithmParameters::OctetKey(_) => bail!("Symmetric keys can't be used for DPoP"),
+    };
+
+    Ok(URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes())))
+}
+
+/// `ath` claim of a proof: the base64url-encoded SHA-256 of the access token.
+fn access_token_hash(access_token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(access_token.as_bytes()))
+}
+
+/// Whether the `jwk` in the proof header has a private part. The parsed [`Jwk`] drops it.
+fn has_private_key(proof: &str) -> bool {
+    let header = proof
+        .split('.')
+        .next()
+        .and_then(|header| URL_SAFE_NO_PAD.decode(header).ok())
+        .and_then(|header| serde_json::from_slice::<serde_json::Value>(&header).ok());
+
+    header
+        .as_ref()
+        .and_then(|header| header.get("jwk"))
+        .is_some_and(|jwk| jwk.get("d").is_some() || jwk.get("k").is_some())
+}
+
+/// `htu` is compared without query and fragment (RFC 9449, section 4.3).
+fn strip_query(url: &str) -> &str {
+    url.split(['?', '#']).next().unwrap_or(url)
+}
+
+/// Remembers a proof ID for as long as the proof is accepted. Returns `false` if it was already used.
+async fn record_proof_use(pool: &Pool, jkt: &str, jti: &str) -> Result<bool> {
+    if jti.is_empty() {
+        bail!("The DPoP proof has no jti");
+    }
+
+    let stored: Option<String> = pool
+        .set(
+            format!("{USED_PROOF_PREFIX}{jkt}:{jti}"),
+            1,
+            Some(Expiration::EX(MAX_PROOF_AGE_SECS + PROOF_CLOCK_SKEW_SECS)),
+            Some(SetOptions::NX),
+            false,
+        )
+        .await
+        .map_err(|e| eyre::eyre!("Failed to record DPoP proof: {e}"))?;
+
+    Ok(stored.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thumbprints_follow_rfc_7638() {
+        // Example key from RFC 7638, section 3.1
+        let jwk: Jwk = serde_json::from_value(serde_json::json!({
+            "kty": "RSA",
+            "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+            "e": "AQAB",
+            "alg": "RS256",
+            "kid": "2011-04-29"
+        }))
+        .unwrap();
+
+        assert_eq!(
+            jwk_thumbprint(&jwk).unwrap(),
+            "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        );
+        assert_eq!(
+            access_token_hash("Kz~8mXK1EalYznwH-LC-1fBAo.4Ljp~zsPE_NeO.gxU"),
+            "fUHyO2r2Z3DZ53EsNrWBb0xWXoaNy59IiKCAqksmQEo"
+        );
+        assert_eq!(
+            strip_query("https://server.example.com/token?x=1"),
+            "https://server.example.com/token"
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use std::{path::Path, sync::Arc};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use color_eyre::eyre::{Context, Result, bail, eyre};
+use ring::{
+    aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
+    rand::{SecureRandom, SystemRandom},
+};
+use tracing::{info, warn};
+
+use crate::settings::Oidc;
+
+/// Length of the AES-256 key, in bytes.
+const KEY_LEN: usize = 32;
+
+/// Key that encrypts secrets stored in the database, such as client secrets and signing keys,
+/// so reading the database isn't enough to use them.
+pub struct EncryptionKey {
+    key: LessSafeKey,
+}
+
+impl EncryptionKey {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let key = UnboundKey::new(&AES_256_GCM, bytes)
+            .map_err(|_| eyre!("The encryption key has to be {KEY_LEN} bytes long"))?;
+
+        Ok(Self {
+            key: LessSafeKey::new(key),
+        })
+    }
+
+    /// Encrypts with AES-256-GCM and a random nonce. Returns the base64 of the nonce followed by
+    /// the ciphertext.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| eyre!("Failed to generate nonce"))?;
+
+        let mut ciphertext = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut ciphertext,
+            )
+            .map_err(|_| eyre!("Failed to encrypt"))?;
+
+        Ok(STANDARD.encode([nonce.as_slice(), &ciphertext].concat()))
+    }
+
+    /// Inverse of [`EncryptionKey::encrypt`].
+    pub fn decrypt(&self, encrypted: &str) -> Result<Vec<u8>> {
+        let encrypted = STANDARD
+            .decode(encrypted)
+            .wrap_err("Malformed encrypted value")?;
+        if encrypted.len() < NONCE_LEN {
+            bail!("Malformed encrypted value");
+        }
+
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| eyre!("Malformed encrypted value"))?;
+
+        let mut plaintext = ciphertext.to_vec();
+        let len = self
+            .key
+            .open_in_place(nonce, Aad::empty(), &mut plaintext)
+            .map_err(|_| eyre!("Failed to decrypt, was it encrypted with another key?"))?
+            .len();
+        plaintext.truncate(len);
+
+        Ok(plaintext)
+    }
+
+    pub fn decrypt_string(&self, encrypted: &str) -> Result<String> {
+        String::from_utf8(self.decrypt(encrypted)?).wrap_err("Encrypted value isn't UTF-8")
+    }
+}
+
+fn generate_key_bytes() -> Result<[u8; KEY_LEN]> {
+    let mut key = [0u8; KEY_LEN];
+    SystemRandom::new()
+        .fill(&mut key)
+        .map_err(|_| eyre!("Failed to generate encryption key"))?;
+
+    Ok(key)
+}
+
+/// Generate an encryption key file at the given path, holding the base64 of a random key.
+/// Called during first-run config generation.
+pub fn generate_encryption_key_file(key_file: &str) -> Result<()> {
+    if Path::new(key_file).exists() {
+        return Ok(());
+    }
+
+    let key = STANDARD.encode(generate_key_bytes()?);
+    std::fs::write(key_file, key).wrap_err("Failed to write encryption key")?;
+    info!("Encryption key saved to {key_file}");
+
+    Ok(())
+}
+
+/// Load the key from `encryption_key_file`, generating it on first start.
+pub fn init_encryption_key(settings: &Oidc) -> Result<Arc<EncryptionKey>> {
+    let key_file = &settings.encryption_key_file;
+
+    if !Path::new(key_file).exists() {
+        warn!("Generating a new encryption key, every instance has to use the same {key_file}");
+        generate_encryption_key_file(key_file)?;
+    }
+
+    let key = std::fs::read_to_string(key_file).wrap_err("Failed to read encryption key")?;
+    let key = STANDARD
+        .decode(key.trim())
+        .wrap_err("The encryption key isn't valid base64")?;
+
+    Ok(Arc::new(EncryptionKey::from_bytes(&key)?))
+}
+
+#[cfg(test)]
+pub fn test_encryption_key() -> EncryptionKey {
+    EncryptionKey::from_bytes(&generate_key_bytes().unwrap()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_values_only_open_with_their_key() {
+        let key = test_encryption_key();
+
+        let encrypted = key.encrypt(b"client secret").unwrap();
+        assert_eq!(key.decrypt_string(&encrypted).unwrap(), "client secret");
+        assert_ne!(encrypted, key.encrypt(b"client secret").unwrap());
+
+        assert!(test_encryption_key().decrypt(&encrypted).is_err());
+        assert!(key.decrypt("dG9vIHNob3J0").is_err());
+    }
+}
@@ -0,0 +1,415 @@
+use std::{sync::Arc, time::Duration};
+
+use color_eyre::eyre::{Context, Result};
+use futures::TryStreamExt;
+use jsonwebtoken::Algorithm;
+use mongodb::{
+    Collection, Database,
+    bson::{self, doc},
+};
+use openidconnect::core::CoreJwsSigningAlgorithm;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    oidc::{
+        OidcKeys, SigningKey, encryption::EncryptionKey, generate_private_key_pem,
+        lifetimes::TokenLifetimeOverrides,
+    },
+    settings::Oidc,
+    state::AppState,
+};
+
+/// How often the key ring is reloaded from the database and checked for a due rotation.
+const KEY_RING_REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// Lifecycle state of a signing key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyStatus {
+    /// Published ahead of time, becomes active on the next rotation.
+    Next,
+    /// Used to sign new tokens.
+    Active,
+    /// No longer used for signing, published until it expires.
+    Retired,
+}
+
+/// JWS algorithm used to sign ID tokens and access tokens.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum SigningAlgorithm {
+    /// RSASSA-PKCS1-v1_5 with SHA-256.
+    #[default]
+    #[serde(rename = "RS256")]
+    Rs256,
+    /// ECDSA with P-256 and SHA-256.
+    #[serde(rename = "ES256")]
+    Es256,
+    /// Ed25519.
+    #[serde(rename = "EdDSA")]
+    EdDsa,
+}
+
+impl SigningAlgorithm {
+    /// Every algorithm the key ring holds keys for.
+    pub const ALL: [SigningAlgorithm; 3] = [
+        SigningAlgorithm::Rs256,
+        SigningAlgorithm::Es256,
+        SigningAlgorithm::EdDsa,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SigningAlgorithm::Rs256 => "RS256",
+            SigningAlgorithm::Es256 => "ES256",
+            SigningAlgorithm::EdDsa => "EdDSA",
+        }
+    }
+
+    pub fn jwt_algorithm(self) -> Algorithm {
+        match self {
+            SigningAlgorithm::Rs256 => Algorithm::RS256,
+            SigningAlgorithm::Es256 => Algorithm::ES256,
+            SigningAlgorithm::EdDsa => Algorithm::EdDSA,
+        }
+    }
+
+    pub fn jws_algorithm(self) -> CoreJwsSigningAlgorithm {
+        match self {
+            SigningAlgorithm::Rs256 => CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256,
+            SigningAlgorithm::Es256 => CoreJwsSigningAlgorithm::EcdsaP256Sha256,
+            SigningAlgorithm::EdDsa => CoreJwsSigningAlgorithm::EdDsa,
+        }
+    }
+}
+
+/// Signing key stored in MongoDB.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StoredSigningKey {
+    pub kid: String,
+    pub algorithm: SigningAlgorithm,
+    /// PKCS#1 PEM private key for RSA, PKCS#8 PEM otherwise, encrypted with the [`EncryptionKey`].
+    pub encrypted_private_key: String,
+    pub status: KeyStatus,
+    pub created_at: bson::DateTime,
+    pub activated_at: Option<bson::DateTime>,
+    pub retired_at: Option<bson::DateTime>,
+}
+
+impl StoredSigningKey {
+    fn new(
+        encryption_key: &EncryptionKey,
+        private_key: &str,
+        algorithm: SigningAlgorithm,
+        status: KeyStatus,
+    ) -> Result<Self> {
+        let kid = SigningKey::from_pem(private_key, algorithm, status)?.kid;
+        let now = bson::DateTime::now();
+
+        Ok(Self {
+            kid,
+            algorithm,
+            encrypted_private_key: encryption_key.encrypt(private_key.as_bytes())?,
+            status,
+            created_at: now,
+            activated_at: (status == KeyStatus::Active).then_some(now),
+            retired_at: None,
+        })
+    }
+
+    fn generate(
+        encryption_key: &EncryptionKey,
+        algorithm: SigningAlgorithm,
+        status: KeyStatus,
+    ) -> Result<Self> {
+        let private_key = generate_private_key_pem(algorithm)?;
+
+        Self::new(encryption_key, &private_key, algorithm, status)
+    }
+
+    fn signing_key(&self, encryption_key: &EncryptionKey) -> Result<SigningKey> {
+        let private_key = encryption_key
+            .decrypt_string(&self.encrypted_private_key)
+            .wrap_err_with(|| format!("Failed to decrypt OIDC signing key {}", self.kid))?;
+
+        SigningKey::from_pem(&private_key, self.algorithm, self.status)
+    }
+
+    /// When a retired key stops being published, see [`retired_key_lifetime`].
+    pub fn expires_at(&self, lifetime_millis: i64) -> Option<bson::DateTime> {
+        self.retired_at.map(|retired_at| {
+            bson::DateTime::from_millis(retired_at.timestamp_millis() + lifetime_millis)
+        })
+    }
+}
+
+/// Lifetime overrides of an application, see [`retired_key_lifetime`].
+#[derive(Deserialize)]
+struct ApplicationLifetimes {
+    #[serde(default)]
+    token_lifetimes: TokenLifetimeOverrides,
+}
+
+/// How long retired keys stay published, in milliseconds: `retired_key_lifetime_days`, or longer
+/// while access and ID tokens signed with them can still be valid.
+pub async fn retired_key_lifetime(database: &Database, settings: &Oidc) -> Result<i64> {
+    let lifetimes = &settings.token_lifetimes;
+    let mut longest_secs = lifetimes.access_token_secs.max(lifetimes.id_token_secs);
+
+    let mut applications = database
+        .collection::<ApplicationLifetimes>("applications")
+        .find(doc! {})
+        .projection(doc! { "token_lifetimes": 1_i32 })
+        .await
+        .wrap_err("Failed to fetch application token lifetimes")?;
+
+    while let Some(app) = applications
+        .try_next()
+        .await
+        .wrap_err("Failed to fetch application token lifetimes")?
+    {
+        let overrides = app.token_lifetimes;
+        longest_secs = longest_secs
+            .max(overrides.access_token_secs.unwrap_or_default())
+            .max(overrides.id_token_secs.unwrap_or_default());
+    }
+
+    Ok(
+        (i64::from(settings.retired_key_lifetime_days) * MILLIS_PER_DAY)
+            .max(i64::from(longest_secs) * 1000),
+    )
+}
+
+fn signing_keys(database: &Database) -> Collection<StoredSigningKey> {
+    database.collection::<StoredSigningKey>("oidc_signing_keys")
+}
+
+/// Load the key ring, creating missing keys on first start.
+/// The RSA key is seeded from `signing_key_file`.
+pub async fn init_oidc_keys(
+    database: &Database,
+    settings: &Oidc,
+    encryption_key: &EncryptionKey,
+) -> Result<Arc<OidcKeys>> {
+    let collection = signing_keys(database);
+
+    for algorithm in SigningAlgorithm::ALL {
+        let filter = doc! { "algorithm": algorithm.as_str(), "status": "active" };
+
+        if collection.find_one(filter).await?.is_none() {
+            let key_file = &settings.signing_key_file;
+            let private_key = if algorithm == SigningAlgorithm::Rs256
+                && std::path::Path::new(key_file).exists()
+            {
+                info!("Importing OIDC signing key from {key_file}");
+                std::fs::read_to_string(key_file).wrap_err("Failed to read OIDC signing key")?
+            } else {
+                generate_private_key_pem(algorithm)?
+            };
+
+            insert_if_missing(
+                &collection,
+                StoredSigningKey::new(encryption_key, &private_key, algorithm, KeyStatus::Active)?,
+            )
+            .await?;
+        }
+
+        ensure_next_key(&collection, encryption_key, algorithm).await?;
+    }
+
+    let oidc_keys = OidcKeys::new(
+        load_signing_keys(database, settings, encryption_key).await?,
+        settings.signing_algorithm,
+    )?;
+    info!(
+        "OIDC provider initialized with kid={}",
+        oidc_keys.active_key(None).kid
+    );
+
+    Ok(Arc::new(oidc_keys))
+}
+
+/// Insert a key unless another instance already created one with the same status.
+async fn insert_if_missing(
+    collection: &Collection<StoredSigningKey>,
+    key: StoredSigningKey,
+) -> Result<()> {
+    let status = bson::to_bson(&key.status)?;
+
+    collection
+        .update_one(
+            doc! { "algorithm": key.algorithm.as_str(), "status": status },
+            doc! { "$setOnInsert": bson::to_document(&key)? },
+        )
+        .upsert(true)
+        .await
+        .wrap_err("Failed to store OIDC signing key")?;
+
+    Ok(())
+}
+
+/// Make sure an upcoming key is published before it is needed.
+async fn ensure_next_key(
+    collection: &Collection<StoredSigningKey>,
+    encryption_key: &EncryptionKey,
+    algorithm: SigningAlgorithm,
+) -> Result<()> {
+    if collection
+        .find_one(doc! { "algorithm": algorithm.as_str(), "status": "next" })
+        .await?
+        .is_none()
+    {
+        insert_if_missing(
+            collection,
+            StoredSigningKey::generate(encryption_key, algorithm, KeyStatus::Next)?,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// All stored signing keys, after deleting retired keys past their lifetime.
+pub async fn stored_signing_keys(
+    database: &Database,
+    settings: &Oidc,
+) -> Result<Vec<StoredSigningKey>> {
+    let collection = signing_keys(database);
+
+    let cutoff = bson::DateTime::from_millis(
+        bson::DateTime::now().timestamp_millis() - retired_key_lifetime(database, settings).await?,
+    );
+    collection
+        .delete_many(doc! { "status": "retired", "retired_at": { "$lt": cutoff } })
+        .await
+        .wrap_err("Failed to delete expired OIDC signing keys")?;
+
+    let keys = collection
+        .find(doc! {})
+        .sort(doc! { "created_at": -1_i32 })
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(keys)
+}
+
+async fn load_signing_keys(
+    database: &Database,
+    settings: &Oidc,
+    encryption_key: &EncryptionKey,
+) -> Result<Vec<SigningKey>> {
+    stored_signing_keys(database, settings)
+        .await?
+        .iter()
+        .map(|key| key.signing_key(encryption_key))
+        .collect()
+}
+
+/// Retire the active key of every algorithm and promote the next ones.
+/// With `due_before`, only rotates keys activated before that time.
+/// Returns whether any key was rotated.
+pub async fn rotate_signing_keys(
+    database: &Database,
+    encryption_key: &EncryptionKey,
+    due_before: Option<bson::DateTime>,
+) -> Result<bool> {
+    let mut rotated = false;
+
+    for algorithm in SigningAlgorithm::ALL {
+        rotated |= rotate_signing_key(database, encryption_key, algorithm, due_before).await?;
+    }
+
+    Ok(rotated)
+}
+
+async fn rotate_signing_key(
+    database: &Database,
+    encryption_key: &EncryptionKey,
+    algorithm: SigningAlgorithm,
+    due_before: Option<bson::DateTime>,
+) -> Result<bool> {
+    let collection = signing_keys(database);
+    let now = bson::DateTime::now();
+
+    let mut filter = doc! { "algorithm": algorithm.as_str(), "status": "active" };
+    if let Some(due_before) = due_before {
+        filter.insert("activated_at", doc! { "$lte": due_before });
+    }
+
+    let Some(retired) = collection
+        .find_one_and_update(
+            filter,
+            doc! { "$set": { "status": "retired", "retired_at": now } },
+        )
+        .await
+        .wrap_err("Failed to retire OIDC signing key")?
+    else {
+        return Ok(false);
+    };
+
+    let promoted = collection
+        .find_one_and_update(
+            doc! { "algorithm": algorithm.as_str(), "status": "next" },
+            doc! { "$set": { "status": "active", "activated_at": now } },
+        )
+        .await
+        .wrap_err("Failed to activate OIDC signing key")?;
+
+    if promoted.is_none() {
+        collection
+            .insert_one(StoredSigningKey::generate(
+                encryption_key,
+                algorithm,
+                KeyStatus::Active,
+            )?)
+            .await
+            .wrap_err("Failed to store OIDC signing key")?;
+    }
+
+    ensure_next_key(&collection, encryption_key, algorithm).await?;
+
+    info!(retired_kid = %retired.kid, "Rotated OIDC signing key");
+
+    Ok(true)
+}
+
+/// Reload the in-memory key ring from the database.
+pub async fn reload_key_ring(state: &AppState) -> Result<()> {
+    let keys =
+        load_signing_keys(&state.database, &state.settings.oidc, &state.encryption_key).await?;
+
+    state.oidc_keys.replace(keys)
+}
+
+/// Periodically rotate the active key when `key_rotation_interval_days` is set,
+/// and pick up rotations done by other instances.
+pub fn spawn_key_ring_refresh(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(KEY_RING_REFRESH_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Some(days) = state.settings.oidc.key_rotation_interval_days {
+                let due_before = bson::DateTime::from_millis(
+                    bson::DateTime::now().timestamp_millis() - i64::from(days) * MILLIS_PER_DAY,
+                );
+
+                if let Err(error) =
+                    rotate_signing_keys(&state.database, &state.encryption_key, Some(due_before))
+                        .await
+                {
+                    warn!(error = ?error, "Failed to rotate OIDC signing key");
+                }
+            }
+
+            if let Err(error) = reload_key_ring(&state).await {
+                warn!(error = ?error, "Failed to reload OIDC key ring");
+            }
+        }
+    });
+}
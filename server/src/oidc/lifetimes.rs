@@ -0,0 +1,253 @@
+use chrono::{DateTime, Duration, Utc};
+use color_eyre::eyre::Result;
+use mongodb::{Database, bson::doc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::database::{Application, SessionRecord};
+
+use super::RefreshToken;
+
+/// How the lifetime of a refresh token is counted.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RefreshTokenExpiration {
+    /// From the last refresh, so clients that keep refreshing stay signed in.
+    #[default]
+    Sliding,
+    /// From the authorization the grant started with. Rotating the token doesn't extend it.
+    Absolute,
+}
+
+/// Longest lifetime of authorization codes, 10 minutes is recommended.
+const MAX_AUTHORIZATION_CODE_SECS: u32 = 60 * 60;
+
+/// Longest lifetime of access and ID tokens.
+const MAX_TOKEN_SECS: u32 = 7 * 24 * 60 * 60;
+
+/// Longest lifetime of refresh tokens.
+const MAX_REFRESH_TOKEN_DAYS: u32 = 10 * 365;
+
+/// Lifetimes of the codes and tokens the server issues.
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct TokenLifetimes {
+    #[validate(range(min = 1, max = MAX_AUTHORIZATION_CODE_SECS))]
+    #[serde(default = "TokenLifetimes::default_authorization_code_secs")]
+    pub authorization_code_secs: u32,
+
+    /// Also the upper bound of tokens from the token exchange grant.
+    #[validate(range(min = 1, max = MAX_TOKEN_SECS))]
+    #[serde(default = "TokenLifetimes::default_access_token_secs")]
+    pub access_token_secs: u32,
+
+    #[validate(range(min = 1, max = MAX_TOKEN_SECS))]
+    #[serde(default = "TokenLifetimes::default_id_token_secs")]
+    pub id_token_secs: u32,
+
+    #[validate(range(min = 1, max = MAX_REFRESH_TOKEN_DAYS))]
+    #[serde(default = "TokenLifetimes::default_refresh_token_days")]
+    pub refresh_token_days: u32,
+
+    #[serde(default)]
+    pub refresh_token_expiration: RefreshTokenExpiration,
+
+    /// Refresh tokens stop working once the SSO session the user signed in with ends,
+    /// e.g. by logging out or being inactive for too long.
+    #[serde(default)]
+    pub refresh_token_bound_to_session: bool,
+}
+
+impl Default for TokenLifetimes {
+    fn default() -> Self {
+        Self {
+            authorization_code_secs: Self::default_authorization_code_secs(),
+            access_token_secs: Self::default_access_token_secs(),
+            id_token_secs: Self::default_id_token_secs(),
+            refresh_token_days: Self::default_refresh_token_days(),
+            refresh_token_expiration: RefreshTokenExpiration::default(),
+            refresh_token_bound_to_session: false,
+        }
+    }
+}
+
+impl TokenLifetimes {
+    fn default_authorization_code_secs() -> u32 {
+        600
+    }
+
+    fn default_access_token_secs() -> u32 {
+        3600
+    }
+
+    fn default_id_token_secs() -> u32 {
+        3600
+    }
+
+    fn default_refresh_token_days() -> u32 {
+        30
+    }
+
+    /// The lifetimes for `app`, with its overrides applied.
+    pub fn for_application(&self, app: &Application) -> Self {
+        let overrides = &app.token_lifetimes;
+
+        Self {
+            authorization_code_secs: overrides
+                .authorization_code_secs
+                .unwrap_or(self.authorization_code_secs),
+            access_token_secs: overrides
+                .access_token_secs
+                .unwrap_or(self.access_token_secs),
+            id_token_secs: overrides.id_token_secs.unwrap_or(self.id_token_secs),
+            refresh_token_days: overrides
+                .refresh_token_days
+                .unwrap_or(self.refresh_token_days),
+            refresh_token_expiration: overrides
+                .refresh_token_expiration
+                .unwrap_or(self.refresh_token_expiration),
+            refresh_token_bound_to_session: overrides
+                .refresh_token_bound_to_session
+                .unwrap_or(self.refresh_token_bound_to_session),
+        }
+    }
+
+    pub fn authorization_code(&self) -> Duration {
+        Duration::seconds(self.authorization_code_secs.into())
+    }
+
+    pub fn id_token(&self) -> Duration {
+        Duration::seconds(self.id_token_secs.into())
+    }
+
+    /// When `token` expires, not counting the end of its SSO session.
+    pub fn refresh_token_expires_at(&self, token: &RefreshToken) -> DateTime<Utc> {
+        let start = match self.refresh_token_expiration {
+            RefreshTokenExpiration::Sliding => token.created_at,
+            RefreshTokenExpiration::Absolute => token.grant_created_at.unwrap_or(token.created_at),
+        };
+
+        start
+            .checked_add_signed(Duration::days(self.refresh_token_days.into()))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+
+    /// Whether `token` expired, or the SSO session it is bound to ended.
+    pub async fn is_refresh_token_expired(
+        &self,
+        database: &Database,
+        token: &RefreshToken,
+    ) -> Result<bool> {
+        if self.refresh_token_expires_at(token) < Utc::now() {
+            return Ok(true);
+        }
+
+        // Tokens from before sessions were recorded with them aren't bound to one
+        let Some(session_id) = token
+            .session_id
+            .as_ref()
+            .filter(|_| self.refresh_token_bound_to_session)
+        else {
+            return Ok(false);
+        };
+
+        let session = database
+            .collection::<SessionRecord>("sessions")
+            .find_one(doc! { "_id": session_id })
+            .await?;
+
+        Ok(session.is_none())
+    }
+}
+
+/// Per-application overrides of [`TokenLifetimes`]. The server setting applies to each one left unset.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Default, Validate)]
+pub struct TokenLifetimeOverrides {
+    #[validate(range(min = 1, max = MAX_AUTHORIZATION_CODE_SECS))]
+    #[serde(default)]
+    pub authorization_code_secs: Option<u32>,
+    #[validate(range(min = 1, max = MAX_TOKEN_SECS))]
+    #[serde(default)]
+    pub access_token_secs: Option<u32>,
+    #[validate(range(min = 1, max = MAX_TOKEN_SECS))]
+    #[serde(default)]
+    pub id_token_secs: Option<u32>,
+    #[validate(range(min = 1, max = MAX_REFRESH_TOKEN_DAYS))]
+    #[serde(default)]
+    pub refresh_token_days: Option<u32>,
+    #[serde(default)]
+    pub refresh_token_expiration: Option<RefreshTokenExpiration>,
+    #[serde(default)]
+    pub refresh_token_bound_to_session: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refresh_token(created_days_ago: i64, grant_created_days_ago: i64) -> RefreshToken {
+        RefreshToken {
+            token_hash: String::new(),
+            client_id: "app".to_string(),
+            user_id: String::new(),
+            scope: "offline_access".to_string(),
+            grant_id: None,
+            grant_created_at: Some(Utc::now() - Duration::days(grant_created_days_ago)),
+            session_id: None,
+            created_at: Utc::now() - Duration::days(created_days_ago),
+            revoked: false,
+            rotated: false,
+            dpop_jkt: None,
+        }
+    }
+
+    #[test]
+    fn refresh_tokens_expire_from_the_last_refresh_or_the_grant() {
+        let sliding = TokenLifetimes::default();
+        let absolute = TokenLifetimes {
+            refresh_token_expiration: RefreshTokenExpiration::Absolute,
+            ..TokenLifetimes::default()
+        };
+
+        // Rotated yesterday, but the grant started 40 days ago
+        let token = refresh_token(1, 40);
+        assert!(sliding.refresh_token_expires_at(&token) > Utc::now());
+        assert!(absolute.refresh_token_expires_at(&token) < Utc::now());
+
+        let token = refresh_token(1, 1);
+        assert!(absolute.refresh_token_expires_at(&token) > Utc::now());
+
+        // Out of range lifetimes never expire instead of overflowing
+        let unbounded = TokenLifetimes {
+            refresh_token_days: u32::MAX,
+            ..TokenLifetimes::default()
+        };
+        assert_eq!(
+            unbounded.refresh_token_expires_at(&token),
+            DateTime::<Utc>::MAX_UTC
+        );
+    }
+
+    #[test]
+    fn lifetimes_have_to_be_in_range() {
+        assert!(TokenLifetimes::default().validate().is_ok());
+        assert!(TokenLifetimeOverrides::default().validate().is_ok());
+
+        for overrides in [
+            TokenLifetimeOverrides {
+                access_token_secs: Some(0),
+                ..Default::default()
+            },
+            TokenLifetimeOverrides {
+                authorization_code_secs: Some(0),
+                ..Default::default()
+            },
+            TokenLifetimeOverrides {
+                refresh_token_days: Some(u32::MAX),
+                ..Default::default()
+            },
+        ] {
+            assert!(overrides.validate().is_err());
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use color_eyre::eyre::{Context, Result};
+use futures::TryStreamExt;
+use mongodb::bson::{self, doc, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+use tower_sessions::Session;
+use tracing::warn;
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    axum_error::AxumResult, database::Application, state::AppState, validators::is_https_url,
+};
+
+/// Event identifier for back-channel logout tokens.
+const BACKCHANNEL_LOGOUT_EVENT: &str = "http://schemas.openid.net/event/backchannel-logout";
+
+/// How many times a logout token is POSTed before giving up.
+const BACKCHANNEL_MAX_ATTEMPTS: u32 = 3;
+
+/// Lifetime of a logout token. The spec recommends at most two minutes.
+const LOGOUT_TOKEN_LIFETIME_SECS: usize = 120;
+
+/// Records that a client was issued tokens during an SSO session.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClientSession {
+    /// Session ID shared with relying parties (the `sid` claim).
+    pub sid: String,
+    /// ID of the tower-session the client was authorized from.
+    pub session_id: Option<String>,
+    pub user_id: ObjectId,
+    /// `sub` of the tokens issued to the client.
+    pub subject: String,
+    pub client_id: String,
+    pub created_at: bson::DateTime,
+}
+
+/// Log of a back-channel logout notification sent to a relying party.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BackchannelLogoutDelivery {
+    pub client_id: String,
+    pub uri: String,
+    pub sid: String,
+    pub subject: String,
+    pub attempts: u32,
+    pub delivered: bool,
+    pub last_error: Option<String>,
+    pub created_at: bson::DateTime,
+    pub updated_at: bson::DateTime,
+}
+
+/// Claims of a back-channel logout token (OpenID Connect Back-Channel Logout 1.0, section 2.4).
+#[derive(Debug, Serialize)]
+pub struct LogoutTokenClaims {
+    pub iss: String,
+    pub aud: String,
+    pub iat: usize,
+    pub exp: usize,
+    pub jti: String,
+    pub sub: String,
+    pub sid: String,
+    pub events: serde_json::Value,
+}
+
+/// Returns the `sid` of the current session, generating one on first use.
+pub async fn get_or_create_sid(session: &Session) -> AxumResult<String> {
+    if let Some(sid) = session.get::<String>("sid").await? {
+        return Ok(sid);
+    }
+
+    let sid = Uuid::new_v4().to_string();
+    session.insert("sid", &sid).await?;
+
+    Ok(sid)
+}
+
+/// Remembers that `client_id` holds tokens tied to the given SSO session.
+pub async fn record_client_session(state: &AppState, client_session: ClientSession) -> Result<()> {
+    state
+        .database
+        .collection::<ClientSession>("oidc_client_sessions")
+        .update_one(
+            doc! { "sid": &client_session.sid, "client_id": &client_session.client_id },
+            doc! { "$setOnInsert": bson::to_document(&client_session)? },
+        )
+        .upsert(true)
+        .await
+        .wrap_err("Failed to record client session")?;
+
+    Ok(())
+}
+
+/// Builds the front-channel logout URLs the frontend has to load in iframes
+/// when the given tower-session ends.
+pub async fn frontchannel_logout_urls(state: &AppState, session_id: &str) -> Result<Vec<String>> {
+    let client_sessions: Vec<ClientSession> = state
+        .database
+        .collection::<ClientSession>("oidc_client_sessions")
+        .find(doc! { "session_id": session_id })
+        .await?
+        .try_collect()
+        .await?;
+
+    let issuer = state.settings.general.public_url.to_string();
+    let issuer = issuer.trim_end_matches('/');
+
+    let mut urls = Vec::new();
+    for client_session in client_sessions {
+        let Some(app) = state
+            .database
+            .collection::<Application>("applications")
+            .find_one(doc! { "client_id": &client_session.client_id })
+            .await?
+        else {
+            continue;
+        };
+        let Some(uri) = app.frontchannel_logout_uri else {
+            continue;
+        };
+        // Loaded in the server's origin, so it can't be `javascript:` or the like
+        let Some(mut url) = Url::parse(&uri).ok().filter(|_| is_https_url(&uri)) else {
+            warn!(client_id = %app.client_id, "Invalid front-channel logout URI");
+            continue;
+        };
+
+        if app.frontchannel_logout_session_required {
+            url.query_pairs_mut()
+                .append_pair("iss", issuer)
+                .append_pair("sid", &client_session.sid);
+        }
+        urls.push(url.to_string());
+    }
+
+    Ok(urls)
+}
+
+/// Notifies relying parties that a tower-session has ended.
+pub fn notify_session_logout(state: &AppState, session_id: String) {
+    notify_logout(state.clone(), doc! { "session_id": session_id });
+}
+
+/// Notifies relying parties that every session of a user has ended, e.g. after account deletion.
+pub fn notify_user_logout(state: &AppState, user_id: ObjectId) {
+    notify_logout(state.clone(), doc! { "user_id": user_id });
+}
+
+fn notify_logout(state: AppState, filter: bson::Document) {
+    tokio::spawn(async move {
+        if let Err(error) = send_logout_notifications(&state, filter).await {
+            warn!(error = ?error, "Failed to send back-channel logout notifications");
+        }
+    });
+}
+
+async fn send_logout_notifications(state: &AppState, filter: bson::Document) -> Result<()> {
+    let collection = state
+        .database
+        .collection::<ClientSession>("oidc_client_sessions");
+
+    let client_sessions: Vec<ClientSession> =
+        collection.find(filter.clone()).await?.try_collect().await?;
+
+    collection.delete_many(filter).await?;
+
+    let mut deliveries = Vec::new();
+    for client_session in client_sessions {
+        let Some(app) = state
+            .database
+            .collection::<Application>("applications")
+            .find_one(doc! { "client_id": &client_session.client_id })
+            .await?
+        else {
+            continue;
+        };
+
+        match app.backchannel_logout_uri {
+            Some(uri) if is_https_url(&uri) => {
+                deliveries.push(deliver_logout_token(state, client_session, uri));
+            }
+            Some(_) => {
+                warn!(client_id = %app.client_id, "Back-channel logout URI doesn't use https");
+            }
+            None => {}
+        }
+    }
+
+    futures::future::join_all(deliveries).await;
+
+    Ok(())
+}
+
+/// POSTs a logout token to a relying party, retrying with exponential backoff.
+async fn deliver_logout_token(state: &AppState, client_session: ClientSession, uri: String) {
+    let deliveries = state
+        .database
+        .collection::<BackchannelLogoutDelivery>("backchannel_logout_deliveries");
+
+    let now = bson::DateTime::now();
+    let delivery_id = match deliveries
+        .insert_one(BackchannelLogoutDelivery {
+            client_id: client_session.client_id.clone(),
+            uri: uri.clone(),
+            sid: client_session.sid.clone(),
+            subject: client_session.subject.clone(),
+            attempts: 0,
+            delivered: false,
+            last_error: None,
+            created_at: now,
+            updated_at: now,
+        })
+        .await
+    {
+        Ok(result) => result.inserted_id,
+        Err(error) => {
+            warn!(error = ?error, "Failed to log back-channel logout delivery");
+            return;
+        }
+    };
+
+    for attempt in 1..=BACKCHANNEL_MAX_ATTEMPTS {
+        let result = post_logout_token(state, &client_session, &uri).await;
+
+        let mut update = match &result {
+            Ok(()) => doc! { "delivered": true, "last_error": null },
+            Err(error) => doc! { "last_error": error.to_string() },
+        };
+        update.insert("attempts", attempt);
+        update.insert("updated_at", bson::DateTime::now());
+
+        if let Err(error) = deliveries
+            .update_one(doc! { "_id": &delivery_id }, doc! { "$set": update })
+            .await
+        {
+            warn!(error = ?error, "Failed to update back-channel logout delivery log");
+        }
+
+        match result {
+            Ok(()) => return,
+            Err(error) if attempt == BACKCHANNEL_MAX_ATTEMPTS => {
+                warn!(
+                    error = ?error,
+                    client_id = %client_session.client_id,
+                    "Giving up on back-channel logout delivery"
+                );
+            }
+            Err(_) => tokio::time::sleep(Duration::from_secs(1 << attempt)).await,
+        }
+    }
+}
+
+async fn post_logout_token(
+    state: &AppState,
+    client_session: &ClientSession,
+    uri: &str,
+) -> Result<()> {
+    let issuer = state
+        .settings
+        .general
+        .public_url
+        .to_string()
+        .trim_end_matches('/')
+        .to_string();
+    let now = Utc::now().timestamp() as usize;
+
+    let claims = LogoutTokenClaims {
+        iss: issuer,
+        aud: client_session.client_id.clone(),
+        iat: now,
+        exp: now + LOGOUT_TOKEN_LIFETIME_SECS,
+        jti: Uuid::new_v4().to_string(),
+        sub: client_session.subject.clone(),
+        sid: client_session.sid.clone(),
+        events: serde_json::json!({ BACKCHANNEL_LOGOUT_EVENT: {} }),
+    };
+    let logout_token = state.oidc_keys.sign_logout_token(&claims)?;
+
+    state
+        .http_client
+        .post(uri)
+        .form(&[("logout_token", logout_token)])
+        .send()
+        .await
+        .wrap_err("Failed to send logout token")?
+        .error_for_status()
+        .wrap_err("Relying party rejected logout token")?;
+
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use color_eyre::eyre::{Context, Result};
+use mongodb::{
+    Database,
+    bson::{self, doc},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{generate_reset_token, hash_token};
+
+/// Prefix of the `request_uri` returned for a pushed authorization request (RFC 9126, section 2.2).
+const REQUEST_URI_PREFIX: &str = "urn:ietf:params:oauth:request_uri:";
+
+/// Whether `request_uri` was returned by [`store_pushed_request`], as opposed to a request object URL of the client.
+pub fn is_pushed_request_uri(request_uri: &str) -> bool {
+    request_uri.starts_with(REQUEST_URI_PREFIX)
+}
+
+/// How long a `request_uri` can be used, in seconds. It's loaded again after the user signs in,
+/// so it has to outlive the login.
+pub const PUSHED_REQUEST_LIFETIME_SECS: i64 = 600;
+
+/// Authorization request pushed by a client, stored in MongoDB.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PushedAuthorizationRequest {
+    pub request_uri_hash: String,
+    pub client_id: String,
+    /// Authorization parameters, form-encoded like the query string of an authorization request.
+    pub parameters: String,
+    pub expires_at: bson::DateTime,
+}
+
+/// Stores the parameters and returns the `request_uri` standing for them.
+pub async fn store_pushed_request(
+    database: &Database,
+    client_id: &str,
+    parameters: String,
+) -> Result<String> {
+    let request_uri = format!("{REQUEST_URI_PREFIX}{}", generate_reset_token());
+
+    let request = PushedAuthorizationRequest {
+        request_uri_hash: hash_token(&request_uri),
+        client_id: client_id.to_string(),
+        parameters,
+        expires_at: bson::DateTime::from_millis(
+            bson::DateTime::now().timestamp_millis() + PUSHED_REQUEST_LIFETIME_SECS * 1000,
+        ),
+    };
+
+    database
+        .collection::<PushedAuthorizationRequest>("pushed_authorization_requests")
+        .insert_one(request)
+        .await
+        .wrap_err("Failed to store pushed authorization request")?;
+
+    Ok(request_uri)
+}
+
+/// Finds an unexpired request pushed by `client_id`.
+pub async fn find_pushed_request(
+    database: &Database,
+    request_uri: &str,
+    client_id: &str,
+) -> Result<Option<PushedAuthorizationRequest>> {
+    database
+        .collection::<PushedAuthorizationRequest>("pushed_authorization_requests")
+        .find_one(doc! {
+            "request_uri_hash": hash_token(request_uri),
+            "client_id": client_id,
+            "expires_at": { "$gt": bson::DateTime::now() },
+        })
+        .await
+        .wrap_err("Failed to fetch pushed authorization request")
+}
+
+/// Removes a request once a code was issued for it, so the `request_uri` can't be used again.
+pub async fn consume_pushed_request(database: &Database, request_uri: &str) -> Result<()> {
+    database
+        .collection::<PushedAuthorizationRequest>("pushed_authorization_requests")
+        .delete_one(doc! { "request_uri_hash": hash_token(request_uri) })
+        .await
+        .wrap_err("Failed to remove pushed authorization request")?;
+
+    Ok(())
+}
@@ -0,0 +1,321 @@
+use jsonwebtoken::jwk::JwkSet;
+use mongodb::bson;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    database::{Application, ClientType},
+    validators::{is_https_url, is_valid_redirect_uri},
+};
+
+use super::{
+    client_auth::TokenEndpointAuthMethod,
+    device::DEVICE_CODE_GRANT_TYPE,
+    subject::{SubjectType, validate_sector},
+};
+
+/// Initial access token an administrator hands out to let a client register itself (RFC 7591, section 3).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InitialAccessToken {
+    pub id: String,
+    pub token_hash: String,
+    pub description: Option<String>,
+    pub created_at: bson::DateTime,
+    pub expires_at: Option<bson::DateTime>,
+}
+
+/// Client metadata accepted and returned by the registration endpoint (RFC 7591, section 2).
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Default)]
+pub struct ClientMetadata {
+    /// `https`, `http` on a loopback host, or a private-use scheme like `com.example.app:`.
+    #[serde(default)]
+    pub redirect_uris: Vec<String>,
+    pub client_name: Option<String>,
+    /// Has to use `https`.
+    pub logo_uri: Option<String>,
+    #[serde(default)]
+    pub grant_types: Vec<String>,
+    /// `none` registers a public client, any other method a confidential one.
+    /// `private_key_jwt` requires `jwks` or `jwks_uri`.
+    pub token_endpoint_auth_method: Option<String>,
+    #[serde(default)]
+    pub post_logout_redirect_uris: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub jwks: Option<serde_json::Value>,
+    /// Has to use `https`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jwks_uri: Option<String>,
+    /// Have to use `https`.
+    #[serde(default)]
+    pub request_uris: Vec<String>,
+    /// `public` or `pairwise`.
+    pub subject_type: Option<String>,
+    /// Has to use `https`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sector_identifier_uri: Option<String>,
+}
+
+/// Error returned by the registration endpoint (RFC 7591, section 3.2.2).
+#[derive(Debug)]
+pub struct MetadataError {
+    pub error: &'static str,
+    pub description: String,
+}
+
+impl MetadataError {
+    fn client_metadata(description: impl Into<String>) -> Self {
+        Self {
+            error: "invalid_client_metadata",
+            description: description.into(),
+        }
+    }
+
+    fn redirect_uri(description: impl Into<String>) -> Self {
+        Self {
+            error: "invalid_redirect_uri",
+            description: description.into(),
+        }
+    }
+}
+
+/// Registered metadata translated to [`Application`] settings.
+#[derive(Debug)]
+pub struct ClientSettings {
+    pub name: String,
+    pub icon: Option<String>,
+    pub client_type: ClientType,
+    pub token_endpoint_auth_method: TokenEndpointAuthMethod,
+    pub redirect_uris: Vec<String>,
+    pub post_logout_redirect_uris: Vec<String>,
+    pub allow_client_credentials: bool,
+    pub allow_device_authorization: bool,
+    pub jwks: Option<serde_json::Value>,
+    pub jwks_uri: Option<String>,
+    pub request_uris: Vec<String>,
+    pub subject_type: SubjectType,
+    pub sector_identifier_uri: Option<String>,
+}
+
+const AUTHORIZATION_CODE_GRANT_TYPE: &str = "authorization_code";
+const REFRESH_TOKEN_GRANT_TYPE: &str = "refresh_token";
+const CLIENT_CREDENTIALS_GRANT_TYPE: &str = "client_credentials";
+
+impl ClientMetadata {
+    /// Validates the metadata and fills in the defaults from RFC 7591.
+    pub fn to_settings(&self, client_id: &str) -> Result<ClientSettings, MetadataError> {
+        let token_endpoint_auth_method = match self.token_endpoint_auth_method.as_deref() {
+            None => TokenEndpointAuthMethod::ClientSecretBasic,
+            Some(method) => TokenEndpointAuthMethod::parse(method).ok_or_else(|| {
+                MetadataError::client_metadata(format!(
+                    "Unsupported token_endpoint_auth_method: {method}"
+                ))
+            })?,
+        };
+        let client_type = match token_endpoint_auth_method {
+            TokenEndpointAuthMethod::None => ClientType::Public,
+            _ => ClientType::Confidential,
+        };
+
+        let grant_types = if self.grant_types.is_empty() {
+            vec![AUTHORIZATION_CODE_GRANT_TYPE.to_string()]
+        } else {
+            self.grant_types.clone()
+        };
+
+        let mut allow_client_credentials = false;
+        let mut allow_device_authorization = false;
+        let mut uses_redirects = false;
+
+        for grant_type in &grant_types {
+            match grant_type.as_str() {
+                AUTHORIZATION_CODE_GRANT_TYPE => uses_redirects = true,
+                REFRESH_TOKEN_GRANT_TYPE => {}
+                CLIENT_CREDENTIALS_GRANT_TYPE => allow_client_credentials = true,
+                DEVICE_CODE_GRANT_TYPE => allow_device_authorization = true,
+                other => {
+                    return Err(MetadataError::client_metadata(format!(
+                        "Unsupported grant type: {other}"
+                    )));
+                }
+            }
+        }
+
+        if allow_client_credentials && matches!(client_type, ClientType::Public) {
+            return Err(MetadataError::client_metadata(
+                "The client_credentials grant requires client authentication",
+            ));
+        }
+
+        if uses_redirects && self.redirect_uris.is_empty() {
+            return Err(MetadataError::redirect_uri(
+                "redirect_uris is required for the authorization_code grant",
+            ));
+        }
+
+        if let Some(uri) = self
+            .redirect_uris
+            .iter()
+            .find(|uri| !is_valid_redirect_uri(uri))
+        {
+            return Err(MetadataError::redirect_uri(format!(
+                "Invalid redirect URI: {uri}"
+            )));
+        }
+
+        if let Some(uri) = self
+            .post_logout_redirect_uris
+            .iter()
+            .find(|uri| !is_valid_redirect_uri(uri))
+        {
+            return Err(MetadataError::redirect_uri(format!(
+                "Invalid post-logout redirect URI: {uri}"
+            )));
+        }
+
+        if self.jwks.is_some() && self.jwks_uri.is_some() {
+            return Err(MetadataError::client_metadata(
+                "jwks and jwks_uri can't be used together",
+            ));
+        }
+
+        if let Some(jwks) = &self.jwks
+            && serde_json::from_value::<JwkSet>(jwks.clone()).is_err()
+        {
+            return Err(MetadataError::client_metadata("Invalid jwks"));
+        }
+
+        if let Some(jwks_uri) = &self.jwks_uri
+            && !is_https_url(jwks_uri)
+        {
+            return Err(MetadataError::client_metadata("jwks_uri must use https"));
+        }
+
+        if token_endpoint_auth_method == TokenEndpointAuthMethod::PrivateKeyJwt
+            && self.jwks.is_none()
+            && self.jwks_uri.is_none()
+        {
+            return Err(MetadataError::client_metadata(
+                "private_key_jwt requires jwks or jwks_uri",
+            ));
+        }
+
+        if let Some(uri) = self.request_uris.iter().find(|uri| !is_https_url(uri)) {
+            return Err(MetadataError::client_metadata(format!(
+                "Request URIs must use https: {uri}"
+            )));
+        }
+
+        let subject_type = match self.subject_type.as_deref() {
+            None => SubjectType::Public,
+            Some(subject_type) => SubjectType::parse(subject_type).ok_or_else(|| {
+                MetadataError::client_metadata(format!("Unsupported subject_type: {subject_type}"))
+            })?,
+        };
+
+        validate_sector(
+            subject_type,
+            self.sector_identifier_uri.as_deref(),
+            &self.redirect_uris,
+        )
+        .map_err(|error| MetadataError::client_metadata(error.to_string()))?;
+
+        // Shown to users as the application icon, so it can't be `javascript:` or `data:`
+        if let Some(logo_uri) = &self.logo_uri
+            && !is_https_url(logo_uri)
+        {
+            return Err(MetadataError::client_metadata("logo_uri must use https"));
+        }
+
+        let name = match self.client_name.as_deref().map(str::trim) {
+            Some(name) if name.chars().count() > 32 => {
+                return Err(MetadataError::client_metadata(
+                    "client_name must be at most 32 characters",
+                ));
+            }
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => client_id.to_string(),
+        };
+
+        Ok(ClientSettings {
+            name,
+            icon: self.logo_uri.clone(),
+            client_type,
+            token_endpoint_auth_method,
+            redirect_uris: self.redirect_uris.clone(),
+            post_logout_redirect_uris: self.post_logout_redirect_uris.clone(),
+            allow_client_credentials,
+            allow_device_authorization,
+            jwks: self.jwks.clone(),
+            jwks_uri: self.jwks_uri.clone(),
+            request_uris: self.request_uris.clone(),
+            subject_type,
+            sector_identifier_uri: self.sector_identifier_uri.clone(),
+        })
+    }
+
+    /// Describes an application in registration metadata terms.
+    pub fn from_application(app: &Application) -> Self {
+        let mut grant_types = Vec::new();
+        if !app.redirect_uris.is_empty() {
+            grant_types.push(AUTHORIZATION_CODE_GRANT_TYPE.to_string());
+            grant_types.push(REFRESH_TOKEN_GRANT_TYPE.to_string());
+        }
+        if app.allow_client_credentials {
+            grant_types.push(CLIENT_CREDENTIALS_GRANT_TYPE.to_string());
+        }
+        if app.allow_device_authorization {
+            grant_types.push(DEVICE_CODE_GRANT_TYPE.to_string());
+        }
+
+        let token_endpoint_auth_method =
+            app.token_endpoint_auth_method
+                .unwrap_or(match app.client_type {
+                    ClientType::Public => TokenEndpointAuthMethod::None,
+                    ClientType::Confidential => TokenEndpointAuthMethod::ClientSecretBasic,
+                });
+
+        Self {
+            redirect_uris: app.redirect_uris.clone(),
+            client_name: Some(app.name.clone()),
+            logo_uri: app.icon.clone(),
+            grant_types,
+            token_endpoint_auth_method: Some(token_endpoint_auth_method.as_str().to_string()),
+            post_logout_redirect_uris: app.post_logout_redirect_uris.clone(),
+            jwks: app.jwks.clone(),
+            jwks_uri: app.jwks_uri.clone(),
+            request_uris: app.request_uris.clone(),
+            subject_type: Some(app.subject_type.as_str().to_string()),
+            sector_identifier_uri: app.sector_identifier_uri.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirect_uris_use_https_loopback_or_private_use_schemes() {
+        for uri in [
+            "https://app.example.com/callback",
+            "http://localhost:8080/callback",
+            "http://127.0.0.1/callback",
+            "http://[::1]:3000/callback",
+            "com.example.app:/callback",
+        ] {
+            assert!(is_valid_redirect_uri(uri), "{uri}");
+        }
+
+        for uri in [
+            "http://app.example.com/callback",
+            "https://app.example.com/callback#fragment",
+            "javascript:alert(1)",
+            "data:text/html,<script>alert(1)</script>",
+            "file:///etc/passwd",
+            "myapp:/callback",
+        ] {
+            assert!(!is_valid_redirect_uri(uri), "{uri}");
+        }
+    }
+}
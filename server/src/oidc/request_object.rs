@@ -0,0 +1,131 @@
+use color_eyre::eyre::{Context, Result, bail};
+use serde_json::{Map, Value};
+
+use crate::{database::Application, state::AppState, validators::is_https_url};
+
+use super::client_keys::{client_jwks, verify_client_jwt};
+
+/// JWT claims of a request object that aren't authorization parameters.
+const JWT_CLAIMS: &[&str] = &["iss", "aud", "exp", "iat", "nbf", "jti"];
+
+/// Verifies a request object (RFC 9101) signed by the application and returns its claims.
+pub async fn verify_request_object(
+    state: &AppState,
+    app: &Application,
+    issuer: &str,
+    request_object: &str,
+) -> Result<Map<String, Value>> {
+    let jwks = client_jwks(state, app).await?;
+    let claims: Map<String, Value> =
+        verify_client_jwt(&jwks, request_object, &app.client_id, &[issuer])?;
+
+    if claims
+        .get("client_id")
+        .is_some_and(|client_id| client_id.as_str() != Some(app.client_id.as_str()))
+    {
+        bail!("client_id of the request object doesn't match the client");
+    }
+
+    Ok(claims)
+}
+
+/// Fetches a request object hosted by the client. Only URLs registered in `request_uris` are fetched.
+pub async fn fetch_request_object(
+    state: &AppState,
+    app: &Application,
+    request_uri: &str,
+) -> Result<String> {
+    if !app.request_uris.iter().any(|uri| uri == request_uri) {
+        bail!("request_uri isn't registered for the application");
+    }
+    if !is_https_url(request_uri) {
+        bail!("request_uri doesn't use https");
+    }
+
+    state
+        .http_client
+        .get(request_uri)
+        .send()
+        .await
+        .wrap_err("Failed to fetch request object")?
+        .error_for_status()
+        .wrap_err("Failed to fetch request object")?
+        .text()
+        .await
+        .wrap_err("Failed to read request object")
+        .map(|body| body.trim().to_string())
+}
+
+/// Replaces authorization parameters with the claims of a request object. Only `client_id` is
+/// taken from outside of it, as unsigned parameters could undo what the client signed
+/// (RFC 9101, section 5). `request`/`request_uri` are dropped as they've been used up.
+pub fn merge_request_object(
+    parameters: Vec<(String, String)>,
+    claims: Map<String, Value>,
+) -> Vec<(String, String)> {
+    let mut merged = parameters
+        .into_iter()
+        .filter(|(name, _)| name == "client_id")
+        .collect::<Vec<_>>();
+
+    for (name, value) in claims {
+        if JWT_CLAIMS.contains(&name.as_str())
+            || ["client_id", "request", "request_uri"].contains(&name.as_str())
+        {
+            continue;
+        }
+
+        let value = match value {
+            Value::String(value) => value,
+            Value::Null => continue,
+            value => value.to_string(),
+        };
+        merged.push((name, value));
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn request_object_claims_replace_parameters() {
+        let parameters = vec![
+            ("client_id".to_string(), "app".to_string()),
+            ("scope".to_string(), "openid".to_string()),
+            ("code_challenge".to_string(), "unsigned".to_string()),
+            ("state".to_string(), "from-query".to_string()),
+            ("request".to_string(), "eyJ...".to_string()),
+        ];
+        let claims = json!({
+            "iss": "app",
+            "aud": "https://auth.example.com",
+            "exp": 1_900_000_000,
+            "client_id": "app",
+            "scope": "openid profile",
+            "state": "from-request",
+            "max_age": 300,
+        });
+        let Value::Object(claims) = claims else {
+            unreachable!()
+        };
+
+        let mut merged = merge_request_object(parameters, claims);
+        merged.sort();
+
+        assert_eq!(
+            merged,
+            [
+                ("client_id", "app"),
+                ("max_age", "300"),
+                ("scope", "openid profile"),
+                ("state", "from-request"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use chrono::Utc;
+use color_eyre::eyre::{self, Context, Result};
+use fred::prelude::{Expiration, KeysInterface, Pool};
+use mongodb::{
+    Database,
+    bson::{doc, oid::ObjectId},
+};
+use tracing::warn;
+
+use crate::{
+    database::{Application, get_user_by_id},
+    oidc::RefreshToken,
+    state::AppState,
+};
+
+/// Redis key prefix for revoked access token IDs.
+const REVOKED_ACCESS_TOKEN_PREFIX: &str = "oidc:revoked_access_token:";
+
+/// Denylists an access token until the point where it would have expired anyway.
+pub async fn revoke_access_token(pool: &Pool, jti: &str, exp: usize) -> Result<()> {
+    let ttl = exp as i64 - Utc::now().timestamp();
+    if jti.is_empty() || ttl <= 0 {
+        return Ok(());
+    }
+
+    pool.set::<(), _, _>(
+        format!("{REVOKED_ACCESS_TOKEN_PREFIX}{jti}"),
+        1,
+        Some(Expiration::EX(ttl)),
+        None,
+        false,
+    )
+    .await
+    .map_err(|e| eyre::eyre!("Failed to revoke access token: {e}"))
+}
+
+pub async fn is_access_token_revoked(pool: &Pool, jti: &str) -> Result<bool> {
+    if jti.is_empty() {
+        return Ok(false);
+    }
+
+    let exists: i64 = pool
+        .exists(format!("{REVOKED_ACCESS_TOKEN_PREFIX}{jti}"))
+        .await
+        .map_err(|e| eyre::eyre!("Failed to check access token revocation: {e}"))?;
+
+    Ok(exists > 0)
+}
+
+/// Revokes a refresh token together with every token rotated from the same grant.
+pub async fn revoke_refresh_token_chain(database: &Database, token: &RefreshToken) -> Result<()> {
+    let filter = match &token.grant_id {
+        Some(grant_id) => doc! { "grant_id": grant_id },
+        None => doc! { "token_hash": &token.token_hash },
+    };
+
+    database
+        .collection::<RefreshToken>("refresh_tokens")
+        .update_many(filter, doc! { "$set": { "revoked": true } })
+        .await
+        .wrap_err("Failed to revoke refresh tokens")?;
+
+    Ok(())
+}
+
+/// Handles a rotated refresh token being presented again (RFC 9700, section 4.14.2).
+///
+/// Either the legitimate client or an attacker holds a stolen copy, and there's no telling which,
+/// so every token of the grant is revoked and the user has to sign in again.
+pub async fn handle_refresh_token_reuse(state: &AppState, token: &RefreshToken) -> Result<()> {
+    revoke_refresh_token_chain(&state.database, token).await?;
+
+    warn!(
+        event = "refresh_token_reuse",
+        user_id = %token.user_id,
+        client_id = %token.client_id,
+        grant_id = ?token.grant_id,
+        "Rotated refresh token was reused, revoked the whole grant"
+    );
+
+    let Some(mail) = &state.mail_service else {
+        return Ok(());
+    };
+
+    let user_id = ObjectId::parse_str(&token.user_id).wrap_err("Invalid user_id")?;
+    let Some(user) = get_user_by_id(&state.database, &user_id).await? else {
+        return Ok(());
+    };
+
+    let app_name = state
+        .database
+        .collection::<Application>("applications")
+        .find_one(doc! { "client_id": &token.client_id })
+        .await?
+        .map_or_else(|| token.client_id.clone(), |app| app.name);
+
+    let mail = mail.clone();
+    tokio::spawn(async move {
+        if let Err(e) = mail.send_refresh_token_reuse(&user.email, &app_name).await {
+            warn!(error = ?e, "Failed to send refresh token reuse notification");
+        }
+    });
+
+    Ok(())
+}
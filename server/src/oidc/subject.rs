@@ -0,0 +1,269 @@
+use std::sync::Arc;
+
+use color_eyre::eyre::{Context, Result, bail};
+use mongodb::{
+    Database,
+    bson::{self, doc, oid::ObjectId},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
+use utoipa::ToSchema;
+
+use crate::{
+    database::{Application, User, get_user_by_id, get_user_by_uuid},
+    state::AppState,
+    utils::generate_reset_token,
+    validators::is_https_url,
+};
+
+/// Which `sub` an application sees for a user (OpenID Connect Core, section 8).
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SubjectType {
+    /// The user's UUID, the same for every application.
+    #[default]
+    Public,
+    /// Derived from the application's sector, so applications of different sectors can't
+    /// correlate their users.
+    Pairwise,
+}
+
+impl SubjectType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SubjectType::Public => "public",
+            SubjectType::Pairwise => "pairwise",
+        }
+    }
+
+    pub fn parse(subject_type: &str) -> Option<Self> {
+        [SubjectType::Public, SubjectType::Pairwise]
+            .into_iter()
+            .find(|t| t.as_str() == subject_type)
+    }
+}
+
+/// Pairwise subject handed out to a sector, so tokens carrying it can be traced back to the user.
+#[derive(Debug, Serialize, Deserialize)]
+struct PairwiseSubject {
+    subject: String,
+    sector_identifier: String,
+    user_id: ObjectId,
+    created_at: bson::DateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredSalt {
+    #[serde(rename = "_id")]
+    id: String,
+    salt: String,
+}
+
+const SALT_ID: &str = "pairwise_subject";
+
+/// Load the salt of pairwise subjects, creating it on first start.
+/// Every instance has to use the same one, or subjects would change between them.
+pub async fn init_pairwise_salt(database: &Database) -> Result<Arc<str>> {
+    let collection = database.collection::<StoredSalt>("oidc_salts");
+
+    collection
+        .update_one(
+            doc! { "_id": SALT_ID },
+            doc! { "$setOnInsert": { "salt": generate_reset_token() } },
+        )
+        .upsert(true)
+        .await
+        .wrap_err("Failed to store pairwise subject salt")?;
+
+    let stored = collection
+        .find_one(doc! { "_id": SALT_ID })
+        .await?
+        .ok_or_else(|| color_eyre::eyre::eyre!("Pairwise subject salt is missing"))?;
+
+    Ok(stored.salt.into())
+}
+
+fn uri_host(uri: &str) -> Option<String> {
+    Url::parse(uri).ok()?.host_str().map(str::to_string)
+}
+
+/// Host the application's pairwise subjects are derived from: the host of its
+/// `sector_identifier_uri`, or of its redirect URIs.
+pub fn sector_identifier(app: &Application) -> String {
+    if let Some(uri) = &app.sector_identifier_uri {
+        return uri_host(uri).unwrap_or_else(|| uri.clone());
+    }
+
+    app.redirect_uris
+        .iter()
+        .find_map(|uri| uri_host(uri))
+        .unwrap_or_else(|| app.client_id.clone())
+}
+
+/// `sub` the application sees for the user.
+pub fn subject_identifier(salt: &str, app: &Application, user: &User) -> String {
+    match app.subject_type {
+        SubjectType::Public => user.uuid.to_string(),
+        SubjectType::Pairwise => pairwise_subject(salt, &sector_identifier(app), &user.uuid),
+    }
+}
+
+/// Like [`subject_identifier`], remembering pairwise subjects for [`find_subject_user`].
+pub async fn issue_subject_identifier(
+    state: &AppState,
+    app: &Application,
+    user: &User,
+) -> Result<String> {
+    let subject = subject_identifier(&state.pairwise_salt, app, user);
+    if app.subject_type == SubjectType::Public {
+        return Ok(subject);
+    }
+
+    let pairwise = PairwiseSubject {
+        subject: subject.clone(),
+        sector_identifier: sector_identifier(app),
+        user_id: user.id,
+        created_at: bson::DateTime::now(),
+    };
+
+    state
+        .database
+        .collection::<PairwiseSubject>("pairwise_subjects")
+        .update_one(
+            doc! { "subject": &subject },
+            doc! { "$setOnInsert": bson::to_document(&pairwise)? },
+        )
+        .upsert(true)
+        .await
+        .wrap_err("Failed to store pairwise subject")?;
+
+    Ok(subject)
+}
+
+/// The user a `sub` from a token stands for, public or pairwise.
+pub async fn find_subject_user(database: &Database, subject: &str) -> Result<Option<User>> {
+    if let Ok(uuid) = uuid::Uuid::parse_str(subject) {
+        return Ok(get_user_by_uuid(database, &uuid).await?);
+    }
+
+    let Some(pairwise) = database
+        .collection::<PairwiseSubject>("pairwise_subjects")
+        .find_one(doc! { "subject": subject })
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    Ok(get_user_by_id(database, &pairwise.user_id).await?)
+}
+
+/// Checks that the sector of a pairwise application is unambiguous: its redirect URIs have to
+/// share a host, unless a `sector_identifier_uri` lists them.
+pub fn validate_sector(
+    subject_type: SubjectType,
+    sector_identifier_uri: Option<&str>,
+    redirect_uris: &[String],
+) -> Result<()> {
+    if let Some(uri) = sector_identifier_uri {
+        if !is_https_url(uri) {
+            bail!("sector_identifier_uri must use https");
+        }
+        return Ok(());
+    }
+
+    if subject_type == SubjectType::Public {
+        return Ok(());
+    }
+
+    let mut hosts = redirect_uris.iter().map(|uri| uri_host(uri));
+
+    match hosts.next() {
+        None => bail!("Pairwise subjects require redirect_uris or a sector_identifier_uri"),
+        Some(first) if first.is_some() && hosts.all(|host| host == first) => Ok(()),
+        Some(_) => bail!("Redirect URIs on several hosts require a sector_identifier_uri"),
+    }
+}
+
+/// Fetches the JSON array of redirect URIs at `sector_identifier_uri` and checks that it lists
+/// every one of `redirect_uris`.
+pub async fn verify_sector_identifier_uri(
+    state: &AppState,
+    sector_identifier_uri: &str,
+    redirect_uris: &[String],
+) -> Result<()> {
+    if !is_https_url(sector_identifier_uri) {
+        bail!("sector_identifier_uri must use https");
+    }
+
+    let body = state
+        .http_client
+        .get(sector_identifier_uri)
+        .send()
+        .await
+        .wrap_err("Failed to fetch sector_identifier_uri")?
+        .error_for_status()
+        .wrap_err("Failed to fetch sector_identifier_uri")?
+        .text()
+        .await
+        .wrap_err("Failed to read sector_identifier_uri")?;
+
+    let listed: Vec<String> =
+        serde_json::from_str(&body).wrap_err("sector_identifier_uri isn't a JSON array of URIs")?;
+
+    if let Some(uri) = redirect_uris.iter().find(|uri| !listed.contains(uri)) {
+        bail!("Redirect URI {uri} isn't listed at the sector_identifier_uri");
+    }
+
+    Ok(())
+}
+
+/// `sub` = SHA-256 of the sector identifier, the local account ID and a salt
+/// (OpenID Connect Core, section 8.1).
+fn pairwise_subject(salt: &str, sector_identifier: &str, user_uuid: &uuid::Uuid) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(sector_identifier.as_bytes());
+    hasher.update(user_uuid.to_string().as_bytes());
+    hasher.update(salt.as_bytes());
+
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairwise_subjects_differ_per_sector() {
+        let user = uuid::Uuid::new_v4();
+
+        let shop = pairwise_subject("salt", "shop.example.com", &user);
+        assert_eq!(shop, pairwise_subject("salt", "shop.example.com", &user));
+        assert_ne!(shop, pairwise_subject("salt", "blog.example.com", &user));
+        assert_ne!(shop, pairwise_subject("other", "shop.example.com", &user));
+        assert!(uuid::Uuid::parse_str(&shop).is_err());
+
+        let same_host = [
+            "https://shop.example.com/callback".to_string(),
+            "https://shop.example.com/other".to_string(),
+        ];
+        let other_host = ["https://blog.example.com/callback".to_string()];
+        let mixed = [same_host[0].clone(), other_host[0].clone()];
+
+        assert!(validate_sector(SubjectType::Pairwise, None, &same_host).is_ok());
+        assert!(validate_sector(SubjectType::Pairwise, None, &mixed).is_err());
+        assert!(validate_sector(SubjectType::Pairwise, None, &[]).is_err());
+        assert!(validate_sector(SubjectType::Public, None, &mixed).is_ok());
+        assert!(
+            validate_sector(SubjectType::Pairwise, Some("file:///etc/passwd"), &mixed).is_err()
+        );
+        assert!(
+            validate_sector(
+                SubjectType::Pairwise,
+                Some("https://example.com/sector.json"),
+                &mixed
+            )
+            .is_ok()
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+pub const TOKEN_EXCHANGE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+
+/// Token type identifiers (RFC 8693, section 3).
+pub const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+pub const ID_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:id_token";
+
+/// Lets an application exchange user tokens for tokens aimed at `audience` (RFC 8693).
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone)]
+pub struct TokenExchangePolicy {
+    /// Audience of the issued tokens, e.g. the client ID of a backend service.
+    pub audience: String,
+    /// Scopes the issued tokens may carry. Any scope of the exchanged token when empty.
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// Other clients whose tokens may be exchanged. Tokens issued to the application itself always can.
+    #[serde(default)]
+    pub subject_clients: Vec<String>,
+    /// Whether an `actor_token` may be passed to act on behalf of the user (delegation).
+    /// Without one, the issued token stands for the user alone (impersonation).
+    #[serde(default)]
+    pub allow_delegation: bool,
+}
+
+impl TokenExchangePolicy {
+    /// Whether a token issued to `client_id` may be exchanged under this policy by `requester`.
+    pub fn accepts_subject_client(&self, requester: &str, client_id: &str) -> bool {
+        client_id == requester || self.subject_clients.iter().any(|c| c == client_id)
+    }
+}
+
+/// `act` claim (RFC 8693, section 4.1): who acts on behalf of the subject.
+/// Earlier actors in a delegation chain are nested inside.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
+pub struct ActorClaim {
+    pub sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(no_recursion)]
+    pub act: Option<Box<ActorClaim>>,
+}
+
+/// Picks the policy for the requested `audience`. Without one, only an application with a single
+/// policy gets a default.
+pub fn find_policy<'a>(
+    policies: &'a [TokenExchangePolicy],
+    audience: Option<&str>,
+) -> Option<&'a TokenExchangePolicy> {
+    match audience {
+        Some(audience) => policies.iter().find(|policy| policy.audience == audience),
+        None if policies.len() == 1 => policies.first(),
+        None => None,
+    }
+}
+
+/// Scope of the issued token: the requested scopes, or all `available` ones, limited to what the
+/// policy allows. Returns the first scope that isn't allowed as the error.
+pub fn narrow_scope(
+    requested: Option<&str>,
+    available: &[&str],
+    policy: &TokenExchangePolicy,
+) -> Result<String, String> {
+    let allowed = |scope: &str| {
+        available.contains(&scope)
+            && (policy.scopes.is_empty() || policy.scopes.iter().any(|s| s == scope))
+    };
+
+    match requested {
+        Some(requested) => {
+            if let Some(scope) = requested.split_whitespace().find(|scope| !allowed(scope)) {
+                return Err(scope.to_string());
+            }
+            Ok(requested.split_whitespace().collect::<Vec<_>>().join(" "))
+        }
+        None => Ok(available
+            .iter()
+            .copied()
+            .filter(|scope| allowed(scope))
+            .collect::<Vec<_>>()
+            .join(" ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(audience: &str, scopes: &[&str]) -> TokenExchangePolicy {
+        TokenExchangePolicy {
+            audience: audience.to_string(),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+            subject_clients: vec!["spa".to_string()],
+            allow_delegation: false,
+        }
+    }
+
+    #[test]
+    fn exchanged_scopes_can_only_narrow() {
+        let orders = policy("orders", &["orders:read", "orders:write"]);
+        let available = ["openid", "orders:read", "orders:write"];
+
+        assert_eq!(
+            narrow_scope(None, &available, &orders).unwrap(),
+            "orders:read orders:write"
+        );
+        assert_eq!(
+            narrow_scope(Some("orders:read"), &available, &orders).unwrap(),
+            "orders:read"
+        );
+        assert_eq!(
+            narrow_scope(Some("orders:read openid"), &available, &orders),
+            Err("openid".to_string())
+        );
+        assert_eq!(
+            narrow_scope(Some("orders:read"), &["openid"], &orders),
+            Err("orders:read".to_string())
+        );
+    }
+
+    #[test]
+    fn policies_are_picked_by_audience() {
+        let policies = [policy("orders", &[]), policy("billing", &[])];
+
+        assert_eq!(
+            find_policy(&policies, Some("billing")).map(|p| p.audience.as_str()),
+            Some("billing")
+        );
+        assert!(find_policy(&policies, Some("unknown")).is_none());
+        assert!(find_policy(&policies, None).is_none());
+        assert!(find_policy(&policies[..1], None).is_some());
+
+        assert!(policies[0].accepts_subject_client("gateway", "gateway"));
+        assert!(policies[0].accepts_subject_client("gateway", "spa"));
+        assert!(!policies[0].accepts_subject_client("gateway", "other"));
+    }
+}
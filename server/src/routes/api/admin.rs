@@ -3,9 +3,16 @@ use utoipa_axum::router::OpenApiRouter;
 use crate::state::AppState;
 
 pub mod applications;
+pub mod groups;
+pub mod initial_access_tokens;
+pub mod signing_keys;
 
 pub fn routes() -> OpenApiRouter<AppState> {
-    OpenApiRouter::new().nest("/applications", applications::routes())
+    OpenApiRouter::new()
+        .nest("/applications", applications::routes())
+        .nest("/groups", groups::routes())
+        .nest("/initial-access-tokens", initial_access_tokens::routes())
+        .nest("/signing-keys", signing_keys::routes())
 }
 
 // TODO: Add proper auth
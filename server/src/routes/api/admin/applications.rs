@@ -1,21 +1,97 @@
-use axum::{Extension, Json};
+use axum::{Extension, Json, extract::Path};
 use axum_valid::Valid;
-use color_eyre::eyre::{Context, ContextCompat};
+use color_eyre::eyre::{self, Context, ContextCompat};
 use futures::TryStreamExt;
-use mongodb::bson::doc;
+use mongodb::bson::{self, doc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 use crate::{
-    axum_error::AxumResult,
-    database::{Application, EditApplicationBody, PartialApplication, PublicApplication},
+    axum_error::{AxumError, AxumResult},
+    database::{
+        Application, ClientType, EditApplicationBody, PartialApplication, PublicApplication,
+    },
     middlewares::require_auth::{ForbiddenError, UnauthorizedError},
-    routes::api::CreateSuccess,
+    oidc::{
+        client_auth::TokenEndpointAuthMethod,
+        client_secrets::generate_client_secret,
+        subject::{validate_sector, verify_sector_identifier_uri},
+    },
     state::AppState,
     utils::generate_client_id,
 };
 
 pub fn routes() -> OpenApiRouter<AppState> {
-    OpenApiRouter::new().routes(routes!(get_applications, create_application))
+    OpenApiRouter::new()
+        .routes(routes!(get_applications, create_application))
+        .routes(routes!(get_client_secrets, create_client_secret))
+        .routes(routes!(delete_client_secret))
+}
+
+#[derive(Serialize, ToSchema)]
+struct CreateApplicationResponse {
+    success: bool,
+    id: String,
+    client_id: String,
+    /// Initial secret of a confidential application. Only returned once, store it before closing the dialog.
+    client_secret: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct PublicClientSecret {
+    id: String,
+    created_at: String,
+    expires_at: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct CreateClientSecretBody {
+    /// Lifetime of the new secret. Secrets without one stay valid until deleted.
+    expires_in_days: Option<u32>,
+    /// Lets the current secrets expire after this many hours, so clients can switch over in the meantime.
+    /// `0` revokes them right away, and they're kept as they are when not set.
+    current_secrets_expire_in_hours: Option<u32>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CreateClientSecretResponse {
+    id: String,
+    /// Only returned once, store it before closing the dialog.
+    client_secret: String,
+    expires_at: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[schema(example = json!({ "success": true }))]
+struct DeleteClientSecretResponse {
+    success: bool,
+}
+
+fn format_date(date: bson::DateTime) -> Option<String> {
+    date.try_to_rfc3339_string().ok()
+}
+
+fn date_in_hours(hours: i64) -> bson::DateTime {
+    bson::DateTime::from_millis(bson::DateTime::now().timestamp_millis() + hours * 60 * 60 * 1000)
+}
+
+/// Whether the application authenticates with a secret, as opposed to being public or using `private_key_jwt`.
+fn uses_client_secret(
+    client_type: &ClientType,
+    token_endpoint_auth_method: Option<TokenEndpointAuthMethod>,
+) -> bool {
+    matches!(client_type, ClientType::Confidential)
+        && token_endpoint_auth_method.is_none_or(TokenEndpointAuthMethod::uses_secret)
+}
+
+async fn find_application(state: &AppState, client_id: &str) -> AxumResult<Application> {
+    state
+        .database
+        .collection::<Application>("applications")
+        .find_one(doc! { "client_id": client_id })
+        .await?
+        .ok_or_else(|| AxumError::not_found(eyre::eyre!("Application not found")))
 }
 
 /// Get applications
@@ -53,7 +129,7 @@ async fn get_applications(
     method(post),
     path = "/",
     responses(
-        (status = OK, description = "Success", body = PublicApplication, content_type = "application/json"),
+        (status = OK, description = "Success", body = CreateApplicationResponse, content_type = "application/json"),
         (status = UNAUTHORIZED, description = "Unauthorized", body = UnauthorizedError, content_type = "application/json"),
         (status = FORBIDDEN, description = "Forbidden", body = ForbiddenError, content_type = "application/json"),
     ),
@@ -62,17 +138,65 @@ async fn get_applications(
 async fn create_application(
     Extension(state): Extension<AppState>,
     Valid(Json(body)): Valid<Json<EditApplicationBody>>,
-) -> AxumResult<Json<CreateSuccess>> {
-    let app = PartialApplication {
+) -> AxumResult<Json<CreateApplicationResponse>> {
+    validate_sector(
+        body.subject_type,
+        body.sector_identifier_uri.as_deref(),
+        &body.redirect_uris,
+    )
+    .map_err(AxumError::bad_request)?;
+
+    if let Some(uri) = &body.sector_identifier_uri {
+        verify_sector_identifier_uri(&state, uri, &body.redirect_uris)
+            .await
+            .map_err(AxumError::bad_request)?;
+    }
+
+    let mut app = PartialApplication {
         name: body.name,
         slug: body.slug,
         icon: body.icon,
         client_type: body.client_type,
         client_id: generate_client_id(),
-        client_secret: None,
+        client_secrets: Vec::new(),
         redirect_uris: body.redirect_uris,
         allowed_groups: body.allowed_groups,
+        require_pkce: body.require_pkce,
+        post_logout_redirect_uris: body.post_logout_redirect_uris,
+        backchannel_logout_uri: body.backchannel_logout_uri,
+        frontchannel_logout_uri: body.frontchannel_logout_uri,
+        frontchannel_logout_session_required: body.frontchannel_logout_session_required,
+        id_token_signed_response_alg: body.id_token_signed_response_alg,
+        allow_client_credentials: body.allow_client_credentials,
+        client_credentials_scopes: body.client_credentials_scopes,
+        client_credentials_audiences: body.client_credentials_audiences,
+        allow_device_authorization: body.allow_device_authorization,
+        exposed_groups: body.exposed_groups,
+        trusted: body.trusted,
+        custom_scopes: body.custom_scopes,
+        claim_mappings: body.claim_mappings,
+        min_security_level: body.min_security_level,
+        require_pushed_authorization_requests: body.require_pushed_authorization_requests,
+        jwks: body.jwks,
+        jwks_uri: body.jwks_uri,
+        request_uris: body.request_uris,
+        token_endpoint_auth_method: body.token_endpoint_auth_method,
+        token_exchange_policies: body.token_exchange_policies,
+        subject_type: body.subject_type,
+        sector_identifier_uri: body.sector_identifier_uri,
+        token_lifetimes: body.token_lifetimes,
+        registration_access_token_hash: None,
+    };
+
+    let client_secret = if uses_client_secret(&app.client_type, app.token_endpoint_auth_method) {
+        let (secret, client_secret) =
+            generate_client_secret(&state.encryption_key, app.token_endpoint_auth_method, None)?;
+        app.client_secrets.push(client_secret);
+        Some(secret)
+    } else {
+        None
     };
+    let client_id = app.client_id.clone();
 
     let inserted = state
         .database
@@ -87,5 +211,152 @@ async fn create_application(
         .wrap_err("Failed to fetch application ID")?
         .to_string();
 
-    Ok(Json(CreateSuccess { success: true, id }))
+    Ok(Json(CreateApplicationResponse {
+        success: true,
+        id,
+        client_id,
+        client_secret,
+    }))
+}
+
+/// Get client secrets
+///
+/// Lists the secrets of a confidential application. The secrets themselves are only shown when generated.
+#[utoipa::path(
+    method(get),
+    path = "/{client_id}/secrets",
+    params(
+        ("client_id" = String, Path, description = "Client ID of the application")
+    ),
+    responses(
+        (status = OK, description = "Success", body = Vec<PublicClientSecret>, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Unauthorized", body = UnauthorizedError, content_type = "application/json"),
+        (status = FORBIDDEN, description = "Forbidden", body = ForbiddenError, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Application not found", body = String, content_type = "application/json"),
+    ),
+    tag = "Admin"
+)]
+async fn get_client_secrets(
+    Extension(state): Extension<AppState>,
+    Path(client_id): Path<String>,
+) -> AxumResult<Json<Vec<PublicClientSecret>>> {
+    let app = find_application(&state, &client_id).await?;
+
+    Ok(Json(
+        app.client_secrets
+            .into_iter()
+            .map(|secret| PublicClientSecret {
+                id: secret.id,
+                created_at: format_date(secret.created_at).unwrap_or_default(),
+                expires_at: secret.expires_at.and_then(format_date),
+            })
+            .collect(),
+    ))
+}
+
+/// Create client secret
+///
+/// Generates a new secret for a confidential application. To rotate secrets without downtime,
+/// let the current ones expire once clients had time to switch to the new one.
+#[utoipa::path(
+    method(post),
+    path = "/{client_id}/secrets",
+    params(
+        ("client_id" = String, Path, description = "Client ID of the application")
+    ),
+    request_body = CreateClientSecretBody,
+    responses(
+        (status = OK, description = "Success", body = CreateClientSecretResponse, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "The application doesn't use client secrets", body = String, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Unauthorized", body = UnauthorizedError, content_type = "application/json"),
+        (status = FORBIDDEN, description = "Forbidden", body = ForbiddenError, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Application not found", body = String, content_type = "application/json"),
+    ),
+    tag = "Admin"
+)]
+async fn create_client_secret(
+    Extension(state): Extension<AppState>,
+    Path(client_id): Path<String>,
+    Json(body): Json<CreateClientSecretBody>,
+) -> AxumResult<Json<CreateClientSecretResponse>> {
+    let app = find_application(&state, &client_id).await?;
+
+    if !uses_client_secret(&app.client_type, app.token_endpoint_auth_method) {
+        return Err(AxumError::bad_request(eyre::eyre!(
+            "The application doesn't authenticate with a client secret"
+        )));
+    }
+
+    let mut client_secrets = app.client_secrets;
+
+    if let Some(hours) = body.current_secrets_expire_in_hours {
+        let expires_at = date_in_hours(i64::from(hours));
+        for secret in &mut client_secrets {
+            secret.expires_at = Some(secret.expires_at.map_or(expires_at, |e| e.min(expires_at)));
+        }
+    }
+    client_secrets.retain(|secret| !secret.is_expired());
+
+    let (secret, client_secret) = generate_client_secret(
+        &state.encryption_key,
+        app.token_endpoint_auth_method,
+        body.expires_in_days
+            .map(|days| date_in_hours(i64::from(days) * 24)),
+    )?;
+    let response = CreateClientSecretResponse {
+        id: client_secret.id.clone(),
+        client_secret: secret,
+        expires_at: client_secret.expires_at.and_then(format_date),
+    };
+    client_secrets.push(client_secret);
+
+    state
+        .database
+        .collection::<Application>("applications")
+        .update_one(
+            doc! { "_id": app.id },
+            doc! { "$set": { "client_secrets": bson::to_bson(&client_secrets)? } },
+        )
+        .await
+        .wrap_err("Failed to store client secret")?;
+
+    Ok(Json(response))
+}
+
+/// Delete client secret
+///
+/// Revokes a secret right away.
+#[utoipa::path(
+    method(delete),
+    path = "/{client_id}/secrets/{id}",
+    params(
+        ("client_id" = String, Path, description = "Client ID of the application"),
+        ("id" = String, Path, description = "ID of the secret")
+    ),
+    responses(
+        (status = OK, description = "Success", body = DeleteClientSecretResponse, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Unauthorized", body = UnauthorizedError, content_type = "application/json"),
+        (status = FORBIDDEN, description = "Forbidden", body = ForbiddenError, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Secret not found", body = String, content_type = "application/json"),
+    ),
+    tag = "Admin"
+)]
+async fn delete_client_secret(
+    Extension(state): Extension<AppState>,
+    Path((client_id, id)): Path<(String, String)>,
+) -> AxumResult<Json<DeleteClientSecretResponse>> {
+    let result = state
+        .database
+        .collection::<Application>("applications")
+        .update_one(
+            doc! { "client_id": &client_id, "client_secrets.id": &id },
+            doc! { "$pull": { "client_secrets": { "id": &id } } },
+        )
+        .await?;
+
+    if result.matched_count == 0 {
+        return Err(AxumError::not_found(eyre::eyre!("Client secret not found")));
+    }
+
+    Ok(Json(DeleteClientSecretResponse { success: true }))
 }
@@ -0,0 +1,104 @@
+use axum::{Extension, Json};
+use axum_valid::Valid;
+use color_eyre::eyre::{self, Context, ContextCompat};
+use futures::TryStreamExt;
+use mongodb::bson::doc;
+use serde::Deserialize;
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+use validator::Validate;
+
+use crate::{
+    axum_error::{AxumError, AxumResult},
+    database::{Group, PartialGroup},
+    middlewares::require_auth::{ForbiddenError, UnauthorizedError},
+    routes::api::CreateSuccess,
+    state::AppState,
+    validators::slug_validator,
+};
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new().routes(routes!(get_groups, create_group))
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+struct CreateGroupBody {
+    #[validate(length(min = 1, max = 32))]
+    name: String,
+
+    #[validate(custom(function = "slug_validator"), length(min = 1, max = 32))]
+    slug: String,
+}
+
+/// Get groups
+#[utoipa::path(
+    method(get),
+    path = "/",
+    responses(
+        (status = OK, description = "Success", body = Vec<Group>, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Unauthorized", body = UnauthorizedError, content_type = "application/json"),
+        (status = FORBIDDEN, description = "Forbidden", body = ForbiddenError, content_type = "application/json"),
+    ),
+    tag = "Admin"
+)]
+async fn get_groups(Extension(state): Extension<AppState>) -> AxumResult<Json<Vec<Group>>> {
+    let groups: Vec<Group> = state
+        .database
+        .collection::<Group>("groups")
+        .find(doc! {})
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(Json(groups))
+}
+
+/// Create group
+#[utoipa::path(
+    method(post),
+    path = "/",
+    request_body = CreateGroupBody,
+    responses(
+        (status = OK, description = "Success", body = CreateSuccess, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Unauthorized", body = UnauthorizedError, content_type = "application/json"),
+        (status = FORBIDDEN, description = "Forbidden", body = ForbiddenError, content_type = "application/json"),
+        (status = CONFLICT, description = "Slug already taken", body = String, content_type = "application/json"),
+    ),
+    tag = "Admin"
+)]
+async fn create_group(
+    Extension(state): Extension<AppState>,
+    Valid(Json(body)): Valid<Json<CreateGroupBody>>,
+) -> AxumResult<Json<CreateSuccess>> {
+    let already_exists = state
+        .database
+        .collection::<Group>("groups")
+        .find_one(doc! { "slug": &body.slug })
+        .await?;
+
+    if already_exists.is_some() {
+        return Err(AxumError::conflict(eyre::eyre!(
+            "Group with this slug already exists"
+        )));
+    }
+
+    let group = PartialGroup {
+        name: body.name,
+        slug: body.slug,
+    };
+
+    let inserted = state
+        .database
+        .collection::<PartialGroup>("groups")
+        .insert_one(group)
+        .await
+        .wrap_err("Failed to create group")?;
+
+    let id = inserted
+        .inserted_id
+        .as_object_id()
+        .wrap_err("Failed to fetch group ID")?
+        .to_string();
+
+    Ok(Json(CreateSuccess { success: true, id }))
+}
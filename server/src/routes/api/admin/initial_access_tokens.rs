@@ -0,0 +1,172 @@
+use axum::{Extension, Json, extract::Path};
+use color_eyre::eyre::{self, Context};
+use futures::TryStreamExt;
+use mongodb::bson::{self, doc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+use uuid::Uuid;
+
+use crate::{
+    axum_error::{AxumError, AxumResult},
+    middlewares::require_auth::{ForbiddenError, UnauthorizedError},
+    oidc::registration::InitialAccessToken,
+    state::AppState,
+    utils::{generate_reset_token, hash_token},
+};
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(
+            get_initial_access_tokens,
+            create_initial_access_token
+        ))
+        .routes(routes!(delete_initial_access_token))
+}
+
+#[derive(Serialize, ToSchema)]
+struct PublicInitialAccessToken {
+    id: String,
+    description: Option<String>,
+    created_at: String,
+    expires_at: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct CreateInitialAccessTokenBody {
+    description: Option<String>,
+    /// Lifetime of the token. Tokens without one stay valid until deleted.
+    expires_in_days: Option<u32>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CreateInitialAccessTokenResponse {
+    id: String,
+    /// Only returned once, store it before closing the dialog.
+    token: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[schema(example = json!({ "success": true }))]
+struct DeleteInitialAccessTokenResponse {
+    success: bool,
+}
+
+fn format_date(date: bson::DateTime) -> Option<String> {
+    date.try_to_rfc3339_string().ok()
+}
+
+/// Get initial access tokens
+///
+/// Lists the tokens that allow clients to register themselves at the registration endpoint.
+#[utoipa::path(
+    method(get),
+    path = "/",
+    responses(
+        (status = OK, description = "Success", body = Vec<PublicInitialAccessToken>, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Unauthorized", body = UnauthorizedError, content_type = "application/json"),
+        (status = FORBIDDEN, description = "Forbidden", body = ForbiddenError, content_type = "application/json"),
+    ),
+    tag = "Admin"
+)]
+async fn get_initial_access_tokens(
+    Extension(state): Extension<AppState>,
+) -> AxumResult<Json<Vec<PublicInitialAccessToken>>> {
+    let tokens: Vec<InitialAccessToken> = state
+        .database
+        .collection::<InitialAccessToken>("initial_access_tokens")
+        .find(doc! {})
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(Json(
+        tokens
+            .into_iter()
+            .map(|token| PublicInitialAccessToken {
+                id: token.id,
+                description: token.description,
+                created_at: format_date(token.created_at).unwrap_or_default(),
+                expires_at: token.expires_at.and_then(format_date),
+            })
+            .collect(),
+    ))
+}
+
+/// Create initial access token
+#[utoipa::path(
+    method(post),
+    path = "/",
+    request_body = CreateInitialAccessTokenBody,
+    responses(
+        (status = OK, description = "Success", body = CreateInitialAccessTokenResponse, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Unauthorized", body = UnauthorizedError, content_type = "application/json"),
+        (status = FORBIDDEN, description = "Forbidden", body = ForbiddenError, content_type = "application/json"),
+    ),
+    tag = "Admin"
+)]
+async fn create_initial_access_token(
+    Extension(state): Extension<AppState>,
+    Json(body): Json<CreateInitialAccessTokenBody>,
+) -> AxumResult<Json<CreateInitialAccessTokenResponse>> {
+    let token = generate_reset_token();
+    let now = bson::DateTime::now();
+
+    let initial_access_token = InitialAccessToken {
+        id: Uuid::new_v4().to_string(),
+        token_hash: hash_token(&token),
+        description: body.description,
+        created_at: now,
+        expires_at: body.expires_in_days.map(|days| {
+            bson::DateTime::from_millis(
+                now.timestamp_millis() + i64::from(days) * 24 * 60 * 60 * 1000,
+            )
+        }),
+    };
+
+    state
+        .database
+        .collection::<InitialAccessToken>("initial_access_tokens")
+        .insert_one(&initial_access_token)
+        .await
+        .wrap_err("Failed to create initial access token")?;
+
+    Ok(Json(CreateInitialAccessTokenResponse {
+        id: initial_access_token.id,
+        token,
+    }))
+}
+
+/// Delete initial access token
+#[utoipa::path(
+    method(delete),
+    path = "/{id}",
+    params(
+        ("id" = String, Path, description = "ID of the initial access token")
+    ),
+    responses(
+        (status = OK, description = "Success", body = DeleteInitialAccessTokenResponse, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Unauthorized", body = UnauthorizedError, content_type = "application/json"),
+        (status = FORBIDDEN, description = "Forbidden", body = ForbiddenError, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Token not found", body = String, content_type = "application/json"),
+    ),
+    tag = "Admin"
+)]
+async fn delete_initial_access_token(
+    Extension(state): Extension<AppState>,
+    Path(id): Path<String>,
+) -> AxumResult<Json<DeleteInitialAccessTokenResponse>> {
+    let result = state
+        .database
+        .collection::<InitialAccessToken>("initial_access_tokens")
+        .delete_one(doc! { "id": &id })
+        .await?;
+
+    if result.deleted_count == 0 {
+        return Err(AxumError::not_found(eyre::eyre!(
+            "Initial access token not found"
+        )));
+    }
+
+    Ok(Json(DeleteInitialAccessTokenResponse { success: true }))
+}
@@ -0,0 +1,93 @@
+use axum::{Extension, Json};
+use mongodb::bson;
+use serde::Serialize;
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    axum_error::AxumResult,
+    middlewares::require_auth::{ForbiddenError, UnauthorizedError},
+    oidc::keys::{
+        KeyStatus, reload_key_ring, retired_key_lifetime, rotate_signing_keys, stored_signing_keys,
+    },
+    state::AppState,
+};
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(get_signing_keys))
+        .routes(routes!(rotate_signing_key))
+}
+
+#[derive(Serialize, ToSchema)]
+struct PublicSigningKey {
+    kid: String,
+    status: KeyStatus,
+    created_at: String,
+    activated_at: Option<String>,
+    retired_at: Option<String>,
+    /// When a retired key is removed from the JWKS.
+    expires_at: Option<String>,
+}
+
+fn format_date(date: Option<bson::DateTime>) -> Option<String> {
+    date.and_then(|date| date.try_to_rfc3339_string().ok())
+}
+
+async fn list_signing_keys(state: &AppState) -> AxumResult<Vec<PublicSigningKey>> {
+    let settings = &state.settings.oidc;
+    let keys = stored_signing_keys(&state.database, settings).await?;
+    let lifetime = retired_key_lifetime(&state.database, settings).await?;
+
+    Ok(keys
+        .iter()
+        .map(|key| PublicSigningKey {
+            kid: key.kid.clone(),
+            status: key.status,
+            created_at: format_date(Some(key.created_at)).unwrap_or_default(),
+            activated_at: format_date(key.activated_at),
+            retired_at: format_date(key.retired_at),
+            expires_at: format_date(key.expires_at(lifetime)),
+        })
+        .collect())
+}
+
+/// Get OIDC signing keys
+#[utoipa::path(
+    method(get),
+    path = "/",
+    responses(
+        (status = OK, description = "Success", body = Vec<PublicSigningKey>, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Unauthorized", body = UnauthorizedError, content_type = "application/json"),
+        (status = FORBIDDEN, description = "Forbidden", body = ForbiddenError, content_type = "application/json"),
+    ),
+    tag = "Admin"
+)]
+async fn get_signing_keys(
+    Extension(state): Extension<AppState>,
+) -> AxumResult<Json<Vec<PublicSigningKey>>> {
+    Ok(Json(list_signing_keys(&state).await?))
+}
+
+/// Rotate OIDC signing key
+///
+/// Retires the active key and promotes the upcoming one. Retired keys stay in the JWKS
+/// until they expire, so tokens already issued remain valid.
+#[utoipa::path(
+    method(post),
+    path = "/rotate",
+    responses(
+        (status = OK, description = "Success", body = Vec<PublicSigningKey>, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Unauthorized", body = UnauthorizedError, content_type = "application/json"),
+        (status = FORBIDDEN, description = "Forbidden", body = ForbiddenError, content_type = "application/json"),
+    ),
+    tag = "Admin"
+)]
+async fn rotate_signing_key(
+    Extension(state): Extension<AppState>,
+) -> AxumResult<Json<Vec<PublicSigningKey>>> {
+    rotate_signing_keys(&state.database, &state.encryption_key, None).await?;
+    reload_key_ring(&state).await?;
+
+    Ok(Json(list_signing_keys(&state).await?))
+}
@@ -10,6 +10,7 @@ use utoipa_axum::{router::OpenApiRouter, routes};
 use crate::{
     axum_error::{AxumError, AxumResult},
     database::{FirstFactor, get_second_factors, get_user, set_recent_factor},
+    oidc::authentication::{record_factor, start_login},
     routes::api::AuthState,
     state::AppState,
     utils::{hash_password, verify_password},
@@ -78,7 +79,8 @@ async fn login_with_password(
     verify_password(&body.password, password_hash)
         .map_err(|_| AxumError::unauthorized(eyre::eyre!("Invalid username or password")))?;
 
-    session.insert("user_id", user.id).await?;
+    start_login(&state, &session, user.id).await?;
+    record_factor(&session, FirstFactor::Password).await?;
 
     let second_factors = get_second_factors(&user);
 
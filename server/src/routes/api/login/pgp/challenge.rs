@@ -13,6 +13,7 @@ use validator::Validate;
 use crate::{
     axum_error::{AxumError, AxumResult},
     database::{FirstFactor, get_second_factors, get_user, set_recent_factor},
+    oidc::authentication::{record_factor, start_login},
     routes::api::AuthState,
     state::AppState,
 };
@@ -151,7 +152,8 @@ async fn respond_to_pgp_challenge(
         .remove::<PgpChallengeConfig>("login::pgp_challenge")
         .await?;
 
-    session.insert("user_id", user.id).await?;
+    start_login(&state, &session, user.id).await?;
+    record_factor(&session, FirstFactor::Pgp).await?;
 
     let second_factors = get_second_factors(&user);
 
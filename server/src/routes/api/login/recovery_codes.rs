@@ -10,6 +10,7 @@ use crate::{
     axum_error::{AxumError, AxumResult},
     database::{SecondFactor, User, get_user_by_id, set_recent_factor},
     middlewares::require_auth::UserId,
+    oidc::authentication::record_factor,
     routes::api::{AuthState, settings::factors::recovery_codes::verify_recovery_code},
     state::AppState,
 };
@@ -87,6 +88,7 @@ async fn login_with_recovery_code(
     }
 
     set_recent_factor(&state.database, &user_id, SecondFactor::RecoveryCode.into()).await?;
+    record_factor(&session, SecondFactor::RecoveryCode).await?;
 
     session
         .insert("auth_state", AuthState::Authenticated)
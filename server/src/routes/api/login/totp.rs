@@ -9,6 +9,7 @@ use crate::{
     axum_error::{AxumError, AxumResult},
     database::{SecondFactor, get_user_by_id, set_recent_factor},
     middlewares::require_auth::UserId,
+    oidc::authentication::record_factor,
     routes::api::{
         AuthState,
         login::SuccessfulLoginResponse,
@@ -59,6 +60,7 @@ async fn login_with_totp(
     verify_totp(&user.auth_factors.totp.unwrap().secret, &body.code)?;
 
     set_recent_factor(&state.database, &user_id, SecondFactor::Totp.into()).await?;
+    record_factor(&session, SecondFactor::Totp).await?;
 
     session
         .insert("auth_state", AuthState::Authenticated)
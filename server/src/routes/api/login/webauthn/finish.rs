@@ -6,8 +6,9 @@ use webauthn_rs::prelude::*;
 
 use crate::{
     axum_error::{AxumError, AxumResult},
-    database::get_user_by_id,
+    database::{SecondFactor, get_user_by_id},
     middlewares::require_auth::{UnauthorizedError, UserId},
+    oidc::authentication::record_factor,
     routes::api::{AuthState, login::SuccessfulLoginResponse},
     state::AppState,
 };
@@ -59,6 +60,7 @@ async fn webauthn_finish_login(
         })?;
 
     update_webauthn_credentials(&state, &user, &auth_result).await?;
+    record_factor(&session, SecondFactor::WebAuthn).await?;
 
     session
         .insert("auth_state", AuthState::Authenticated)
@@ -6,8 +6,9 @@ use webauthn_rs::prelude::*;
 
 use crate::{
     axum_error::{AxumError, AxumResult},
-    database::get_user_by_uuid,
+    database::{FirstFactor, get_user_by_uuid},
     middlewares::require_auth::UnauthorizedError,
+    oidc::authentication::{record_factor, start_login},
     routes::api::{AuthState, login::SuccessfulLoginResponse},
     state::AppState,
 };
@@ -86,7 +87,8 @@ async fn passwordless_finish(
 
     update_webauthn_credentials(&state, &user, &auth_result).await?;
 
-    session.insert("user_id", user.id).await?;
+    start_login(&state, &session, user.id).await?;
+    record_factor(&session, FirstFactor::WebAuthnPasswordless).await?;
     session
         .insert("auth_state", AuthState::Authenticated)
         .await?;
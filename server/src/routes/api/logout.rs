@@ -1,12 +1,13 @@
 use axum::{Extension, Json};
-use mongodb::bson::doc;
 use serde::Serialize;
 use tower_sessions::Session;
-use tracing::warn;
 use utoipa::ToSchema;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
-use crate::{axum_error::AxumResult, database::SessionRecord, state::AppState};
+use crate::{
+    axum_error::AxumResult, database::destroy_session, oidc::logout::notify_session_logout,
+    state::AppState,
+};
 
 pub fn routes() -> OpenApiRouter<AppState> {
     OpenApiRouter::new().routes(routes!(logout))
@@ -32,18 +33,8 @@ async fn logout(
     Extension(state): Extension<AppState>,
     session: Session,
 ) -> AxumResult<Json<LogoutResponse>> {
-    let session_id = session.id().map(|id| id.to_string());
-
-    session.flush().await?;
-
-    if let Some(session_id) = session_id
-        && let Err(error) = state
-            .database
-            .collection::<SessionRecord>("sessions")
-            .delete_one(doc! { "_id": session_id })
-            .await
-    {
-        warn!(error = ?error, "Failed to clean up session record after logout");
+    if let Some(session_id) = destroy_session(&state.database, &session).await? {
+        notify_session_logout(&state, session_id);
     }
 
     Ok(Json(LogoutResponse { success: true }))
@@ -3,6 +3,7 @@ use utoipa_axum::router::OpenApiRouter;
 use crate::state::AppState;
 
 pub mod account;
+pub mod authorized_apps;
 pub mod factors;
 pub mod password;
 pub mod profile;
@@ -11,6 +12,7 @@ pub mod sessions;
 pub fn routes() -> OpenApiRouter<AppState> {
     OpenApiRouter::new()
         .nest("/account", account::routes())
+        .nest("/authorized-apps", authorized_apps::routes())
         .nest("/factors", factors::routes())
         .nest("/password", password::routes())
         .nest("/profile", profile::routes())
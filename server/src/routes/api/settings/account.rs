@@ -10,6 +10,7 @@ use crate::{
     axum_error::{AxumError, AxumResult},
     database::User,
     middlewares::require_auth::UserId,
+    oidc::logout::notify_user_logout,
     state::AppState,
     utils::verify_password,
 };
@@ -81,6 +82,8 @@ async fn delete_account(
             .await;
     });
 
+    notify_user_logout(&state, *user_id);
+
     // Destroy session
     session.flush().await?;
 
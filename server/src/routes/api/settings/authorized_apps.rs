@@ -0,0 +1,127 @@
+use axum::{Extension, Json, extract::Path};
+use color_eyre::eyre;
+use futures::TryStreamExt;
+use mongodb::bson::doc;
+use serde::Serialize;
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    axum_error::{AxumError, AxumResult},
+    database::Application,
+    middlewares::require_auth::{UnauthorizedError, UserId},
+    oidc::consent::{ConsentGrant, revoke_consent},
+    state::AppState,
+};
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(list_authorized_apps))
+        .routes(routes!(revoke_authorized_app))
+}
+
+#[derive(Serialize, ToSchema)]
+struct AuthorizedApp {
+    client_id: String,
+    name: String,
+    icon: Option<String>,
+    scopes: Vec<String>,
+    created_at: String,
+    updated_at: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[schema(example = json!({ "success": true }))]
+struct RevokeAuthorizedAppResponse {
+    success: bool,
+}
+
+/// List authorized applications
+///
+/// Returns the applications the current user has granted access to, with the granted scopes.
+#[utoipa::path(
+    method(get),
+    path = "/",
+    responses(
+        (status = OK, description = "Success", body = Vec<AuthorizedApp>, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Unauthorized", body = UnauthorizedError, content_type = "application/json"),
+    ),
+    tag = "Settings"
+)]
+async fn list_authorized_apps(
+    Extension(state): Extension<AppState>,
+    Extension(user_id): Extension<UserId>,
+) -> AxumResult<Json<Vec<AuthorizedApp>>> {
+    let grants: Vec<ConsentGrant> = state
+        .database
+        .collection::<ConsentGrant>("oidc_consents")
+        .find(doc! { "user_id": *user_id })
+        .sort(doc! { "updated_at": -1_i32 })
+        .await?
+        .try_collect()
+        .await?;
+
+    let client_ids = grants
+        .iter()
+        .map(|grant| grant.client_id.clone())
+        .collect::<Vec<_>>();
+
+    let applications: Vec<Application> = state
+        .database
+        .collection::<Application>("applications")
+        .find(doc! { "client_id": { "$in": client_ids } })
+        .await?
+        .try_collect()
+        .await?;
+
+    // Grants of deleted applications are skipped
+    let authorized_apps = grants
+        .into_iter()
+        .filter_map(|grant| {
+            let app = applications
+                .iter()
+                .find(|app| app.client_id == grant.client_id)?;
+
+            Some(AuthorizedApp {
+                client_id: grant.client_id,
+                name: app.name.clone(),
+                icon: app.icon.clone(),
+                scopes: grant.scopes,
+                created_at: grant.created_at.try_to_rfc3339_string().unwrap_or_default(),
+                updated_at: grant.updated_at.try_to_rfc3339_string().unwrap_or_default(),
+            })
+        })
+        .collect();
+
+    Ok(Json(authorized_apps))
+}
+
+/// Revoke an authorized application
+///
+/// Removes the application's access and revokes its refresh tokens. The user is asked for consent again on the next sign-in.
+#[utoipa::path(
+    method(delete),
+    path = "/{client_id}",
+    params(
+        ("client_id" = String, Path, description = "Client ID of the application")
+    ),
+    responses(
+        (status = OK, description = "Success", body = RevokeAuthorizedAppResponse, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Unauthorized", body = UnauthorizedError, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Application not authorized", body = String, content_type = "application/json"),
+    ),
+    tag = "Settings"
+)]
+async fn revoke_authorized_app(
+    Extension(state): Extension<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Path(client_id): Path<String>,
+) -> AxumResult<Json<RevokeAuthorizedAppResponse>> {
+    if !revoke_consent(&state.database, &user_id, &client_id).await? {
+        return Err(AxumError::not_found(eyre::eyre!(
+            "Application not authorized"
+        )));
+    }
+
+    Ok(Json(RevokeAuthorizedAppResponse { success: true }))
+}
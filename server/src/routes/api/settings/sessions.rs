@@ -14,6 +14,7 @@ use crate::{
     axum_error::{AxumError, AxumResult},
     database::SessionRecord,
     middlewares::require_auth::UserId,
+    oidc::logout::notify_session_logout,
     state::AppState,
 };
 
@@ -211,6 +212,8 @@ async fn delete_session(
         .await
         .map_err(|e| AxumError::new(eyre::eyre!("Failed to invalidate session: {}", e)))?;
 
+    notify_session_logout(&state, record.id.clone());
+
     if let Err(error) = state
         .database
         .collection::<SessionRecord>("sessions")
@@ -1,37 +1,82 @@
+use auth_core::SecurityLevel;
 use axum::{
     Extension, Json,
-    extract::Query,
+    extract::{Query, RawQuery},
     http::{HeaderMap, StatusCode},
     response::IntoResponse,
 };
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use color_eyre::eyre::{self, Context as _};
-use mongodb::bson::doc;
+use mongodb::bson::{doc, oid::ObjectId};
 use openidconnect::{
-    AccessToken, Audience, EmptyAdditionalClaims, EndUserEmail, EndUserFamilyName,
-    EndUserGivenName, EndUserName, EndUserUsername, IssuerUrl, LocalizedClaim, Nonce,
-    SubjectIdentifier,
-    core::{CoreIdToken, CoreIdTokenClaims, CoreJwsSigningAlgorithm},
+    AccessToken, Audience, AuthenticationContextClass, AuthenticationMethodReference, EndUserEmail,
+    EndUserFamilyName, EndUserGivenName, EndUserName, EndUserUsername, IssuerUrl, LocalizedClaim,
+    Nonce, SubjectIdentifier,
 };
 use serde::{Deserialize, Serialize};
 use tower_sessions::Session;
+use tracing::warn;
 use utoipa::ToSchema;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 use crate::{
     axum_error::{AxumError, AxumResult},
-    database::{Application, User, get_user_by_uuid},
-    oidc::{AccessTokenClaims, AuthorizationCode, RefreshToken},
+    database::{Application, SecondFactor, User, get_second_factors, get_user, get_user_by_id},
+    oidc::{
+        AccessTokenClaims, AuthorizationCode, ExtraIdTokenClaims, IdToken, IdTokenClaims,
+        RefreshToken,
+        authentication::{
+            Prompt, SessionAuthentication, clear_step_up, factor_security_level,
+            forget_login_request, logged_in_since_request, remember_required_level,
+            request_step_up, requested_security_level, session_authentication, take_required_level,
+        },
+        claims::{CustomScope, STANDARD_SCOPES, extra_claims},
+        client_auth::{
+            ClientCredential, TokenEndpointAuthMethod, client_assertion_subject,
+            verify_client_credential,
+        },
+        consent::{has_consent, record_consent},
+        device::{
+            DEVICE_CODE_GRANT_TYPE, DeviceAuthorization, DeviceAuthorizationStatus,
+            SLOW_DOWN_INCREMENT_SECS,
+        },
+        dpop::{ConfirmationClaim, access_token_type, verify_dpop_proof},
+        logout::{ClientSession, get_or_create_sid, record_client_session},
+        par::{consume_pushed_request, find_pushed_request, is_pushed_request_uri},
+        pkce::{self, CodeChallengeMethod},
+        request_object::{fetch_request_object, merge_request_object, verify_request_object},
+        revocation::{handle_refresh_token_reuse, is_access_token_revoked},
+        subject::{find_subject_user, issue_subject_identifier},
+        token_exchange::{
+            ACCESS_TOKEN_TYPE, ActorClaim, ID_TOKEN_TYPE, TOKEN_EXCHANGE_GRANT_TYPE, find_policy,
+            narrow_scope,
+        },
+    },
     state::AppState,
     utils::{generate_reset_token, hash_token},
 };
 
+mod device;
+mod device_authorization;
+mod end_session;
+mod introspect;
+mod par;
+mod register;
+mod revoke;
+
 pub fn routes() -> OpenApiRouter<AppState> {
     OpenApiRouter::new()
         .routes(routes!(jwks))
         .routes(routes!(authorize_get, authorize_post))
         .routes(routes!(token))
         .routes(routes!(userinfo))
+        .nest("/device", device::routes())
+        .nest("/device_authorization", device_authorization::routes())
+        .nest("/end_session", end_session::routes())
+        .nest("/introspect", introspect::routes())
+        .nest("/par", par::routes())
+        .nest("/register", register::routes())
+        .nest("/revoke", revoke::routes())
 }
 
 // ── Discovery ────────────────────────────────────────────────────
@@ -48,15 +93,18 @@ pub fn routes() -> OpenApiRouter<AppState> {
     tag = "OIDC"
 )]
 async fn jwks(Extension(state): Extension<AppState>) -> impl IntoResponse {
-    Json(serde_json::to_value(&state.oidc_keys.jwks).unwrap_or_default())
+    Json(serde_json::to_value(state.oidc_keys.jwks()).unwrap_or_default())
 }
 
 // ── Authorize (GET) ──────────────────────────────────────────────
 
-#[derive(Debug, Deserialize, ToSchema)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct AuthorizeQuery {
     pub client_id: String,
+    /// Required unless the parameters were pushed, see `request_uri`.
+    #[serde(default)]
     pub redirect_uri: String,
+    #[serde(default)]
     pub response_type: String,
     #[serde(default)]
     pub scope: Option<String>,
@@ -64,6 +112,38 @@ pub struct AuthorizeQuery {
     pub state: Option<String>,
     #[serde(default)]
     pub nonce: Option<String>,
+    #[serde(default)]
+    pub code_challenge: Option<String>,
+    #[serde(default)]
+    pub code_challenge_method: Option<String>,
+    /// Space-separated `none`, `login`, `consent` and `select_account`.
+    #[serde(default)]
+    pub prompt: Option<String>,
+    /// Maximum time since the user last signed in, in seconds.
+    #[serde(default)]
+    pub max_age: Option<i64>,
+    /// Username or email address of the user the client expects.
+    #[serde(default)]
+    pub login_hint: Option<String>,
+    /// Space-separated `acr` values the client accepts, see [`crate::oidc::authentication::ACR_VALUES`].
+    #[serde(default)]
+    pub acr_values: Option<String>,
+    /// Request object (RFC 9101), a JWT signed by the client whose claims replace every parameter but `client_id`.
+    #[serde(default)]
+    pub request: Option<String>,
+    /// Reference to parameters pushed by the client (RFC 9126), which replace all others,
+    /// or a URL of a request object registered in the client's `request_uris`.
+    #[serde(default)]
+    pub request_uri: Option<String>,
+}
+
+/// Factor the user has to complete before the application can be authorized.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StepUpRequirement {
+    /// Level the sign-in has to reach.
+    pub security_level: SecurityLevel,
+    /// Second factors of the user that reach it.
+    pub factors: Vec<SecondFactor>,
 }
 
 #[derive(Debug, Serialize, ToSchema)]
@@ -71,13 +151,29 @@ pub struct AuthorizeInfo {
     pub app_name: String,
     pub app_icon: Option<String>,
     pub scopes: Vec<String>,
+    /// Descriptions of the application-specific scopes among `scopes`.
+    pub custom_scopes: Vec<CustomScope>,
     pub client_id: String,
     pub redirect_uri: String,
     pub state: Option<String>,
     pub nonce: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<CodeChallengeMethod>,
+    /// Set when the user doesn't need to be asked for consent, the code has already been issued.
+    /// With `prompt=none`, may carry an error for the client instead.
+    pub redirect_url: Option<String>,
+    /// Set when the user has to complete another factor first, then load this again.
+    pub step_up: Option<StepUpRequirement>,
+    /// Pushed request the parameters come from, to be sent along with the consent.
+    pub request_uri: Option<String>,
 }
 
 /// Get authorization info (requires session)
+///
+/// Skips the consent prompt and issues the code right away for trusted applications and
+/// for scopes the user already granted. Responds with 401 when the user has to sign in,
+/// including again for `prompt=login`, an exceeded `max_age` or a different `login_hint`.
+/// Asks for another factor when the sign-in is weaker than the application's minimum level or `acr_values`.
 #[utoipa::path(
     method(get),
     path = "/authorize",
@@ -88,10 +184,20 @@ pub struct AuthorizeInfo {
         ("scope" = Option<String>, Query,),
         ("state" = Option<String>, Query,),
         ("nonce" = Option<String>, Query,),
+        ("code_challenge" = Option<String>, Query,),
+        ("code_challenge_method" = Option<String>, Query,),
+        ("prompt" = Option<String>, Query,),
+        ("max_age" = Option<i64>, Query,),
+        ("login_hint" = Option<String>, Query,),
+        ("acr_values" = Option<String>, Query,),
+        ("request" = Option<String>, Query,),
+        ("request_uri" = Option<String>, Query,),
     ),
     responses(
         (status = OK, description = "Authorization info", body = AuthorizeInfo),
         (status = BAD_REQUEST, description = "Invalid request"),
+        (status = UNAUTHORIZED, description = "Login required"),
+        (status = FORBIDDEN, description = "No access to the application, or no factor strong enough"),
     ),
     tag = "OIDC"
 )]
@@ -99,34 +205,278 @@ async fn authorize_get(
     Extension(state): Extension<AppState>,
     session: Session,
     Query(params): Query<AuthorizeQuery>,
+    RawQuery(query): RawQuery,
 ) -> AxumResult<Json<AuthorizeInfo>> {
-    // Check user is authenticated
-    let user_id = session
-        .get::<mongodb::bson::oid::ObjectId>("user_id")
-        .await?;
-    let auth_state = session
-        .get::<crate::routes::api::AuthState>("auth_state")
-        .await?;
+    let params = match params.request_uri {
+        Some(request_uri) if is_pushed_request_uri(&request_uri) => {
+            resolve_pushed_request(&state, &params.client_id, request_uri).await?
+        }
+        _ if params.request.is_some() || params.request_uri.is_some() => {
+            resolve_request_object(&state, params, query.as_deref().unwrap_or_default()).await?
+        }
+        _ => params,
+    };
 
-    if user_id.is_none()
-        || !matches!(
-            auth_state,
-            Some(crate::routes::api::AuthState::Authenticated)
-        )
-    {
+    // Look up application by client_id
+    let app = state
+        .database
+        .collection::<Application>("applications")
+        .find_one(doc! { "client_id": &params.client_id })
+        .await
+        .wrap_err("Database error")?
+        .ok_or_else(|| AxumError::bad_request(eyre::eyre!("Unknown client_id")))?;
+
+    if app.require_pushed_authorization_requests && params.request_uri.is_none() {
+        return Err(AxumError::bad_request(eyre::eyre!(
+            "This application requires pushed authorization requests"
+        )));
+    }
+
+    let (code_challenge_method, prompt) = validate_authorization_request(&app, &params)?;
+
+    let scopes = filter_scopes(&app, params.scope.as_deref().unwrap_or_default());
+
+    // Check user is authenticated, recently enough and as the expected user
+    let user_id = authenticated_user(&session).await?;
+    let authentication = session_authentication(&session).await?;
+
+    let mut login_required = user_id.is_none();
+
+    // Offer to switch accounts once, the user may still continue as themselves
+    let other_user_hinted = match (&user_id, &params.login_hint) {
+        (Some(user_id), Some(login_hint)) => get_user(&state.database, login_hint)
+            .await
+            .wrap_err("Database error")?
+            .is_none_or(|user| user.id != *user_id),
+        _ => false,
+    };
+
+    let stale = params.max_age.is_some_and(|max_age| {
+        !authentication
+            .as_ref()
+            .is_some_and(|authentication| authentication.is_fresh(max_age))
+    });
+    let request_key = authorization_request_key(&params);
+    if prompt.login || prompt.select_account || stale || other_user_hinted {
+        login_required |= prompt.none
+            || !logged_in_since_request(&session, &request_key, authentication.as_ref()).await?;
+    } else if !login_required {
+        forget_login_request(&session, &request_key).await?;
+    }
+
+    let Some(user_id) = user_id.filter(|_| !login_required) else {
+        if prompt.none {
+            let redirect_url = client_redirect_url(
+                &params.redirect_uri,
+                &[("error", "login_required")],
+                params.state.as_deref(),
+            );
+            return Ok(Json(authorize_info(
+                app,
+                params,
+                scopes,
+                code_challenge_method,
+                Some(redirect_url),
+            )));
+        }
         return Err(AxumError::unauthorized(eyre::eyre!(
             "Login required. Redirect to login page first."
         )));
+    };
+
+    // Approving the request has to reach this level too, whatever the browser sends back
+    let approval_key = approval_key(
+        &params.client_id,
+        &params.redirect_uri,
+        params.state.as_deref(),
+        params.nonce.as_deref(),
+        params.code_challenge.as_deref(),
+        params.request_uri.as_deref(),
+    );
+    remember_required_level(
+        &session,
+        &approval_key,
+        required_security_level(&app, params.acr_values.as_deref()),
+    )
+    .await?;
+
+    if let Some(step_up) = step_up_requirement(
+        &state,
+        &session,
+        &user_id,
+        &app,
+        params.acr_values.as_deref(),
+    )
+    .await?
+    {
+        if prompt.none {
+            let redirect_url = client_redirect_url(
+                &params.redirect_uri,
+                &[("error", "interaction_required")],
+                params.state.as_deref(),
+            );
+            return Ok(Json(authorize_info(
+                app,
+                params,
+                scopes,
+                code_challenge_method,
+                Some(redirect_url),
+            )));
+        }
+
+        return Ok(Json(AuthorizeInfo {
+            step_up: Some(step_up),
+            ..authorize_info(app, params, scopes, code_challenge_method, None)
+        }));
+    }
+
+    let consent_given = !prompt.consent
+        && (app.trusted || has_consent(&state.database, &user_id, &app.client_id, &scopes).await?);
+
+    let redirect_url = if consent_given {
+        check_application_access(&state, &app, &user_id).await?;
+
+        let request = ApprovedAuthorization {
+            app: &app,
+            redirect_uri: &params.redirect_uri,
+            scopes: &scopes,
+            state: params.state.as_deref(),
+            nonce: params.nonce.clone(),
+            code_challenge: params.code_challenge.clone(),
+            code_challenge_method,
+            request_uri: params.request_uri.as_deref(),
+        };
+        Some(issue_authorization_code(&state, &session, &user_id, request).await?)
+    } else if prompt.none {
+        Some(client_redirect_url(
+            &params.redirect_uri,
+            &[("error", "consent_required")],
+            params.state.as_deref(),
+        ))
+    } else {
+        None
+    };
+
+    Ok(Json(authorize_info(
+        app,
+        params,
+        scopes,
+        code_challenge_method,
+        redirect_url,
+    )))
+}
+
+fn authorize_info(
+    app: Application,
+    params: AuthorizeQuery,
+    scopes: Vec<String>,
+    code_challenge_method: Option<CodeChallengeMethod>,
+    redirect_url: Option<String>,
+) -> AuthorizeInfo {
+    AuthorizeInfo {
+        custom_scopes: requested_custom_scopes(&app, &scopes),
+        app_name: app.name,
+        app_icon: app.icon,
+        scopes,
+        client_id: params.client_id,
+        redirect_uri: params.redirect_uri,
+        state: params.state,
+        nonce: params.nonce,
+        code_challenge: params.code_challenge,
+        code_challenge_method,
+        redirect_url,
+        step_up: None,
+        request_uri: params.request_uri,
     }
+}
+
+/// Identifies an authorization request across the trip to the login page, see [`logged_in_since_request`].
+fn authorization_request_key(params: &AuthorizeQuery) -> String {
+    let parameters = serde_urlencoded::to_string(params).unwrap_or_default();
+
+    hash_token(&parameters)
+}
+
+/// Identifies an authorization request between loading and approving it, see [`remember_required_level`].
+/// Made of the parameters the approval has to send back unchanged.
+fn approval_key(
+    client_id: &str,
+    redirect_uri: &str,
+    state: Option<&str>,
+    nonce: Option<&str>,
+    code_challenge: Option<&str>,
+    request_uri: Option<&str>,
+) -> String {
+    let parameters = serde_urlencoded::to_string([
+        ("client_id", Some(client_id)),
+        ("redirect_uri", Some(redirect_uri)),
+        ("state", state),
+        ("nonce", nonce),
+        ("code_challenge", code_challenge),
+        ("request_uri", request_uri),
+    ])
+    .unwrap_or_default();
+
+    hash_token(&parameters)
+}
 
-    // Validate response_type
+/// Checks the parameters of an authorization request against the application.
+/// Returns the effective PKCE method and the parsed `prompt`.
+fn validate_authorization_request(
+    app: &Application,
+    params: &AuthorizeQuery,
+) -> AxumResult<(Option<CodeChallengeMethod>, Prompt)> {
     if params.response_type != "code" {
         return Err(AxumError::bad_request(eyre::eyre!(
             "Unsupported response_type. Only 'code' is supported."
         )));
     }
 
-    // Look up application by client_id
+    if !app.redirect_uris.contains(&params.redirect_uri) {
+        return Err(AxumError::bad_request(eyre::eyre!(
+            "Invalid redirect_uri for this application"
+        )));
+    }
+
+    let code_challenge_method = validate_code_challenge(
+        app,
+        params.code_challenge.as_deref(),
+        params.code_challenge_method.as_deref(),
+    )?;
+
+    let prompt = Prompt::parse(params.prompt.as_deref().unwrap_or_default()).ok_or_else(|| {
+        AxumError::bad_request(eyre::eyre!(
+            "prompt=none can't be combined with other values"
+        ))
+    })?;
+
+    Ok((code_challenge_method, prompt))
+}
+
+/// Loads the parameters a client pushed for `request_uri`, see [`crate::oidc::par`].
+async fn resolve_pushed_request(
+    state: &AppState,
+    client_id: &str,
+    request_uri: String,
+) -> AxumResult<AuthorizeQuery> {
+    let pushed = find_pushed_request(&state.database, &request_uri, client_id)
+        .await?
+        .ok_or_else(|| AxumError::bad_request(eyre::eyre!("Invalid or expired request_uri")))?;
+
+    let mut params: AuthorizeQuery = serde_urlencoded::from_str(&pushed.parameters)
+        .wrap_err("Invalid pushed authorization request")?;
+    params.request_uri = Some(request_uri);
+
+    Ok(params)
+}
+
+/// Applies the request object passed in `request` or fetched from `request_uri` to the
+/// parameters of the `query` string.
+async fn resolve_request_object(
+    state: &AppState,
+    params: AuthorizeQuery,
+    query: &str,
+) -> AxumResult<AuthorizeQuery> {
     let app = state
         .database
         .collection::<Application>("applications")
@@ -135,36 +485,168 @@ async fn authorize_get(
         .wrap_err("Database error")?
         .ok_or_else(|| AxumError::bad_request(eyre::eyre!("Unknown client_id")))?;
 
-    // Validate redirect_uri
-    if !app.redirect_uris.contains(&params.redirect_uri) {
-        return Err(AxumError::bad_request(eyre::eyre!(
-            "Invalid redirect_uri for this application"
+    let request_object = match (params.request, params.request_uri) {
+        (Some(request), None) => request,
+        (None, Some(request_uri)) => fetch_request_object(state, &app, &request_uri)
+            .await
+            .map_err(AxumError::bad_request)?,
+        _ => {
+            return Err(AxumError::bad_request(eyre::eyre!(
+                "request and request_uri can't be used together"
+            )));
+        }
+    };
+
+    let public_url = state.settings.general.public_url.to_string();
+    let claims = verify_request_object(
+        state,
+        &app,
+        public_url.trim_end_matches('/'),
+        &request_object,
+    )
+    .await
+    .map_err(AxumError::bad_request)?;
+
+    let parameters: Vec<(String, String)> =
+        serde_urlencoded::from_str(query).wrap_err("Invalid query string")?;
+    let parameters = serde_urlencoded::to_string(merge_request_object(parameters, claims))
+        .wrap_err("Invalid request object")?;
+
+    serde_urlencoded::from_str(&parameters)
+        .map_err(|error| AxumError::bad_request(eyre::eyre!("Invalid request object: {error}")))
+}
+
+/// Level the sign-in has to reach: the application's minimum or what the client asked for, whichever is higher.
+fn required_security_level(app: &Application, acr_values: Option<&str>) -> Option<SecurityLevel> {
+    app.min_security_level
+        .max(acr_values.and_then(requested_security_level))
+}
+
+fn reaches_security_level(
+    authentication: Option<&SessionAuthentication>,
+    required: SecurityLevel,
+) -> bool {
+    authentication
+        .and_then(SessionAuthentication::security_level)
+        .is_some_and(|level| level >= required)
+}
+
+/// Checks the sign-in against the level the application and client require.
+/// Returns the factors the user can step up with, or `None` if the sign-in is strong enough.
+async fn step_up_requirement(
+    state: &AppState,
+    session: &Session,
+    user_id: &ObjectId,
+    app: &Application,
+    acr_values: Option<&str>,
+) -> AxumResult<Option<StepUpRequirement>> {
+    let authentication = session_authentication(session).await?;
+
+    let Some(required) = required_security_level(app, acr_values)
+        .filter(|required| !reaches_security_level(authentication.as_ref(), *required))
+    else {
+        clear_step_up(session).await?;
+        return Ok(None);
+    };
+
+    let user = get_user_by_id(&state.database, user_id)
+        .await
+        .wrap_err("Database error")?
+        .ok_or_else(|| AxumError::bad_request(eyre::eyre!("User not found")))?;
+
+    let factors = get_second_factors(&user)
+        .into_iter()
+        .filter(|factor| factor_security_level(&factor.clone().into()) >= required)
+        .collect::<Vec<_>>();
+
+    if factors.is_empty() {
+        return Err(AxumError::forbidden(eyre::eyre!(
+            "This application requires a stronger sign-in method than you have set up"
         )));
     }
 
-    // Parse scopes
-    let scopes: Vec<String> = params
-        .scope
-        .unwrap_or_default()
+    request_step_up(session, required).await?;
+
+    Ok(Some(StepUpRequirement {
+        security_level: required,
+        factors,
+    }))
+}
+
+/// Fails unless the sign-in reaches the required level, for requests that can't ask the user to step up.
+async fn check_security_level(
+    session: &Session,
+    app: &Application,
+    acr_values: Option<&str>,
+) -> AxumResult<()> {
+    let authentication = session_authentication(session).await?;
+
+    if let Some(required) = required_security_level(app, acr_values)
+        && !reaches_security_level(authentication.as_ref(), required)
+    {
+        return Err(AxumError::forbidden(eyre::eyre!(
+            "This application requires a stronger sign-in"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Parses a requested scope, dropping scopes neither this server nor the application supports.
+fn filter_scopes(app: &Application, scope: &str) -> Vec<String> {
+    scope
         .split_whitespace()
+        .filter(|s| {
+            STANDARD_SCOPES.contains(s) || app.custom_scopes.iter().any(|custom| custom.name == *s)
+        })
         .map(|s| s.to_string())
-        .collect();
+        .collect()
+}
 
-    let valid_scopes = ["openid", "profile", "email", "offline_access"];
-    let filtered_scopes: Vec<String> = scopes
-        .into_iter()
-        .filter(|s| valid_scopes.contains(&s.as_str()))
-        .collect();
+/// The application's custom scopes among `scopes`, to describe them on the consent screen.
+fn requested_custom_scopes(app: &Application, scopes: &[String]) -> Vec<CustomScope> {
+    app.custom_scopes
+        .iter()
+        .filter(|custom| scopes.contains(&custom.name))
+        .cloned()
+        .collect()
+}
 
-    Ok(Json(AuthorizeInfo {
-        app_name: app.name,
-        app_icon: app.icon,
-        scopes: filtered_scopes,
-        client_id: params.client_id,
-        redirect_uri: params.redirect_uri,
-        state: params.state,
-        nonce: params.nonce,
-    }))
+/// Validates the PKCE parameters of an authorization request against the application's policy.
+/// Returns the effective challenge method, defaulting to `plain` as per RFC 7636.
+fn validate_code_challenge(
+    app: &Application,
+    code_challenge: Option<&str>,
+    code_challenge_method: Option<&str>,
+) -> AxumResult<Option<CodeChallengeMethod>> {
+    let Some(code_challenge) = code_challenge else {
+        if app.require_pkce {
+            return Err(AxumError::bad_request(eyre::eyre!(
+                "This application requires a PKCE code_challenge"
+            )));
+        }
+        if code_challenge_method.is_some() {
+            return Err(AxumError::bad_request(eyre::eyre!(
+                "code_challenge_method provided without code_challenge"
+            )));
+        }
+        return Ok(None);
+    };
+
+    let method = match code_challenge_method {
+        Some(method) => CodeChallengeMethod::parse(method).ok_or_else(|| {
+            AxumError::bad_request(eyre::eyre!("Unsupported code_challenge_method"))
+        })?,
+        None => CodeChallengeMethod::Plain,
+    };
+
+    if !pkce::is_well_formed(code_challenge) {
+        return Err(AxumError::bad_request(eyre::eyre!(
+            "Malformed code_challenge"
+        )));
+    }
+
+    Ok(Some(method))
 }
 
 // ── Authorize (POST) ─────────────────────────────────────────────
@@ -176,6 +658,32 @@ pub struct AuthorizeConsent {
     pub scope: String,
     pub state: Option<String>,
     pub nonce: Option<String>,
+    #[serde(default)]
+    pub code_challenge: Option<String>,
+    #[serde(default)]
+    pub code_challenge_method: Option<CodeChallengeMethod>,
+    /// Pushed request being approved. Its parameters replace the ones above.
+    #[serde(default)]
+    pub request_uri: Option<String>,
+}
+
+impl AuthorizeConsent {
+    /// Takes the parameters of a pushed request, leaving only the client ID and `request_uri`.
+    fn from_pushed_request(params: AuthorizeQuery) -> Self {
+        AuthorizeConsent {
+            client_id: params.client_id,
+            redirect_uri: params.redirect_uri,
+            scope: params.scope.unwrap_or_default(),
+            state: params.state,
+            nonce: params.nonce,
+            code_challenge: params.code_challenge,
+            code_challenge_method: params
+                .code_challenge_method
+                .as_deref()
+                .and_then(CodeChallengeMethod::parse),
+            request_uri: params.request_uri,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, ToSchema)]
@@ -192,6 +700,7 @@ pub struct AuthorizeResponse {
         (status = OK, description = "Authorization code issued", body = AuthorizeResponse),
         (status = BAD_REQUEST, description = "Invalid request"),
         (status = UNAUTHORIZED, description = "Not authenticated"),
+        (status = FORBIDDEN, description = "No access to the application, or the sign-in is too weak"),
     ),
     tag = "OIDC"
 )]
@@ -200,21 +709,14 @@ async fn authorize_post(
     session: Session,
     Json(body): Json<AuthorizeConsent>,
 ) -> AxumResult<Json<AuthorizeResponse>> {
-    // Check user is authenticated
-    let user_id = session
-        .get::<mongodb::bson::oid::ObjectId>("user_id")
-        .await?
-        .ok_or_else(|| AxumError::unauthorized(eyre::eyre!("Not authenticated")))?;
+    let user_id = require_authenticated_session(&session).await?;
 
-    let auth_state = session
-        .get::<crate::routes::api::AuthState>("auth_state")
-        .await?;
-    if !matches!(
-        auth_state,
-        Some(crate::routes::api::AuthState::Authenticated)
-    ) {
-        return Err(AxumError::unauthorized(eyre::eyre!("Not authenticated")));
-    }
+    let body = match body.request_uri {
+        Some(request_uri) => AuthorizeConsent::from_pushed_request(
+            resolve_pushed_request(&state, &body.client_id, request_uri).await?,
+        ),
+        None => body,
+    };
 
     // Validate application
     let app = state
@@ -225,40 +727,118 @@ async fn authorize_post(
         .wrap_err("Database error")?
         .ok_or_else(|| AxumError::bad_request(eyre::eyre!("Unknown client_id")))?;
 
+    if app.require_pushed_authorization_requests && body.request_uri.is_none() {
+        return Err(AxumError::bad_request(eyre::eyre!(
+            "This application requires pushed authorization requests"
+        )));
+    }
+
     if !app.redirect_uris.contains(&body.redirect_uri) {
         return Err(AxumError::bad_request(eyre::eyre!("Invalid redirect_uri")));
     }
 
-    // Check user group access
-    let user = state
-        .database
-        .collection::<User>("users")
-        .find_one(doc! { "_id": &user_id })
-        .await
-        .wrap_err("Database error")?
-        .ok_or_else(|| AxumError::bad_request(eyre::eyre!("User not found")))?;
+    let code_challenge_method = validate_code_challenge(
+        &app,
+        body.code_challenge.as_deref(),
+        body.code_challenge_method.map(|m| m.as_str()),
+    )?;
+
+    check_application_access(&state, &app, &user_id).await?;
+
+    let request = ApprovedAuthorization {
+        app: &app,
+        redirect_uri: &body.redirect_uri,
+        scopes: &filter_scopes(&app, &body.scope),
+        state: body.state.as_deref(),
+        nonce: body.nonce,
+        code_challenge: body.code_challenge,
+        code_challenge_method,
+        request_uri: body.request_uri.as_deref(),
+    };
+    let redirect_url = issue_authorization_code(&state, &session, &user_id, request).await?;
 
-    if !app.allowed_groups.is_empty() {
-        let has_access = user.groups.iter().any(|g| app.allowed_groups.contains(g));
-        if !has_access {
-            return Err(AxumError::forbidden(eyre::eyre!(
-                "You don't have access to this application"
-            )));
-        }
+    Ok(Json(AuthorizeResponse { redirect_url }))
+}
+
+/// Authorization request the user has consented to.
+struct ApprovedAuthorization<'a> {
+    app: &'a Application,
+    redirect_uri: &'a str,
+    scopes: &'a [String],
+    state: Option<&'a str>,
+    nonce: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<CodeChallengeMethod>,
+    /// Pushed request the code is issued for, which can't be used again.
+    request_uri: Option<&'a str>,
+}
+
+/// Remembers the user's consent and issues an authorization code.
+/// Returns the URL to send the user back to the client with.
+///
+/// The request has to have been loaded in this session, and the sign-in has to reach the level
+/// it required then.
+async fn issue_authorization_code(
+    state: &AppState,
+    session: &Session,
+    user_id: &ObjectId,
+    request: ApprovedAuthorization<'_>,
+) -> AxumResult<String> {
+    let client_id = &request.app.client_id;
+
+    let approval_key = approval_key(
+        client_id,
+        request.redirect_uri,
+        request.state,
+        request.nonce.as_deref(),
+        request.code_challenge.as_deref(),
+        request.request_uri,
+    );
+    let required = take_required_level(session, &approval_key)
+        .await?
+        .ok_or_else(|| {
+            AxumError::bad_request(eyre::eyre!(
+                "Unknown or expired authorization request, load it again"
+            ))
+        })?;
+
+    let authentication = session_authentication(session).await?;
+    if let Some(level) = request.app.min_security_level.max(required.level)
+        && !reaches_security_level(authentication.as_ref(), level)
+    {
+        return Err(AxumError::forbidden(eyre::eyre!(
+            "This application requires a stronger sign-in"
+        )));
     }
 
+    record_consent(&state.database, user_id, client_id, request.scopes).await?;
+
+    let sid = get_or_create_sid(session).await?;
+
+    let lifetimes = state
+        .settings
+        .oidc
+        .token_lifetimes
+        .for_application(request.app);
+
     // Generate authorization code
     let code = generate_reset_token(); // 64 char random string
     let code_hash = hash_token(&code);
 
     let auth_code = AuthorizationCode {
         code_hash,
-        client_id: body.client_id.clone(),
+        client_id: client_id.clone(),
         user_id: user_id.to_hex(),
-        redirect_uri: body.redirect_uri.clone(),
-        scope: body.scope.clone(),
-        nonce: body.nonce.clone(),
+        redirect_uri: request.redirect_uri.to_string(),
+        scope: request.scopes.join(" "),
+        nonce: request.nonce,
+        sid: Some(sid),
+        session_id: session.id().map(|id| id.to_string()),
+        code_challenge: request.code_challenge,
+        code_challenge_method: request.code_challenge_method,
+        authentication,
         created_at: Utc::now(),
+        expires_at: Some(Utc::now() + lifetimes.authorization_code()),
         used: false,
     };
 
@@ -269,15 +849,77 @@ async fn authorize_post(
         .await
         .wrap_err("Failed to store authorization code")?;
 
-    // Build redirect URL with code and state
-    let mut redirect_url = body.redirect_uri.clone();
-    redirect_url.push_str(if redirect_url.contains('?') { "&" } else { "?" });
-    redirect_url.push_str(&format!("code={code}"));
-    if let Some(ref st) = body.state {
-        redirect_url.push_str(&format!("&state={}", urlencoding::encode(st)));
+    if let Some(request_uri) = request.request_uri {
+        consume_pushed_request(&state.database, request_uri).await?;
     }
 
-    Ok(Json(AuthorizeResponse { redirect_url }))
+    Ok(client_redirect_url(
+        request.redirect_uri,
+        &[("code", &code)],
+        request.state,
+    ))
+}
+
+/// Appends `params` and the client's `state` to the redirect URI.
+fn client_redirect_url(redirect_uri: &str, params: &[(&str, &str)], state: Option<&str>) -> String {
+    let query = params
+        .iter()
+        .copied()
+        .chain(state.map(|state| ("state", state)))
+        .map(|(name, value)| format!("{name}={}", urlencoding::encode(value)))
+        .collect::<Vec<_>>()
+        .join("&");
+    let separator = if redirect_uri.contains('?') { '&' } else { '?' };
+
+    format!("{redirect_uri}{separator}{query}")
+}
+
+/// Returns the signed-in user, or `None` if the session hasn't completed login.
+async fn authenticated_user(session: &Session) -> AxumResult<Option<ObjectId>> {
+    let user_id = session.get::<ObjectId>("user_id").await?;
+    let auth_state = session
+        .get::<crate::routes::api::AuthState>("auth_state")
+        .await?;
+
+    Ok(user_id.filter(|_| {
+        matches!(
+            auth_state,
+            Some(crate::routes::api::AuthState::Authenticated)
+        )
+    }))
+}
+
+/// Returns the signed-in user, or fails if the session hasn't completed login.
+async fn require_authenticated_session(session: &Session) -> AxumResult<ObjectId> {
+    authenticated_user(session)
+        .await?
+        .ok_or_else(|| AxumError::unauthorized(eyre::eyre!("Not authenticated")))
+}
+
+/// Checks that the user is in one of the application's `allowed_groups`, if it restricts access.
+async fn check_application_access(
+    state: &AppState,
+    app: &Application,
+    user_id: &ObjectId,
+) -> AxumResult<User> {
+    let user = state
+        .database
+        .collection::<User>("users")
+        .find_one(doc! { "_id": user_id })
+        .await
+        .wrap_err("Database error")?
+        .ok_or_else(|| AxumError::bad_request(eyre::eyre!("User not found")))?;
+
+    if !app.allowed_groups.is_empty() {
+        let has_access = user.groups.iter().any(|g| app.allowed_groups.contains(g));
+        if !has_access {
+            return Err(AxumError::forbidden(eyre::eyre!(
+                "You don't have access to this application"
+            )));
+        }
+    }
+
+    Ok(user)
 }
 
 // ── Token ────────────────────────────────────────────────────────
@@ -289,7 +931,20 @@ pub struct TokenRequest {
     pub redirect_uri: Option<String>,
     pub client_id: Option<String>,
     pub client_secret: Option<String>,
+    pub client_assertion_type: Option<String>,
+    pub client_assertion: Option<String>,
     pub refresh_token: Option<String>,
+    pub code_verifier: Option<String>,
+    pub scope: Option<String>,
+    pub audience: Option<String>,
+    pub device_code: Option<String>,
+    /// Token exchange (RFC 8693) parameters. `resource` is accepted in place of `audience`.
+    pub resource: Option<String>,
+    pub subject_token: Option<String>,
+    pub subject_token_type: Option<String>,
+    pub actor_token: Option<String>,
+    pub actor_token_type: Option<String>,
+    pub requested_token_type: Option<String>,
 }
 
 #[derive(Debug, Serialize, ToSchema)]
@@ -302,6 +957,9 @@ pub struct TokenResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub id_token: Option<String>,
     pub scope: String,
+    /// Type of the issued token, only set for token exchange.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issued_token_type: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -326,29 +984,87 @@ async fn token(
     axum::Form(body): axum::Form<TokenRequest>,
 ) -> Result<Json<TokenResponse>, axum::response::Response> {
     // Extract client credentials from Basic auth header or body
-    let (client_id, client_secret) = extract_client_credentials(&headers, &body);
+    let (client_id, credential) = extract_client_credentials(
+        &headers,
+        body.client_id.as_deref(),
+        body.client_secret.as_deref(),
+        body.client_assertion_type.as_deref(),
+        body.client_assertion.as_deref(),
+    );
+
+    // Tokens are bound to the key of a DPoP proof sent along
+    let dpop_jkt = dpop_key(&state, &headers, "POST", "token", None)
+        .await
+        .map_err(|error| {
+            token_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_dpop_proof",
+                &error.to_string(),
+            )
+        })?;
+    let dpop_jkt = dpop_jkt.as_deref();
 
     match body.grant_type.as_str() {
         "authorization_code" => {
-            handle_authorization_code_grant(&state, &body, &client_id, &client_secret).await
+            handle_authorization_code_grant(&state, &body, &client_id, &credential, dpop_jkt).await
         }
         "refresh_token" => {
-            handle_refresh_token_grant(&state, &body, &client_id, &client_secret).await
+            handle_refresh_token_grant(&state, &body, &client_id, &credential, dpop_jkt).await
+        }
+        "client_credentials" => {
+            handle_client_credentials_grant(&state, &body, &client_id, &credential, dpop_jkt).await
+        }
+        DEVICE_CODE_GRANT_TYPE => {
+            handle_device_code_grant(&state, &body, &client_id, &credential, dpop_jkt).await
+        }
+        TOKEN_EXCHANGE_GRANT_TYPE => {
+            handle_token_exchange_grant(&state, &body, &client_id, &credential, dpop_jkt).await
         }
         _ => Err(token_error(
             StatusCode::BAD_REQUEST,
             "unsupported_grant_type",
-            "Only authorization_code and refresh_token are supported",
+            "Only authorization_code, refresh_token, client_credentials, device_code and token-exchange are supported",
         )),
     }
 }
 
-fn extract_client_credentials(
+/// Verifies the `DPoP` header of a request to an OIDC `endpoint` and returns the thumbprint of
+/// the proof key. `None` without a proof.
+async fn dpop_key(
+    state: &AppState,
     headers: &HeaderMap,
-    body: &TokenRequest,
-) -> (Option<String>, Option<String>) {
-    // Try Basic auth first
-    if let Some(auth) = headers.get("authorization")
+    method: &str,
+    endpoint: &str,
+    access_token: Option<&str>,
+) -> eyre::Result<Option<String>> {
+    let mut proofs = headers.get_all("dpop").iter();
+    let Some(proof) = proofs.next() else {
+        return Ok(None);
+    };
+    if proofs.next().is_some() {
+        eyre::bail!("Only one DPoP proof may be sent");
+    }
+
+    let proof = proof.to_str().wrap_err("Malformed DPoP proof")?;
+    let issuer = state.settings.general.public_url.to_string();
+    let url = format!("{}/api/oidc/{endpoint}", issuer.trim_end_matches('/'));
+
+    verify_dpop_proof(&state.redis_pool, proof, method, &url, access_token)
+        .await
+        .map(Some)
+}
+
+/// Reads client credentials from the HTTP Basic `Authorization` header, falling back to a
+/// `client_assertion` or the `client_id`/`client_secret` form parameters.
+fn extract_client_credentials(
+    headers: &HeaderMap,
+    body_client_id: Option<&str>,
+    body_client_secret: Option<&str>,
+    client_assertion_type: Option<&str>,
+    client_assertion: Option<&str>,
+) -> (Option<String>, ClientCredential) {
+    // Try Basic auth first
+    if let Some(auth) = headers.get("authorization")
         && let Ok(auth_str) = auth.to_str()
         && let Some(basic) = auth_str.strip_prefix("Basic ")
         && let Ok(decoded) =
@@ -356,18 +1072,86 @@ fn extract_client_credentials(
         && let Ok(creds) = String::from_utf8(decoded)
         && let Some((id, secret)) = creds.split_once(':')
     {
-        return (Some(id.to_string()), Some(secret.to_string()));
+        return (
+            Some(id.to_string()),
+            secret_credential(secret, TokenEndpointAuthMethod::ClientSecretBasic),
+        );
+    }
+
+    if let Some(assertion) = client_assertion {
+        let client_id = body_client_id
+            .map(str::to_string)
+            .or_else(|| client_assertion_subject(assertion));
+
+        return (
+            client_id,
+            ClientCredential::Assertion {
+                assertion_type: client_assertion_type.unwrap_or_default().to_string(),
+                assertion: assertion.to_string(),
+            },
+        );
     }
 
     // Fall back to body parameters
-    (body.client_id.clone(), body.client_secret.clone())
+    (
+        body_client_id.map(str::to_string),
+        body_client_secret.map_or(ClientCredential::None, |secret| {
+            secret_credential(secret, TokenEndpointAuthMethod::ClientSecretPost)
+        }),
+    )
+}
+
+/// Some libraries send an empty secret for public clients, which is the same as none.
+fn secret_credential(secret: &str, method: TokenEndpointAuthMethod) -> ClientCredential {
+    if secret.is_empty() {
+        return ClientCredential::None;
+    }
+
+    ClientCredential::Secret {
+        secret: secret.to_string(),
+        method,
+    }
+}
+
+/// Looks up the application and checks how the client authenticated, see [`verify_client_credential`].
+async fn authenticate_client(
+    state: &AppState,
+    client_id: &str,
+    credential: &ClientCredential,
+) -> Result<Application, axum::response::Response> {
+    let app = state
+        .database
+        .collection::<Application>("applications")
+        .find_one(doc! { "client_id": client_id })
+        .await
+        .map_err(|_| {
+            token_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                "Database error",
+            )
+        })?
+        .ok_or_else(|| token_error(StatusCode::BAD_REQUEST, "invalid_client", "Unknown client"))?;
+
+    verify_client_credential(state, &app, credential)
+        .await
+        .map_err(|error| {
+            token_error(
+                StatusCode::UNAUTHORIZED,
+                "invalid_client",
+                &error.to_string(),
+            )
+        })?;
+
+    Ok(app)
 }
 
 async fn handle_authorization_code_grant(
     state: &AppState,
     body: &TokenRequest,
     client_id: &Option<String>,
-    client_secret: &Option<String>,
+    credential: &ClientCredential,
+    dpop_jkt: Option<&str>,
 ) -> Result<Json<TokenResponse>, axum::response::Response> {
     let code = body
         .code
@@ -395,7 +1179,12 @@ async fn handle_authorization_code_grant(
     let auth_code = state
         .database
         .collection::<AuthorizationCode>("authorization_codes")
-        .find_one(doc! { "code_hash": &code_hash, "used": false })
+        // Claimed before anything else, so concurrent requests can't both redeem the code.
+        // A failed attempt spends it too.
+        .find_one_and_update(
+            doc! { "code_hash": &code_hash, "used": false },
+            doc! { "$set": { "used": true } },
+        )
         .await
         .map_err(|_| {
             token_error(
@@ -412,19 +1201,11 @@ async fn handle_authorization_code_grant(
             )
         })?;
 
-    // Validate code hasn't expired (10 minute lifetime)
-    let age = Utc::now() - auth_code.created_at;
-    if age.num_minutes() > 10 {
-        // Mark as used to prevent replay
-        let _ = state
-            .database
-            .collection::<AuthorizationCode>("authorization_codes")
-            .update_one(
-                doc! { "code_hash": &code_hash },
-                doc! { "$set": { "used": true } },
-            )
-            .await;
-
+    // Validate code hasn't expired. Codes issued before lifetimes were stored last 10 minutes.
+    let expires_at = auth_code
+        .expires_at
+        .unwrap_or(auth_code.created_at + chrono::Duration::minutes(10));
+    if expires_at < Utc::now() {
         return Err(token_error(
             StatusCode::BAD_REQUEST,
             "invalid_grant",
@@ -448,64 +1229,111 @@ async fn handle_authorization_code_grant(
         ));
     }
 
-    // Validate client_secret for confidential clients
-    let app = state
-        .database
-        .collection::<Application>("applications")
-        .find_one(doc! { "client_id": client_id })
+    // Verify the PKCE code_verifier against the challenge stored with the code
+    match (&auth_code.code_challenge, &body.code_verifier) {
+        (Some(challenge), Some(verifier)) => {
+            let method = auth_code
+                .code_challenge_method
+                .unwrap_or(CodeChallengeMethod::Plain);
+            if !pkce::verify_code_verifier(verifier, challenge, method) {
+                return Err(token_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_grant",
+                    "PKCE verification failed",
+                ));
+            }
+        }
+        // RFC 7636, section 4.6
+        (Some(_), None) => {
+            return Err(token_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+                "Missing code_verifier",
+            ));
+        }
+        (None, Some(_)) => {
+            return Err(token_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+                "code_verifier provided but no code_challenge was used",
+            ));
+        }
+        (None, None) => {}
+    }
+
+    // Authenticate confidential clients
+    let app = authenticate_client(state, client_id, credential).await?;
+
+    issue_user_tokens(
+        state,
+        &app,
+        UserGrant {
+            user_id: &auth_code.user_id,
+            scope: &auth_code.scope,
+            nonce: auth_code.nonce.as_deref(),
+            sid: auth_code.sid.as_deref(),
+            session_id: auth_code.session_id.as_deref(),
+            authentication: auth_code.authentication.as_ref(),
+            dpop_jkt,
+        },
+    )
+    .await
+}
+
+/// What a user authorized a client for, from an authorization code or a device code.
+struct UserGrant<'a> {
+    user_id: &'a str,
+    scope: &'a str,
+    nonce: Option<&'a str>,
+    sid: Option<&'a str>,
+    session_id: Option<&'a str>,
+    authentication: Option<&'a SessionAuthentication>,
+    dpop_jkt: Option<&'a str>,
+}
+
+/// Scope-dependent claims for a user's tokens, see [`extra_claims`].
+async fn user_extra_claims(
+    state: &AppState,
+    app: &Application,
+    user: &User,
+    scopes: &[&str],
+) -> Result<serde_json::Map<String, serde_json::Value>, axum::response::Response> {
+    extra_claims(&state.database, &state.settings.oidc, app, user, scopes)
         .await
         .map_err(|_| {
             token_error(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "server_error",
-                "Database error",
-            )
-        })?
-        .ok_or_else(|| token_error(StatusCode::BAD_REQUEST, "invalid_client", "Unknown client"))?;
-
-    if matches!(app.client_type, crate::database::ClientType::Confidential) {
-        let expected_secret = app.client_secret.as_ref().ok_or_else(|| {
-            token_error(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "server_error",
-                "Confidential client has no secret",
-            )
-        })?;
-        let provided_secret = client_secret.as_ref().ok_or_else(|| {
-            token_error(
-                StatusCode::UNAUTHORIZED,
-                "invalid_client",
-                "Client secret required",
+                "Failed to build claims",
             )
-        })?;
-        if provided_secret != expected_secret {
-            return Err(token_error(
-                StatusCode::UNAUTHORIZED,
-                "invalid_client",
-                "Invalid client secret",
-            ));
-        }
-    }
+        })
+}
 
-    // Mark code as used
-    state
-        .database
-        .collection::<AuthorizationCode>("authorization_codes")
-        .update_one(
-            doc! { "code_hash": &code_hash },
-            doc! { "$set": { "used": true } },
-        )
+/// `sub` of the user's tokens for the application, see [`issue_subject_identifier`].
+async fn user_subject(
+    state: &AppState,
+    app: &Application,
+    user: &User,
+) -> Result<String, axum::response::Response> {
+    issue_subject_identifier(state, app, user)
         .await
         .map_err(|_| {
             token_error(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "server_error",
-                "Failed to invalidate code",
+                "Failed to store subject identifier",
             )
-        })?;
+        })
+}
 
+/// Issues the access token, and the ID and refresh tokens if the scope asks for them.
+async fn issue_user_tokens(
+    state: &AppState,
+    app: &Application,
+    grant: UserGrant<'_>,
+) -> Result<Json<TokenResponse>, axum::response::Response> {
     // Get user for claims
-    let user_oid = mongodb::bson::oid::ObjectId::parse_str(&auth_code.user_id).map_err(|_| {
+    let user_oid = mongodb::bson::oid::ObjectId::parse_str(grant.user_id).map_err(|_| {
         token_error(
             StatusCode::INTERNAL_SERVER_ERROR,
             "server_error",
@@ -536,17 +1364,23 @@ async fn handle_authorization_code_grant(
         .to_string();
 
     let now = Utc::now().timestamp() as usize;
-    let scopes: Vec<&str> = auth_code.scope.split_whitespace().collect();
+    let scopes: Vec<&str> = grant.scope.split_whitespace().collect();
+    let extra_claims = user_extra_claims(state, app, &user, &scopes).await?;
+    let subject = user_subject(state, app, &user).await?;
+    let lifetimes = state.settings.oidc.token_lifetimes.for_application(app);
 
-    // Build access token (1 hour)
     let access_claims = AccessTokenClaims {
         iss: issuer.clone(),
-        sub: user.uuid.to_string(),
-        aud: client_id.clone(),
-        exp: now + 3600,
+        sub: subject.clone(),
+        aud: app.client_id.clone(),
+        exp: now + lifetimes.access_token_secs as usize,
         iat: now,
-        scope: auth_code.scope.clone(),
-        client_id: client_id.clone(),
+        scope: grant.scope.to_string(),
+        client_id: app.client_id.clone(),
+        jti: uuid::Uuid::new_v4().to_string(),
+        act: None,
+        cnf: ConfirmationClaim::for_key(grant.dpop_jkt),
+        extra: extra_claims.clone(),
     };
     let access_token = state
         .oidc_keys
@@ -570,7 +1404,7 @@ async fn handle_authorization_code_grant(
         })?;
 
         let mut standard_claims =
-            openidconnect::StandardClaims::new(SubjectIdentifier::new(user.uuid.to_string()));
+            openidconnect::StandardClaims::new(SubjectIdentifier::new(subject.clone()));
 
         if scopes.contains(&"profile") {
             standard_claims = standard_claims
@@ -591,22 +1425,48 @@ async fn handle_authorization_code_grant(
                 .set_email_verified(Some(user.email_confirmed));
         }
 
-        let id_claims = CoreIdTokenClaims::new(
+        let id_claims = IdTokenClaims::new(
             issuer_url,
-            vec![Audience::new(client_id.clone())],
-            Utc::now() + chrono::Duration::hours(1),
+            vec![Audience::new(app.client_id.clone())],
+            Utc::now() + lifetimes.id_token(),
             Utc::now(),
             standard_claims,
-            EmptyAdditionalClaims {},
+            ExtraIdTokenClaims {
+                sid: grant.sid.map(str::to_string),
+                extra: extra_claims,
+            },
+        )
+        .set_nonce(grant.nonce.map(|n| Nonce::new(n.to_string())))
+        .set_auth_time(
+            grant
+                .authentication
+                .and_then(|authentication| DateTime::from_timestamp(authentication.auth_time, 0)),
         )
-        .set_nonce(auth_code.nonce.as_ref().map(|n| Nonce::new(n.clone())));
+        .set_auth_context_ref(
+            grant
+                .authentication
+                .and_then(SessionAuthentication::acr)
+                .map(|acr| AuthenticationContextClass::new(acr.to_string())),
+        )
+        .set_auth_method_refs(
+            grant
+                .authentication
+                .map(SessionAuthentication::amr)
+                .filter(|amr| !amr.is_empty())
+                .map(|amr| {
+                    amr.into_iter()
+                        .map(AuthenticationMethodReference::new)
+                        .collect()
+                }),
+        );
 
         let access_token_obj = AccessToken::new(access_token.clone());
 
-        let signed_id_token = CoreIdToken::new(
+        let signing_key = state.oidc_keys.active_key(app.id_token_signed_response_alg);
+        let signed_id_token = IdToken::new(
             id_claims,
-            &state.oidc_keys.signing_key,
-            CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256,
+            signing_key.as_ref(),
+            signing_key.algorithm.jws_algorithm(),
             Some(&access_token_obj),
             None,
         )
@@ -630,11 +1490,19 @@ async fn handle_authorization_code_grant(
 
         let rt = RefreshToken {
             token_hash,
-            client_id: client_id.clone(),
-            user_id: auth_code.user_id.clone(),
-            scope: auth_code.scope.clone(),
+            client_id: app.client_id.clone(),
+            user_id: grant.user_id.to_string(),
+            scope: grant.scope.to_string(),
+            grant_id: Some(uuid::Uuid::new_v4().to_string()),
+            grant_created_at: Some(Utc::now()),
+            session_id: grant.session_id.map(str::to_string),
             created_at: Utc::now(),
             revoked: false,
+            rotated: false,
+            // Confidential clients authenticate when refreshing, which is binding enough
+            dpop_jkt: matches!(app.client_type, crate::database::ClientType::Public)
+                .then(|| grant.dpop_jkt.map(str::to_string))
+                .flatten(),
         };
 
         state
@@ -655,21 +1523,47 @@ async fn handle_authorization_code_grant(
         None
     };
 
+    // Remember which clients hold tokens for this SSO session, for back-channel logout
+    if let Some(sid) = grant.sid
+        && let Err(error) = record_client_session(
+            state,
+            ClientSession {
+                sid: sid.to_string(),
+                session_id: grant.session_id.map(str::to_string),
+                user_id: user.id,
+                subject,
+                client_id: app.client_id.clone(),
+                created_at: mongodb::bson::DateTime::now(),
+            },
+        )
+        .await
+    {
+        warn!(error = ?error, "Failed to record client session");
+    }
+
     Ok(Json(TokenResponse {
         access_token,
-        token_type: "Bearer".to_string(),
-        expires_in: 3600,
+        token_type: access_token_type(access_claims.cnf.as_ref()).to_string(),
+        expires_in: lifetimes.access_token_secs.into(),
         refresh_token,
         id_token,
-        scope: auth_code.scope,
+        scope: grant.scope.to_string(),
+        issued_token_type: None,
     }))
 }
 
+async fn reject_reused_refresh_token(state: &AppState, token: &RefreshToken) {
+    if let Err(e) = handle_refresh_token_reuse(state, token).await {
+        warn!(error = ?e, "Failed to revoke reused refresh token grant");
+    }
+}
+
 async fn handle_refresh_token_grant(
     state: &AppState,
     body: &TokenRequest,
     client_id: &Option<String>,
-    client_secret: &Option<String>,
+    credential: &ClientCredential,
+    dpop_jkt: Option<&str>,
 ) -> Result<Json<TokenResponse>, axum::response::Response> {
     let raw_token = body.refresh_token.as_ref().ok_or_else(|| {
         token_error(
@@ -683,7 +1577,7 @@ async fn handle_refresh_token_grant(
     let stored = state
         .database
         .collection::<RefreshToken>("refresh_tokens")
-        .find_one(doc! { "token_hash": &token_hash, "revoked": false })
+        .find_one(doc! { "token_hash": &token_hash })
         .await
         .map_err(|_| {
             token_error(
@@ -700,24 +1594,6 @@ async fn handle_refresh_token_grant(
             )
         })?;
 
-    // Validate refresh token age (30 days max)
-    let age = Utc::now() - stored.created_at;
-    if age.num_days() > 30 {
-        let _ = state
-            .database
-            .collection::<RefreshToken>("refresh_tokens")
-            .update_one(
-                doc! { "token_hash": &token_hash },
-                doc! { "$set": { "revoked": true } },
-            )
-            .await;
-        return Err(token_error(
-            StatusCode::BAD_REQUEST,
-            "invalid_grant",
-            "Refresh token expired",
-        ));
-    }
-
     // Validate client
     let req_client_id = client_id.as_ref().ok_or_else(|| {
         token_error(
@@ -735,11 +1611,25 @@ async fn handle_refresh_token_grant(
         ));
     }
 
-    // Validate client_secret for confidential clients
-    let app = state
-        .database
-        .collection::<Application>("applications")
-        .find_one(doc! { "client_id": req_client_id })
+    // Authenticate confidential clients
+    let app = authenticate_client(state, req_client_id, credential).await?;
+
+    // Only the owning client may trigger reuse detection, or anyone holding an old token
+    // could revoke the grant
+    if stored.revoked {
+        if stored.rotated {
+            reject_reused_refresh_token(state, &stored).await;
+        }
+        return Err(token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_grant",
+            "Invalid or revoked refresh token",
+        ));
+    }
+
+    let lifetimes = state.settings.oidc.token_lifetimes.for_application(&app);
+    let expired = lifetimes
+        .is_refresh_token_expired(&state.database, &stored)
         .await
         .map_err(|_| {
             token_error(
@@ -747,31 +1637,34 @@ async fn handle_refresh_token_grant(
                 "server_error",
                 "Database error",
             )
-        })?
-        .ok_or_else(|| token_error(StatusCode::BAD_REQUEST, "invalid_client", "Unknown client"))?;
-
-    if matches!(app.client_type, crate::database::ClientType::Confidential) {
-        let expected = app.client_secret.as_ref().ok_or_else(|| {
-            token_error(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "server_error",
-                "Confidential client has no secret",
-            )
         })?;
-        let provided = client_secret.as_ref().ok_or_else(|| {
-            token_error(
-                StatusCode::UNAUTHORIZED,
-                "invalid_client",
-                "Client secret required",
+    if expired {
+        if let Err(e) = state
+            .database
+            .collection::<RefreshToken>("refresh_tokens")
+            .update_one(
+                doc! { "token_hash": &token_hash },
+                doc! { "$set": { "revoked": true } },
             )
-        })?;
-        if provided != expected {
-            return Err(token_error(
-                StatusCode::UNAUTHORIZED,
-                "invalid_client",
-                "Invalid client secret",
-            ));
+            .await
+        {
+            warn!(error = ?e, "Failed to revoke expired refresh token");
         }
+        return Err(token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_grant",
+            "Refresh token expired",
+        ));
+    }
+
+    if let Some(bound_jkt) = &stored.dpop_jkt
+        && dpop_jkt != Some(bound_jkt.as_str())
+    {
+        return Err(token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_dpop_proof",
+            "The refresh token is bound to a different DPoP key",
+        ));
     }
 
     // Get user
@@ -805,15 +1698,20 @@ async fn handle_refresh_token_grant(
         .trim_end_matches('/')
         .to_string();
     let now = Utc::now().timestamp() as usize;
+    let scopes: Vec<&str> = stored.scope.split_whitespace().collect();
 
     let access_claims = AccessTokenClaims {
         iss: issuer,
-        sub: user.uuid.to_string(),
+        sub: user_subject(state, &app, &user).await?,
         aud: req_client_id.clone(),
-        exp: now + 3600,
+        exp: now + lifetimes.access_token_secs as usize,
         iat: now,
         scope: stored.scope.clone(),
         client_id: req_client_id.clone(),
+        jti: uuid::Uuid::new_v4().to_string(),
+        act: None,
+        cnf: ConfirmationClaim::for_key(dpop_jkt),
+        extra: user_extra_claims(state, &app, &user, &scopes).await?,
     };
 
     let access_token = state
@@ -827,118 +1725,764 @@ async fn handle_refresh_token_grant(
             )
         })?;
 
-    // Revoke the used refresh token and issue a new one (rotation)
-    let _ = state
-        .database
-        .collection::<RefreshToken>("refresh_tokens")
-        .update_one(
-            doc! { "token_hash": &token_hash },
-            doc! { "$set": { "revoked": true } },
-        )
-        .await;
-
+    // Store the new refresh token before revoking the used one (rotation), so a failed write
+    // can't hand out a token that doesn't exist
     let raw_new_token = generate_reset_token();
     let new_token_hash = hash_token(&raw_new_token);
     let new_refresh_token = RefreshToken {
-        token_hash: new_token_hash,
-        user_id: stored.user_id,
+        token_hash: new_token_hash.clone(),
+        user_id: stored.user_id.clone(),
         client_id: req_client_id.clone(),
         scope: stored.scope.clone(),
+        grant_id: stored.grant_id.clone(),
+        grant_created_at: stored.grant_created_at.or(Some(stored.created_at)),
+        session_id: stored.session_id.clone(),
         created_at: Utc::now(),
         revoked: false,
+        rotated: false,
+        dpop_jkt: stored.dpop_jkt.clone(),
     };
-    let _ = state
-        .database
-        .collection::<RefreshToken>("refresh_tokens")
+    let refresh_tokens = state.database.collection::<RefreshToken>("refresh_tokens");
+    refresh_tokens
         .insert_one(new_refresh_token)
+        .await
+        .map_err(|_| {
+            token_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                "Failed to store refresh token",
+            )
+        })?;
+
+    // Losing the race to a concurrent request with the same token counts as reuse
+    let rotated = refresh_tokens
+        .find_one_and_update(
+            doc! { "token_hash": &token_hash, "revoked": false },
+            doc! { "$set": { "revoked": true, "rotated": true } },
+        )
         .await;
 
+    if !matches!(rotated, Ok(Some(_))) {
+        if let Err(e) = refresh_tokens
+            .delete_one(doc! { "token_hash": &new_token_hash })
+            .await
+        {
+            warn!(error = ?e, "Failed to delete unused refresh token");
+        }
+
+        return Err(match rotated {
+            Ok(_) => {
+                reject_reused_refresh_token(state, &stored).await;
+                token_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_grant",
+                    "Invalid or revoked refresh token",
+                )
+            }
+            Err(_) => token_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                "Database error",
+            ),
+        });
+    }
+
     Ok(Json(TokenResponse {
         access_token,
-        token_type: "Bearer".to_string(),
-        expires_in: 3600,
+        token_type: access_token_type(access_claims.cnf.as_ref()).to_string(),
+        expires_in: lifetimes.access_token_secs.into(),
         refresh_token: Some(raw_new_token),
         id_token: None,
         scope: stored.scope,
+        issued_token_type: None,
     }))
 }
 
-// ── UserInfo ─────────────────────────────────────────────────────
-
-#[derive(Debug, Serialize, ToSchema)]
-pub struct UserInfoResponse {
-    pub sub: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub name: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub preferred_username: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub given_name: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub family_name: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub email: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub email_verified: Option<bool>,
-}
-
-/// OpenID Connect UserInfo endpoint
-#[utoipa::path(
-    method(get),
-    path = "/userinfo",
-    responses(
-        (status = OK, description = "User info", body = UserInfoResponse),
-        (status = UNAUTHORIZED, description = "Invalid or missing access token"),
-    ),
-    tag = "OIDC"
-)]
-async fn userinfo(
-    Extension(state): Extension<AppState>,
-    headers: HeaderMap,
-) -> Result<Json<UserInfoResponse>, axum::response::Response> {
-    // Extract Bearer token
-    let auth_header = headers
-        .get("authorization")
-        .and_then(|v| v.to_str().ok())
-        .and_then(|v| v.strip_prefix("Bearer "))
-        .ok_or_else(|| {
-            token_error(
-                StatusCode::UNAUTHORIZED,
-                "invalid_token",
-                "Missing or invalid Bearer token",
-            )
-        })?;
-
-    // Verify access token
-    let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::RS256);
-    validation.set_audience(&[""]); // We'll skip audience validation for userinfo
-    validation.validate_aud = false;
-
-    let token_data = jsonwebtoken::decode::<AccessTokenClaims>(
-        auth_header,
-        &state.oidc_keys.decoding_key,
-        &validation,
-    )
-    .map_err(|e| {
+async fn handle_client_credentials_grant(
+    state: &AppState,
+    body: &TokenRequest,
+    client_id: &Option<String>,
+    credential: &ClientCredential,
+    dpop_jkt: Option<&str>,
+) -> Result<Json<TokenResponse>, axum::response::Response> {
+    let client_id = client_id.as_ref().ok_or_else(|| {
         token_error(
-            StatusCode::UNAUTHORIZED,
-            "invalid_token",
-            &format!("Token verification failed: {e}"),
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "Missing client_id",
         )
     })?;
 
-    let claims = token_data.claims;
+    let app = authenticate_client(state, client_id, credential).await?;
 
-    // Get user by UUID
-    let user_uuid = uuid::Uuid::parse_str(&claims.sub).map_err(|_| {
-        token_error(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "server_error",
-            "Invalid sub claim",
-        )
+    if !matches!(app.client_type, crate::database::ClientType::Confidential)
+        || !app.allow_client_credentials
+    {
+        return Err(token_error(
+            StatusCode::BAD_REQUEST,
+            "unauthorized_client",
+            "Client is not allowed to use the client_credentials grant",
+        ));
+    }
+
+    // Default to every allowed scope when none are requested
+    let scope = match body.scope.as_deref() {
+        Some(requested) => {
+            if let Some(scope) = requested
+                .split_whitespace()
+                .find(|scope| !app.client_credentials_scopes.iter().any(|s| s == scope))
+            {
+                return Err(token_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_scope",
+                    &format!("Scope not allowed: {scope}"),
+                ));
+            }
+            requested.split_whitespace().collect::<Vec<_>>().join(" ")
+        }
+        None => app.client_credentials_scopes.join(" "),
+    };
+
+    // Tokens are meant for the client itself unless an allowed audience is requested
+    let audience = match body.audience.as_deref() {
+        Some(audience)
+            if app
+                .client_credentials_audiences
+                .iter()
+                .any(|a| a == audience) =>
+        {
+            audience.to_string()
+        }
+        Some(_) => {
+            return Err(token_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_target",
+                "Audience not allowed",
+            ));
+        }
+        None => app
+            .client_credentials_audiences
+            .first()
+            .unwrap_or(&app.client_id)
+            .clone(),
+    };
+
+    let issuer = state
+        .settings
+        .general
+        .public_url
+        .to_string()
+        .trim_end_matches('/')
+        .to_string();
+    let now = Utc::now().timestamp() as usize;
+    let lifetimes = state.settings.oidc.token_lifetimes.for_application(&app);
+
+    let access_claims = AccessTokenClaims {
+        iss: issuer,
+        sub: app.client_id.clone(),
+        aud: audience,
+        exp: now + lifetimes.access_token_secs as usize,
+        iat: now,
+        scope: scope.clone(),
+        client_id: app.client_id.clone(),
+        jti: uuid::Uuid::new_v4().to_string(),
+        act: None,
+        cnf: ConfirmationClaim::for_key(dpop_jkt),
+        extra: serde_json::Map::new(),
+    };
+
+    let access_token = state
+        .oidc_keys
+        .sign_access_token(&access_claims)
+        .map_err(|_| {
+            token_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                "Failed to sign access token",
+            )
+        })?;
+
+    Ok(Json(TokenResponse {
+        access_token,
+        token_type: access_token_type(access_claims.cnf.as_ref()).to_string(),
+        expires_in: lifetimes.access_token_secs.into(),
+        refresh_token: None,
+        id_token: None,
+        scope,
+        issued_token_type: None,
+    }))
+}
+
+/// Token passed to the token exchange grant, see [`verify_exchanged_token`].
+struct ExchangedToken {
+    sub: String,
+    /// Clients the token was issued to.
+    clients: Vec<String>,
+    /// Only set for access tokens.
+    scope: Option<String>,
+    exp: usize,
+    act: Option<ActorClaim>,
+}
+
+/// Verifies an access or ID token issued by this server for the token exchange grant.
+/// A DPoP-bound access token is only accepted from the holder of its key, so a stolen one
+/// can't be swapped for a bearer token.
+async fn verify_exchanged_token(
+    state: &AppState,
+    token: &str,
+    token_type: Option<&str>,
+    dpop_jkt: Option<&str>,
+) -> Result<ExchangedToken, axum::response::Response> {
+    let invalid_token = |_| token_error(StatusCode::BAD_REQUEST, "invalid_grant", "Invalid token");
+
+    match token_type {
+        Some(ACCESS_TOKEN_TYPE) => {
+            let claims = state
+                .oidc_keys
+                .verify_access_token(token)
+                .map_err(invalid_token)?;
+            check_exchanged_token_use(state, Some(&claims.jti), claims.cnf.as_ref(), dpop_jkt)
+                .await?;
+
+            Ok(ExchangedToken {
+                sub: claims.sub,
+                clients: vec![claims.client_id],
+                scope: Some(claims.scope),
+                exp: claims.exp,
+                act: claims.act,
+            })
+        }
+        Some(ID_TOKEN_TYPE) => {
+            let claims = state
+                .oidc_keys
+                .verify_id_token(token)
+                .map_err(invalid_token)?;
+            check_exchanged_token_use(state, claims.jti.as_deref(), claims.cnf.as_ref(), dpop_jkt)
+                .await?;
+
+            Ok(ExchangedToken {
+                sub: claims.sub,
+                clients: claims.aud.into_vec(),
+                scope: None,
+                exp: claims.exp,
+                act: None,
+            })
+        }
+        _ => Err(token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "Only access and ID tokens can be exchanged",
+        )),
+    }
+}
+
+/// Rejects an exchanged token that was revoked, or is bound to a DPoP key other than `dpop_jkt`.
+async fn check_exchanged_token_use(
+    state: &AppState,
+    jti: Option<&str>,
+    cnf: Option<&ConfirmationClaim>,
+    dpop_jkt: Option<&str>,
+) -> Result<(), axum::response::Response> {
+    if let Some(jti) = jti {
+        let revoked = is_access_token_revoked(&state.redis_pool, jti)
+            .await
+            .map_err(|_| {
+                token_error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "server_error",
+                    "Failed to check token revocation",
+                )
+            })?;
+        if revoked {
+            return Err(token_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+                "Token has been revoked",
+            ));
+        }
+    }
+
+    if let Some(cnf) = cnf
+        && dpop_jkt != Some(cnf.jkt.as_str())
+    {
+        return Err(token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_dpop_proof",
+            "The token is bound to a different DPoP key",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Token exchange grant (RFC 8693)
+///
+/// Swaps a user's token for an access token aimed at an audience the client's
+/// [`crate::oidc::token_exchange::TokenExchangePolicy`] allows, with the same or fewer scopes.
+/// With an `actor_token` issued to the client, the new token carries an `act` claim naming the actor.
+async fn handle_token_exchange_grant(
+    state: &AppState,
+    body: &TokenRequest,
+    client_id: &Option<String>,
+    credential: &ClientCredential,
+    dpop_jkt: Option<&str>,
+) -> Result<Json<TokenResponse>, axum::response::Response> {
+    let client_id = client_id.as_ref().ok_or_else(|| {
+        token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "Missing client_id",
+        )
+    })?;
+
+    let app = authenticate_client(state, client_id, credential).await?;
+
+    if !matches!(app.client_type, crate::database::ClientType::Confidential)
+        || app.token_exchange_policies.is_empty()
+    {
+        return Err(token_error(
+            StatusCode::BAD_REQUEST,
+            "unauthorized_client",
+            "Client is not allowed to use the token exchange grant",
+        ));
+    }
+
+    if body
+        .requested_token_type
+        .as_deref()
+        .is_some_and(|token_type| token_type != ACCESS_TOKEN_TYPE)
+    {
+        return Err(token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "Only access tokens can be issued",
+        ));
+    }
+
+    let audience = body.audience.as_deref().or(body.resource.as_deref());
+    let policy = find_policy(&app.token_exchange_policies, audience).ok_or_else(|| {
+        token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_target",
+            "Audience not allowed",
+        )
+    })?;
+
+    let subject_token = body.subject_token.as_deref().ok_or_else(|| {
+        token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "Missing subject_token",
+        )
+    })?;
+    let subject = verify_exchanged_token(
+        state,
+        subject_token,
+        body.subject_token_type.as_deref(),
+        dpop_jkt,
+    )
+    .await?;
+
+    if !subject
+        .clients
+        .iter()
+        .any(|client| policy.accepts_subject_client(&app.client_id, client))
+    {
+        return Err(token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_grant",
+            "The subject token can't be exchanged by this client",
+        ));
+    }
+
+    // Only user tokens can be exchanged, client_credentials tokens have the client as subject
+    let user = find_subject_user(&state.database, &subject.sub)
+        .await
+        .map_err(|_| {
+            token_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                "Database error",
+            )
+        })?
+        .ok_or_else(|| {
+            token_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+                "The subject token doesn't belong to a user",
+            )
+        })?;
+
+    // ID tokens carry no scopes, so they can only be exchanged for the ones the policy lists
+    let available = match &subject.scope {
+        Some(scope) => scope.split_whitespace().collect::<Vec<_>>(),
+        None => policy.scopes.iter().map(String::as_str).collect(),
+    };
+    let scope = narrow_scope(body.scope.as_deref(), &available, policy).map_err(|scope| {
+        token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_scope",
+            &format!("Scope not allowed: {scope}"),
+        )
+    })?;
+
+    let act = match body.actor_token.as_deref() {
+        Some(actor_token) => {
+            if !policy.allow_delegation {
+                return Err(token_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_request",
+                    "Delegation is not allowed for this audience",
+                ));
+            }
+
+            let actor = verify_exchanged_token(
+                state,
+                actor_token,
+                body.actor_token_type.as_deref(),
+                dpop_jkt,
+            )
+            .await?;
+            if !actor.clients.contains(&app.client_id) {
+                return Err(token_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_grant",
+                    "The actor token has to be issued to the client",
+                ));
+            }
+
+            // Earlier actors of the subject token stay in the chain
+            Some(ActorClaim {
+                sub: actor.sub,
+                client_id: Some(app.client_id.clone()),
+                act: subject.act.map(Box::new),
+            })
+        }
+        None => subject.act,
+    };
+
+    let issuer = state
+        .settings
+        .general
+        .public_url
+        .to_string()
+        .trim_end_matches('/')
+        .to_string();
+    let now = Utc::now().timestamp() as usize;
+    // The exchanged token can't outlive the one it was exchanged for
+    let lifetimes = state.settings.oidc.token_lifetimes.for_application(&app);
+    let exp = (now + lifetimes.access_token_secs as usize).min(subject.exp);
+    let scopes: Vec<&str> = scope.split_whitespace().collect();
+
+    // The issued token names the user the way the client sees them
+    let access_claims = AccessTokenClaims {
+        iss: issuer,
+        sub: user_subject(state, &app, &user).await?,
+        aud: policy.audience.clone(),
+        exp,
+        iat: now,
+        scope: scope.clone(),
+        client_id: app.client_id.clone(),
+        jti: uuid::Uuid::new_v4().to_string(),
+        act,
+        cnf: ConfirmationClaim::for_key(dpop_jkt),
+        extra: user_extra_claims(state, &app, &user, &scopes).await?,
+    };
+
+    let access_token = state
+        .oidc_keys
+        .sign_access_token(&access_claims)
+        .map_err(|_| {
+            token_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                "Failed to sign access token",
+            )
+        })?;
+
+    Ok(Json(TokenResponse {
+        access_token,
+        token_type: access_token_type(access_claims.cnf.as_ref()).to_string(),
+        expires_in: exp.saturating_sub(now) as u64,
+        refresh_token: None,
+        id_token: None,
+        scope,
+        issued_token_type: Some(ACCESS_TOKEN_TYPE.to_string()),
+    }))
+}
+
+async fn handle_device_code_grant(
+    state: &AppState,
+    body: &TokenRequest,
+    client_id: &Option<String>,
+    credential: &ClientCredential,
+    dpop_jkt: Option<&str>,
+) -> Result<Json<TokenResponse>, axum::response::Response> {
+    let device_code = body.device_code.as_ref().ok_or_else(|| {
+        token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "Missing device_code",
+        )
+    })?;
+
+    let client_id = client_id.as_ref().ok_or_else(|| {
+        token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "Missing client_id",
+        )
     })?;
 
-    let user = get_user_by_uuid(&state.database, &user_uuid)
+    let app = authenticate_client(state, client_id, credential).await?;
+
+    let database_error = |_| {
+        token_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "server_error",
+            "Database error",
+        )
+    };
+
+    let collection = state
+        .database
+        .collection::<DeviceAuthorization>("device_authorizations");
+    let device_code_hash = hash_token(device_code);
+
+    let authorization = collection
+        .find_one(doc! { "device_code_hash": &device_code_hash })
+        .await
+        .map_err(database_error)?
+        .filter(|authorization| authorization.client_id == app.client_id)
+        .ok_or_else(|| {
+            token_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+                "Invalid device_code",
+            )
+        })?;
+
+    let now = mongodb::bson::DateTime::now();
+    if authorization.expires_at < now {
+        return Err(token_error(
+            StatusCode::BAD_REQUEST,
+            "expired_token",
+            "The device_code has expired",
+        ));
+    }
+
+    match authorization.status {
+        DeviceAuthorizationStatus::Pending => {
+            let polled_too_fast = authorization.last_polled_at.is_some_and(|last_polled_at| {
+                now.timestamp_millis() - last_polled_at.timestamp_millis()
+                    < authorization.interval * 1000
+            });
+
+            let mut update = doc! { "$set": { "last_polled_at": now } };
+            if polled_too_fast {
+                update.insert("$inc", doc! { "interval": SLOW_DOWN_INCREMENT_SECS });
+            }
+            collection
+                .update_one(doc! { "device_code_hash": &device_code_hash }, update)
+                .await
+                .map_err(database_error)?;
+
+            if polled_too_fast {
+                Err(token_error(
+                    StatusCode::BAD_REQUEST,
+                    "slow_down",
+                    "Polling too frequently",
+                ))
+            } else {
+                Err(token_error(
+                    StatusCode::BAD_REQUEST,
+                    "authorization_pending",
+                    "The user hasn't approved the request yet",
+                ))
+            }
+        }
+        DeviceAuthorizationStatus::Denied => {
+            collection
+                .delete_one(doc! { "device_code_hash": &device_code_hash })
+                .await
+                .map_err(database_error)?;
+
+            Err(token_error(
+                StatusCode::BAD_REQUEST,
+                "access_denied",
+                "The user denied the request",
+            ))
+        }
+        DeviceAuthorizationStatus::Approved => {
+            // Deleting makes the device code single-use, even with concurrent polls
+            let authorization = collection
+                .find_one_and_delete(
+                    doc! { "device_code_hash": &device_code_hash, "status": "approved" },
+                )
+                .await
+                .map_err(database_error)?
+                .ok_or_else(|| {
+                    token_error(
+                        StatusCode::BAD_REQUEST,
+                        "invalid_grant",
+                        "Invalid device_code",
+                    )
+                })?;
+
+            let user_id = authorization.user_id.as_deref().ok_or_else(|| {
+                token_error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "server_error",
+                    "Approved device authorization has no user",
+                )
+            })?;
+
+            issue_user_tokens(
+                state,
+                &app,
+                UserGrant {
+                    user_id,
+                    scope: &authorization.scope,
+                    nonce: None,
+                    sid: authorization.sid.as_deref(),
+                    session_id: authorization.session_id.as_deref(),
+                    authentication: authorization.authentication.as_ref(),
+                    dpop_jkt,
+                },
+            )
+            .await
+        }
+    }
+}
+
+// ── UserInfo ─────────────────────────────────────────────────────
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserInfoResponse {
+    pub sub: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preferred_username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub given_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub family_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// OpenID Connect UserInfo endpoint
+#[utoipa::path(
+    method(get),
+    path = "/userinfo",
+    responses(
+        (status = OK, description = "User info", body = UserInfoResponse),
+        (status = UNAUTHORIZED, description = "Invalid or missing access token"),
+    ),
+    tag = "OIDC"
+)]
+async fn userinfo(
+    Extension(state): Extension<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<UserInfoResponse>, axum::response::Response> {
+    // Extract Bearer or DPoP token
+    let (scheme, access_token) = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split_once(' '))
+        .filter(|(scheme, _)| matches!(*scheme, "Bearer" | "DPoP"))
+        .ok_or_else(|| {
+            token_error(
+                StatusCode::UNAUTHORIZED,
+                "invalid_token",
+                "Missing or invalid Bearer token",
+            )
+        })?;
+
+    // Verify access token
+    let claims = state
+        .oidc_keys
+        .verify_access_token(access_token)
+        .map_err(|e| {
+            token_error(
+                StatusCode::UNAUTHORIZED,
+                "invalid_token",
+                &format!("Token verification failed: {e}"),
+            )
+        })?;
+
+    // Tokens exchanged for another audience are meant for that resource server, not for userinfo
+    let openid = claims
+        .scope
+        .split_whitespace()
+        .any(|scope| scope == "openid");
+    if claims.aud != claims.client_id && !openid {
+        return Err(token_error(
+            StatusCode::UNAUTHORIZED,
+            "invalid_token",
+            "The access token isn't meant for userinfo",
+        ));
+    }
+
+    // Bound tokens have to come with a proof of their key, so a leaked token alone is useless
+    match &claims.cnf {
+        Some(cnf) => {
+            let jkt = if scheme == "DPoP" {
+                dpop_key(&state, &headers, "GET", "userinfo", Some(access_token))
+                    .await
+                    .map_err(|error| {
+                        token_error(
+                            StatusCode::UNAUTHORIZED,
+                            "invalid_dpop_proof",
+                            &error.to_string(),
+                        )
+                    })?
+            } else {
+                None
+            };
+
+            if jkt.as_deref() != Some(cnf.jkt.as_str()) {
+                return Err(token_error(
+                    StatusCode::UNAUTHORIZED,
+                    "invalid_token",
+                    "The access token is bound to a different DPoP key",
+                ));
+            }
+        }
+        None if scheme == "DPoP" => {
+            return Err(token_error(
+                StatusCode::UNAUTHORIZED,
+                "invalid_token",
+                "The access token isn't bound to a DPoP key",
+            ));
+        }
+        None => {}
+    }
+
+    let revoked = is_access_token_revoked(&state.redis_pool, &claims.jti)
+        .await
+        .map_err(|_| {
+            token_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                "Failed to check token revocation",
+            )
+        })?;
+    if revoked {
+        return Err(token_error(
+            StatusCode::UNAUTHORIZED,
+            "invalid_token",
+            "Token has been revoked",
+        ));
+    }
+
+    // Get user by their public or pairwise subject
+    let user = find_subject_user(&state.database, &claims.sub)
         .await
         .map_err(|_| {
             token_error(
@@ -951,14 +2495,29 @@ async fn userinfo(
 
     let scopes: Vec<&str> = claims.scope.split_whitespace().collect();
 
+    let app = state
+        .database
+        .collection::<Application>("applications")
+        .find_one(doc! { "client_id": &claims.client_id })
+        .await
+        .map_err(|_| {
+            token_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                "Database error",
+            )
+        })?
+        .ok_or_else(|| token_error(StatusCode::UNAUTHORIZED, "invalid_token", "Unknown client"))?;
+
     let mut response = UserInfoResponse {
-        sub: user.uuid.to_string(),
+        sub: claims.sub.clone(),
         name: None,
         preferred_username: None,
         given_name: None,
         family_name: None,
         email: None,
         email_verified: None,
+        extra: user_extra_claims(&state, &app, &user, &scopes).await?,
     };
 
     if scopes.contains(&"profile") {
@@ -985,3 +2544,378 @@ fn token_error(status: StatusCode, error: &str, description: &str) -> axum::resp
     };
     (status, Json(body)).into_response()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::body::to_bytes;
+    use mongodb::bson::{self, Document};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        database::{AuthFactors, init_database, init_session_store},
+        oidc::{
+            client_secrets::generate_client_secret, encryption::test_encryption_key,
+            init_http_client, keys::init_oidc_keys, subject::init_pairwise_salt,
+        },
+        settings::{Settings, env_var},
+        webauthn::init_webauthn,
+    };
+
+    const SPA_REDIRECT_URI: &str = "https://spa.example/callback";
+
+    // Test vector from RFC 7636, Appendix B.
+    const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    const CHALLENGE: &str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+    /// State on the MongoDB and Redis of `AGINAUTH_TEST_DB` and `AGINAUTH_TEST_REDIS`, local ones
+    /// when unset. Every test gets a database of its own.
+    async fn test_state() -> AppState {
+        let mut settings = Settings::example();
+        if let Ok(connection_string) = env_var("TEST_DB") {
+            settings.db.connection_string = connection_string;
+        }
+        if let Ok(connection_string) = env_var("TEST_REDIS") {
+            settings.redis.connection_string = connection_string;
+        }
+        settings.db.database_name = format!("agin-auth-test-{}", Uuid::new_v4());
+        // Doesn't exist, so every signing key is generated
+        settings.oidc.signing_key_file = std::env::temp_dir()
+            .join(format!("{}.pem", Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned();
+
+        let encryption_key = Arc::new(test_encryption_key());
+        let database = init_database(&settings, &encryption_key).await.unwrap();
+        let oidc_keys = init_oidc_keys(&database, &settings.oidc, &encryption_key)
+            .await
+            .unwrap();
+        let pairwise_salt = init_pairwise_salt(&database).await.unwrap();
+        let (_, redis_pool) = init_session_store(&settings).await.unwrap();
+
+        AppState {
+            database,
+            webauthn: init_webauthn(&settings).unwrap(),
+            settings: Arc::new(settings),
+            mail_service: None,
+            oidc_keys,
+            redis_pool,
+            http_client: init_http_client().unwrap(),
+            pairwise_salt,
+            encryption_key,
+        }
+    }
+
+    /// Inserts a user, the public client `spa`, and the confidential client `backend` that may
+    /// exchange tokens of `spa` for `api`. Returns the user's ID and the secret of `backend`.
+    async fn seed(state: &AppState) -> (ObjectId, String) {
+        let user = User {
+            id: ObjectId::new(),
+            uuid: Uuid::new_v4(),
+            first_name: "Ada".to_string(),
+            last_name: "Lovelace".to_string(),
+            display_name: "Ada Lovelace".to_string(),
+            preferred_username: "ada".to_string(),
+            email: "ada@example.com".to_string(),
+            email_confirmed: true,
+            auth_factors: AuthFactors::default(),
+            groups: vec![],
+        };
+        state
+            .database
+            .collection::<User>("users")
+            .insert_one(&user)
+            .await
+            .unwrap();
+
+        let (secret, client_secret) =
+            generate_client_secret(&state.encryption_key, None, None).unwrap();
+        state
+            .database
+            .collection::<Document>("applications")
+            .insert_many([
+                doc! {
+                    "_id": ObjectId::new(),
+                    "name": "SPA",
+                    "slug": "spa",
+                    "icon": null,
+                    "client_type": "public",
+                    "client_id": "spa",
+                    "redirect_uris": [SPA_REDIRECT_URI],
+                    "allowed_groups": [],
+                    "require_pkce": true,
+                },
+                doc! {
+                    "_id": ObjectId::new(),
+                    "name": "Backend",
+                    "slug": "backend",
+                    "icon": null,
+                    "client_type": "confidential",
+                    "client_id": "backend",
+                    "client_secrets": [bson::to_bson(&client_secret).unwrap()],
+                    "redirect_uris": [],
+                    "allowed_groups": [],
+                    "token_exchange_policies": [{
+                        "audience": "api",
+                        "scopes": ["openid", "profile", "email"],
+                        "subject_clients": ["spa"],
+                    }],
+                },
+            ])
+            .await
+            .unwrap();
+
+        (user.id, secret)
+    }
+
+    /// Stores a code `spa` got for the user, with `challenge` as its S256 PKCE challenge.
+    async fn issue_code(
+        state: &AppState,
+        user_id: ObjectId,
+        scope: &str,
+        challenge: Option<&str>,
+    ) -> String {
+        let code = generate_reset_token();
+        let auth_code = AuthorizationCode {
+            code_hash: hash_token(&code),
+            client_id: "spa".to_string(),
+            user_id: user_id.to_hex(),
+            redirect_uri: SPA_REDIRECT_URI.to_string(),
+            scope: scope.to_string(),
+            nonce: None,
+            sid: None,
+            session_id: None,
+            code_challenge: challenge.map(str::to_string),
+            code_challenge_method: challenge.map(|_| CodeChallengeMethod::S256),
+            authentication: None,
+            created_at: Utc::now(),
+            expires_at: None,
+            used: false,
+        };
+        state
+            .database
+            .collection::<AuthorizationCode>("authorization_codes")
+            .insert_one(auth_code)
+            .await
+            .unwrap();
+
+        code
+    }
+
+    /// Posts `params` to the token endpoint, returning the `error` code of a failure.
+    async fn request_token(
+        state: &AppState,
+        params: &[(&str, &str)],
+    ) -> Result<TokenResponse, String> {
+        let body =
+            serde_urlencoded::from_str(&serde_urlencoded::to_string(params).unwrap()).unwrap();
+
+        match token(Extension(state.clone()), HeaderMap::new(), axum::Form(body)).await {
+            Ok(Json(response)) => Ok(response),
+            Err(response) => {
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                Err(error["error"].as_str().unwrap().to_string())
+            }
+        }
+    }
+
+    async fn redeem_code(
+        state: &AppState,
+        code: &str,
+        verifier: Option<&str>,
+    ) -> Result<TokenResponse, String> {
+        let mut params = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", SPA_REDIRECT_URI),
+            ("client_id", "spa"),
+        ];
+        params.extend(verifier.map(|verifier| ("code_verifier", verifier)));
+
+        request_token(state, &params).await
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB and Redis"]
+    async fn authorization_codes_cant_be_replayed() {
+        let state = test_state().await;
+        let (user_id, _) = seed(&state).await;
+        let code = issue_code(&state, user_id, "openid", Some(CHALLENGE)).await;
+
+        assert!(redeem_code(&state, &code, Some(VERIFIER)).await.is_ok());
+        assert_eq!(
+            redeem_code(&state, &code, Some(VERIFIER))
+                .await
+                .unwrap_err(),
+            "invalid_grant"
+        );
+
+        state.database.drop().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB and Redis"]
+    async fn pkce_mismatches_spend_the_code() {
+        let state = test_state().await;
+        let (user_id, _) = seed(&state).await;
+
+        let code = issue_code(&state, user_id, "openid", Some(CHALLENGE)).await;
+        let wrong_verifier = VERIFIER.replace('d', "e");
+        assert_eq!(
+            redeem_code(&state, &code, Some(&wrong_verifier))
+                .await
+                .unwrap_err(),
+            "invalid_grant"
+        );
+        assert_eq!(
+            redeem_code(&state, &code, Some(VERIFIER))
+                .await
+                .unwrap_err(),
+            "invalid_grant"
+        );
+
+        let code = issue_code(&state, user_id, "openid", Some(CHALLENGE)).await;
+        assert_eq!(
+            redeem_code(&state, &code, None).await.unwrap_err(),
+            "invalid_grant"
+        );
+
+        state.database.drop().await.unwrap();
+    }
+
+    async fn refresh(state: &AppState, refresh_token: &str) -> Result<TokenResponse, String> {
+        request_token(
+            state,
+            &[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token),
+                ("client_id", "spa"),
+            ],
+        )
+        .await
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB and Redis"]
+    async fn reused_refresh_tokens_revoke_the_grant() {
+        let state = test_state().await;
+        let (user_id, _) = seed(&state).await;
+        let code = issue_code(&state, user_id, "openid offline_access", Some(CHALLENGE)).await;
+
+        let first = redeem_code(&state, &code, Some(VERIFIER))
+            .await
+            .unwrap()
+            .refresh_token
+            .unwrap();
+        let second = refresh(&state, &first)
+            .await
+            .unwrap()
+            .refresh_token
+            .unwrap();
+        assert_ne!(first, second);
+
+        // The rotated token leaked, so the one it was rotated into can't be trusted either
+        assert_eq!(refresh(&state, &first).await.unwrap_err(), "invalid_grant");
+        assert_eq!(refresh(&state, &second).await.unwrap_err(), "invalid_grant");
+
+        state.database.drop().await.unwrap();
+    }
+
+    /// Has `backend` exchange `subject_token` for a token aimed at `api`.
+    async fn exchange(
+        state: &AppState,
+        secret: &str,
+        subject_token: &str,
+        subject_token_type: &str,
+        scope: Option<&str>,
+    ) -> Result<TokenResponse, String> {
+        let mut params = vec![
+            ("grant_type", TOKEN_EXCHANGE_GRANT_TYPE),
+            ("client_id", "backend"),
+            ("client_secret", secret),
+            ("audience", "api"),
+            ("subject_token", subject_token),
+            ("subject_token_type", subject_token_type),
+        ];
+        params.extend(scope.map(|scope| ("scope", scope)));
+
+        request_token(state, &params).await
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB and Redis"]
+    async fn access_tokens_cant_be_exchanged_as_id_tokens() {
+        let state = test_state().await;
+        let (user_id, secret) = seed(&state).await;
+        let code = issue_code(&state, user_id, "openid profile", Some(CHALLENGE)).await;
+        let tokens = redeem_code(&state, &code, Some(VERIFIER)).await.unwrap();
+
+        assert_eq!(
+            exchange(&state, &secret, &tokens.access_token, ID_TOKEN_TYPE, None)
+                .await
+                .unwrap_err(),
+            "invalid_grant"
+        );
+        assert_eq!(
+            exchange(
+                &state,
+                &secret,
+                &tokens.id_token.unwrap(),
+                ACCESS_TOKEN_TYPE,
+                None
+            )
+            .await
+            .unwrap_err(),
+            "invalid_grant"
+        );
+
+        state.database.drop().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB and Redis"]
+    async fn exchanged_tokens_only_narrow_the_scope() {
+        let state = test_state().await;
+        let (user_id, secret) = seed(&state).await;
+        let code = issue_code(&state, user_id, "openid profile", Some(CHALLENGE)).await;
+        let access_token = redeem_code(&state, &code, Some(VERIFIER))
+            .await
+            .unwrap()
+            .access_token;
+
+        let exchanged = exchange(&state, &secret, &access_token, ACCESS_TOKEN_TYPE, None)
+            .await
+            .unwrap();
+        assert_eq!(exchanged.scope, "openid profile");
+
+        let exchanged = exchange(
+            &state,
+            &secret,
+            &access_token,
+            ACCESS_TOKEN_TYPE,
+            Some("profile"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(exchanged.scope, "profile");
+
+        // Allowed for the audience, but not granted to the subject token
+        assert_eq!(
+            exchange(
+                &state,
+                &secret,
+                &access_token,
+                ACCESS_TOKEN_TYPE,
+                Some("profile email")
+            )
+            .await
+            .unwrap_err(),
+            "invalid_scope"
+        );
+
+        state.database.drop().await.unwrap();
+    }
+}
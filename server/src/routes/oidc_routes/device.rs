@@ -0,0 +1,185 @@
+use axum::{Extension, Json, extract::Query};
+use color_eyre::eyre::{self, Context as _};
+use mongodb::bson::{self, doc};
+use serde::{Deserialize, Serialize};
+use tower_sessions::Session;
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    axum_error::{AxumError, AxumResult},
+    database::Application,
+    oidc::{
+        authentication::session_authentication,
+        claims::CustomScope,
+        device::{DeviceAuthorization, format_user_code, normalize_user_code},
+        logout::get_or_create_sid,
+    },
+    state::AppState,
+};
+
+use super::{
+    StepUpRequirement, check_application_access, check_security_level, requested_custom_scopes,
+    require_authenticated_session, step_up_requirement,
+};
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new().routes(routes!(device_get, device_post))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct DeviceQuery {
+    /// User code shown on the device, with or without the separator.
+    pub user_code: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeviceInfo {
+    pub app_name: String,
+    pub app_icon: Option<String>,
+    pub scopes: Vec<String>,
+    /// Descriptions of the application-specific scopes among `scopes`.
+    pub custom_scopes: Vec<CustomScope>,
+    pub user_code: String,
+    /// Set when the user has to complete another factor before approving.
+    pub step_up: Option<StepUpRequirement>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DeviceConsent {
+    pub user_code: String,
+    /// Whether the user approved or denied the request.
+    pub approve: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeviceConsentResponse {
+    pub success: bool,
+}
+
+/// Looks up a pending, unexpired device authorization by the code the user entered.
+async fn find_pending_authorization(
+    state: &AppState,
+    user_code: &str,
+) -> AxumResult<(DeviceAuthorization, Application)> {
+    let authorization = state
+        .database
+        .collection::<DeviceAuthorization>("device_authorizations")
+        .find_one(doc! {
+            "user_code": normalize_user_code(user_code),
+            "status": "pending",
+            "expires_at": { "$gt": mongodb::bson::DateTime::now() },
+        })
+        .await
+        .wrap_err("Database error")?
+        .ok_or_else(|| AxumError::not_found(eyre::eyre!("Invalid or expired code")))?;
+
+    let app = state
+        .database
+        .collection::<Application>("applications")
+        .find_one(doc! { "client_id": &authorization.client_id })
+        .await
+        .wrap_err("Database error")?
+        .ok_or_else(|| AxumError::not_found(eyre::eyre!("Unknown client_id")))?;
+
+    Ok((authorization, app))
+}
+
+/// Get device authorization info (requires session)
+///
+/// Returns the application and scopes behind a user code, so the user can confirm it before approving.
+#[utoipa::path(
+    method(get),
+    path = "/",
+    params(DeviceQuery),
+    responses(
+        (status = OK, description = "Device authorization info", body = DeviceInfo),
+        (status = UNAUTHORIZED, description = "Not authenticated"),
+        (status = FORBIDDEN, description = "No factor strong enough for the application"),
+        (status = NOT_FOUND, description = "Invalid or expired code"),
+    ),
+    tag = "OIDC"
+)]
+async fn device_get(
+    Extension(state): Extension<AppState>,
+    session: Session,
+    Query(query): Query<DeviceQuery>,
+) -> AxumResult<Json<DeviceInfo>> {
+    let user_id = require_authenticated_session(&session).await?;
+
+    let (authorization, app) = find_pending_authorization(&state, &query.user_code).await?;
+
+    let step_up = step_up_requirement(&state, &session, &user_id, &app, None).await?;
+
+    let scopes = authorization
+        .scope
+        .split_whitespace()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+
+    Ok(Json(DeviceInfo {
+        custom_scopes: requested_custom_scopes(&app, &scopes),
+        app_name: app.name,
+        app_icon: app.icon,
+        scopes,
+        user_code: format_user_code(&authorization.user_code),
+        step_up,
+    }))
+}
+
+/// Approve or deny a device authorization (user consent)
+#[utoipa::path(
+    method(post),
+    path = "/",
+    request_body = DeviceConsent,
+    responses(
+        (status = OK, description = "Decision recorded", body = DeviceConsentResponse),
+        (status = UNAUTHORIZED, description = "Not authenticated"),
+        (status = FORBIDDEN, description = "No access to the application, or the sign-in is too weak"),
+        (status = NOT_FOUND, description = "Invalid or expired code"),
+    ),
+    tag = "OIDC"
+)]
+async fn device_post(
+    Extension(state): Extension<AppState>,
+    session: Session,
+    Json(body): Json<DeviceConsent>,
+) -> AxumResult<Json<DeviceConsentResponse>> {
+    let user_id = require_authenticated_session(&session).await?;
+
+    let (authorization, app) = find_pending_authorization(&state, &body.user_code).await?;
+
+    let update = if body.approve {
+        check_application_access(&state, &app, &user_id).await?;
+        check_security_level(&session, &app, None).await?;
+
+        let sid = get_or_create_sid(&session).await?;
+        let authentication = bson::to_bson(&session_authentication(&session).await?)
+            .wrap_err("Failed to serialize authentication")?;
+        doc! {
+            "status": "approved",
+            "user_id": user_id.to_hex(),
+            "sid": sid,
+            "session_id": session.id().map(|id| id.to_string()),
+            "authentication": authentication,
+        }
+    } else {
+        doc! { "status": "denied" }
+    };
+
+    let result = state
+        .database
+        .collection::<DeviceAuthorization>("device_authorizations")
+        .update_one(
+            doc! { "device_code_hash": &authorization.device_code_hash, "status": "pending" },
+            doc! { "$set": update },
+        )
+        .await
+        .wrap_err("Failed to update device authorization")?;
+
+    if result.modified_count == 0 {
+        return Err(AxumError::not_found(eyre::eyre!("Invalid or expired code")));
+    }
+
+    Ok(Json(DeviceConsentResponse { success: true }))
+}
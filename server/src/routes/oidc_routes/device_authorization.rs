@@ -0,0 +1,137 @@
+use axum::{
+    Extension, Json,
+    http::{HeaderMap, StatusCode},
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    oidc::device::{
+        DEVICE_CODE_LIFETIME_SECS, DEVICE_POLL_INTERVAL_SECS, DeviceAuthorization,
+        DeviceAuthorizationStatus, format_user_code, generate_user_code,
+    },
+    state::AppState,
+    utils::{generate_reset_token, hash_token},
+};
+
+use super::{authenticate_client, extract_client_credentials, filter_scopes, token_error};
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new().routes(routes!(device_authorization))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DeviceAuthorizationRequest {
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub client_assertion_type: Option<String>,
+    pub client_assertion: Option<String>,
+    pub scope: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeviceAuthorizationResponse {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: String,
+    pub expires_in: i64,
+    pub interval: i64,
+}
+
+/// OAuth2 Device Authorization endpoint (RFC 8628)
+///
+/// Starts a sign-in for a device that can't open a browser. The user approves the returned user code on another device,
+/// while the client polls the token endpoint with the device code.
+#[utoipa::path(
+    method(post),
+    path = "/",
+    request_body(content = DeviceAuthorizationRequest, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = OK, description = "Device authorization started", body = DeviceAuthorizationResponse),
+        (status = BAD_REQUEST, description = "Invalid request"),
+        (status = UNAUTHORIZED, description = "Client authentication failed"),
+    ),
+    tag = "OIDC"
+)]
+async fn device_authorization(
+    Extension(state): Extension<AppState>,
+    headers: HeaderMap,
+    axum::Form(body): axum::Form<DeviceAuthorizationRequest>,
+) -> Result<Json<DeviceAuthorizationResponse>, axum::response::Response> {
+    let (client_id, credential) = extract_client_credentials(
+        &headers,
+        body.client_id.as_deref(),
+        body.client_secret.as_deref(),
+        body.client_assertion_type.as_deref(),
+        body.client_assertion.as_deref(),
+    );
+    let client_id = client_id.ok_or_else(|| {
+        token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "Missing client_id",
+        )
+    })?;
+
+    let app = authenticate_client(&state, &client_id, &credential).await?;
+
+    if !app.allow_device_authorization {
+        return Err(token_error(
+            StatusCode::BAD_REQUEST,
+            "unauthorized_client",
+            "Client is not allowed to use the device authorization grant",
+        ));
+    }
+
+    let scope = filter_scopes(&app, body.scope.as_deref().unwrap_or_default()).join(" ");
+    let device_code = generate_reset_token();
+    let user_code = generate_user_code();
+
+    let authorization = DeviceAuthorization {
+        device_code_hash: hash_token(&device_code),
+        user_code: user_code.clone(),
+        client_id: app.client_id,
+        scope,
+        status: DeviceAuthorizationStatus::Pending,
+        user_id: None,
+        sid: None,
+        session_id: None,
+        authentication: None,
+        interval: DEVICE_POLL_INTERVAL_SECS,
+        last_polled_at: None,
+        expires_at: mongodb::bson::DateTime::from_millis(
+            mongodb::bson::DateTime::now().timestamp_millis() + DEVICE_CODE_LIFETIME_SECS * 1000,
+        ),
+    };
+
+    state
+        .database
+        .collection::<DeviceAuthorization>("device_authorizations")
+        .insert_one(authorization)
+        .await
+        .map_err(|_| {
+            token_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                "Failed to store device authorization",
+            )
+        })?;
+
+    let public_url = state.settings.general.public_url.to_string();
+    let verification_uri = format!("{}/application/device", public_url.trim_end_matches('/'));
+    let user_code = format_user_code(&user_code);
+
+    Ok(Json(DeviceAuthorizationResponse {
+        device_code,
+        verification_uri_complete: format!(
+            "{verification_uri}?user_code={}",
+            urlencoding::encode(&user_code)
+        ),
+        verification_uri,
+        user_code,
+        expires_in: DEVICE_CODE_LIFETIME_SECS,
+        interval: DEVICE_POLL_INTERVAL_SECS,
+    }))
+}
@@ -0,0 +1,198 @@
+use axum::{Extension, Json, extract::Query};
+use color_eyre::eyre::{self, Context as _};
+use mongodb::bson::{doc, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+use tower_sessions::Session;
+use url::Url;
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    axum_error::{AxumError, AxumResult},
+    database::{Application, destroy_session, get_user_by_id},
+    oidc::{
+        logout::{frontchannel_logout_urls, notify_session_logout},
+        subject::subject_identifier,
+    },
+    state::AppState,
+};
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new().routes(routes!(end_session_get, end_session_post))
+}
+
+#[derive(Debug, Deserialize, ToSchema, IntoParams)]
+pub struct EndSessionParams {
+    pub id_token_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub post_logout_redirect_uri: Option<String>,
+    pub state: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EndSessionInfo {
+    pub app_name: Option<String>,
+    pub app_icon: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EndSessionResponse {
+    /// Where to send the user after logging out, if the relying party asked for a redirect.
+    pub redirect_url: Option<String>,
+    /// Front-channel logout pages to load in iframes before redirecting.
+    pub frontchannel_logout_urls: Vec<String>,
+}
+
+/// A validated RP-initiated logout request.
+struct EndSessionRequest {
+    app: Option<Application>,
+    /// `sub` of the `id_token_hint`, if one was provided.
+    subject: Option<String>,
+    redirect_url: Option<String>,
+}
+
+/// Get logout request info (OpenID Connect RP-Initiated Logout 1.0)
+///
+/// Validates the logout request so the frontend can ask the user to confirm logging out.
+#[utoipa::path(
+    method(get),
+    path = "/",
+    params(EndSessionParams),
+    responses(
+        (status = OK, description = "Logout request info", body = EndSessionInfo),
+        (status = BAD_REQUEST, description = "Invalid request"),
+    ),
+    tag = "OIDC"
+)]
+async fn end_session_get(
+    Extension(state): Extension<AppState>,
+    Query(params): Query<EndSessionParams>,
+) -> AxumResult<Json<EndSessionInfo>> {
+    let request = validate_end_session(&state, &params).await?;
+
+    Ok(Json(EndSessionInfo {
+        app_name: request.app.as_ref().map(|app| app.name.clone()),
+        app_icon: request.app.and_then(|app| app.icon),
+    }))
+}
+
+/// Confirm logout (OpenID Connect RP-Initiated Logout 1.0)
+///
+/// Ends the current session and returns the URL to redirect the user to.
+#[utoipa::path(
+    method(post),
+    path = "/",
+    request_body = EndSessionParams,
+    responses(
+        (status = OK, description = "Logged out", body = EndSessionResponse),
+        (status = BAD_REQUEST, description = "Invalid request"),
+    ),
+    tag = "OIDC"
+)]
+async fn end_session_post(
+    Extension(state): Extension<AppState>,
+    session: Session,
+    Json(body): Json<EndSessionParams>,
+) -> AxumResult<Json<EndSessionResponse>> {
+    let request = validate_end_session(&state, &body).await?;
+
+    // Only end the session if it belongs to the user the relying party is logging out
+    let user_id = session.get::<ObjectId>("user_id").await?;
+    let matches_subject = match (&request.subject, user_id) {
+        (Some(subject), Some(user_id)) => get_user_by_id(&state.database, &user_id)
+            .await
+            .wrap_err("Database error")?
+            .is_some_and(|user| match &request.app {
+                Some(app) => subject_identifier(&state.pairwise_salt, app, &user) == *subject,
+                None => user.uuid.to_string() == *subject,
+            }),
+        _ => true,
+    };
+
+    let mut frontchannel_urls = Vec::new();
+    if user_id.is_some() && matches_subject {
+        // Front-channel URLs have to be collected before the client sessions are cleared
+        if let Some(session_id) = session.id() {
+            frontchannel_urls = frontchannel_logout_urls(&state, &session_id.to_string())
+                .await
+                .wrap_err("Failed to collect front-channel logout URLs")?;
+        }
+
+        if let Some(session_id) = destroy_session(&state.database, &session).await? {
+            notify_session_logout(&state, session_id);
+        }
+    }
+
+    Ok(Json(EndSessionResponse {
+        redirect_url: request.redirect_url,
+        frontchannel_logout_urls: frontchannel_urls,
+    }))
+}
+
+async fn validate_end_session(
+    state: &AppState,
+    params: &EndSessionParams,
+) -> AxumResult<EndSessionRequest> {
+    let hint = params
+        .id_token_hint
+        .as_deref()
+        .map(|token| state.oidc_keys.verify_id_token_hint(token))
+        .transpose()
+        .map_err(|_| AxumError::bad_request(eyre::eyre!("Invalid id_token_hint")))?;
+
+    if let (Some(hint), Some(client_id)) = (&hint, &params.client_id)
+        && !hint.aud.contains(client_id)
+    {
+        return Err(AxumError::bad_request(eyre::eyre!(
+            "client_id does not match id_token_hint"
+        )));
+    }
+
+    let client_id = params
+        .client_id
+        .as_deref()
+        .or_else(|| hint.as_ref().and_then(|hint| hint.aud.primary()));
+
+    let app = match client_id {
+        Some(client_id) => Some(
+            state
+                .database
+                .collection::<Application>("applications")
+                .find_one(doc! { "client_id": client_id })
+                .await
+                .wrap_err("Database error")?
+                .ok_or_else(|| AxumError::bad_request(eyre::eyre!("Unknown client_id")))?,
+        ),
+        None => None,
+    };
+
+    let redirect_url = match &params.post_logout_redirect_uri {
+        Some(uri) => {
+            let app = app.as_ref().ok_or_else(|| {
+                AxumError::bad_request(eyre::eyre!(
+                    "post_logout_redirect_uri requires client_id or id_token_hint"
+                ))
+            })?;
+            if !app.post_logout_redirect_uris.contains(uri) {
+                return Err(AxumError::bad_request(eyre::eyre!(
+                    "Invalid post_logout_redirect_uri for this application"
+                )));
+            }
+
+            let mut url = Url::parse(uri).map_err(|_| {
+                AxumError::bad_request(eyre::eyre!("Invalid post_logout_redirect_uri"))
+            })?;
+            if let Some(st) = &params.state {
+                url.query_pairs_mut().append_pair("state", st);
+            }
+            Some(url.to_string())
+        }
+        None => None,
+    };
+
+    Ok(EndSessionRequest {
+        app,
+        subject: hint.map(|hint| hint.sub),
+        redirect_url,
+    })
+}
@@ -0,0 +1,290 @@
+use axum::{
+    Extension, Json,
+    http::{HeaderMap, StatusCode},
+};
+use mongodb::bson::{doc, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    database::{Application, ClientType, User, get_user_by_id},
+    oidc::{
+        RefreshToken,
+        dpop::{ConfirmationClaim, access_token_type},
+        revocation::is_access_token_revoked,
+        subject::{SubjectType, find_subject_user, subject_identifier},
+        token_exchange::ActorClaim,
+    },
+    state::AppState,
+    utils::hash_token,
+};
+
+use super::{authenticate_client, extract_client_credentials, token_error};
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new().routes(routes!(introspect))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct IntrospectionRequest {
+    pub token: String,
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub client_assertion_type: Option<String>,
+    pub client_assertion: Option<String>,
+}
+
+#[derive(Debug, Serialize, Default, ToSchema)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    /// Actor of a delegated token (RFC 8693, section 4.1).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub act: Option<ActorClaim>,
+    /// DPoP key the token is bound to (RFC 9449, section 6.2).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<ConfirmationClaim>,
+}
+
+impl IntrospectionResponse {
+    fn inactive() -> Self {
+        Self::default()
+    }
+}
+
+/// OAuth2 Token Introspection endpoint (RFC 7662)
+///
+/// Reports whether an access or refresh token is currently active. Only confidential clients may introspect tokens,
+/// and only those issued to them or, for access tokens, meant for them.
+#[utoipa::path(
+    method(post),
+    path = "/",
+    request_body(content = IntrospectionRequest, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = OK, description = "Introspection response", body = IntrospectionResponse),
+        (status = UNAUTHORIZED, description = "Client authentication failed"),
+    ),
+    tag = "OIDC"
+)]
+async fn introspect(
+    Extension(state): Extension<AppState>,
+    headers: HeaderMap,
+    axum::Form(body): axum::Form<IntrospectionRequest>,
+) -> Result<Json<IntrospectionResponse>, axum::response::Response> {
+    let (client_id, credential) = extract_client_credentials(
+        &headers,
+        body.client_id.as_deref(),
+        body.client_secret.as_deref(),
+        body.client_assertion_type.as_deref(),
+        body.client_assertion.as_deref(),
+    );
+    let client_id = client_id.ok_or_else(|| {
+        token_error(
+            StatusCode::UNAUTHORIZED,
+            "invalid_client",
+            "Client authentication required",
+        )
+    })?;
+    let app = authenticate_client(&state, &client_id, &credential).await?;
+
+    // Public clients can't keep a secret, so they can't prove they are a resource server
+    if matches!(app.client_type, ClientType::Public) {
+        return Err(token_error(
+            StatusCode::UNAUTHORIZED,
+            "invalid_client",
+            "Public clients cannot introspect tokens",
+        ));
+    }
+
+    let response = if body.token_type_hint.as_deref() == Some("refresh_token") {
+        match introspect_refresh_token(&state, &app, &body.token).await? {
+            Some(response) => response,
+            None => introspect_access_token(&state, &app, &body.token)
+                .await?
+                .unwrap_or_else(IntrospectionResponse::inactive),
+        }
+    } else {
+        match introspect_access_token(&state, &app, &body.token).await? {
+            Some(response) => response,
+            None => introspect_refresh_token(&state, &app, &body.token)
+                .await?
+                .unwrap_or_else(IntrospectionResponse::inactive),
+        }
+    };
+
+    Ok(Json(response))
+}
+
+/// Returns `None` if the token isn't an access token issued by this server.
+/// Inactive unless `caller` is its audience or the client it was issued to.
+async fn introspect_access_token(
+    state: &AppState,
+    caller: &Application,
+    token: &str,
+) -> Result<Option<IntrospectionResponse>, axum::response::Response> {
+    let Ok(claims) = state.oidc_keys.verify_access_token(token) else {
+        return Ok(None);
+    };
+
+    // Other clients would learn the subject, which may be pairwise
+    if claims.aud != caller.client_id && claims.client_id != caller.client_id {
+        return Ok(Some(IntrospectionResponse::inactive()));
+    }
+
+    let revoked = is_access_token_revoked(&state.redis_pool, &claims.jti)
+        .await
+        .map_err(|_| server_error("Failed to check token revocation"))?;
+    if revoked {
+        return Ok(Some(IntrospectionResponse::inactive()));
+    }
+
+    // Tokens from the client_credentials grant are issued to the client itself
+    let username = if claims.sub == claims.client_id {
+        None
+    } else {
+        let Some(user) = find_subject_user(&state.database, &claims.sub)
+            .await
+            .map_err(|_| server_error("Database error"))?
+        else {
+            return Ok(Some(IntrospectionResponse::inactive()));
+        };
+
+        let public_subject = claims.sub == user.uuid.to_string();
+        introspected_username(caller, &claims.scope, public_subject, user)
+    };
+
+    Ok(Some(IntrospectionResponse {
+        active: true,
+        scope: Some(claims.scope),
+        client_id: Some(claims.client_id),
+        username,
+        token_type: Some(access_token_type(claims.cnf.as_ref()).to_string()),
+        exp: Some(claims.exp),
+        iat: Some(claims.iat),
+        sub: Some(claims.sub),
+        aud: Some(claims.aud),
+        iss: Some(claims.iss),
+        jti: (!claims.jti.is_empty()).then_some(claims.jti),
+        act: claims.act,
+        cnf: claims.cnf,
+    }))
+}
+
+/// Returns `None` if the token isn't a refresh token known to this server.
+/// Inactive unless it was issued to `caller`.
+async fn introspect_refresh_token(
+    state: &AppState,
+    caller: &Application,
+    token: &str,
+) -> Result<Option<IntrospectionResponse>, axum::response::Response> {
+    let Some(stored) = state
+        .database
+        .collection::<RefreshToken>("refresh_tokens")
+        .find_one(doc! { "token_hash": hash_token(token) })
+        .await
+        .map_err(|_| server_error("Database error"))?
+    else {
+        return Ok(None);
+    };
+
+    // Other clients would learn the subject, which may be pairwise
+    if stored.revoked || stored.client_id != caller.client_id {
+        return Ok(Some(IntrospectionResponse::inactive()));
+    }
+
+    let lifetimes = state.settings.oidc.token_lifetimes.for_application(caller);
+    let expired = lifetimes
+        .is_refresh_token_expired(&state.database, &stored)
+        .await
+        .map_err(|_| server_error("Database error"))?;
+    if expired {
+        return Ok(Some(IntrospectionResponse::inactive()));
+    }
+    let expires_at = lifetimes.refresh_token_expires_at(&stored);
+
+    let Ok(user_oid) = ObjectId::parse_str(&stored.user_id) else {
+        return Ok(Some(IntrospectionResponse::inactive()));
+    };
+    let Some(user) = get_user_by_id(&state.database, &user_oid)
+        .await
+        .map_err(|_| server_error("Database error"))?
+    else {
+        return Ok(Some(IntrospectionResponse::inactive()));
+    };
+
+    // The subject is the one the token's client sees, which may be pairwise
+    let subject = subject_identifier(&state.pairwise_salt, caller, &user);
+    let username = introspected_username(
+        caller,
+        &stored.scope,
+        caller.subject_type == SubjectType::Public,
+        user,
+    );
+
+    let issuer = state
+        .settings
+        .general
+        .public_url
+        .to_string()
+        .trim_end_matches('/')
+        .to_string();
+
+    Ok(Some(IntrospectionResponse {
+        active: true,
+        scope: Some(stored.scope),
+        client_id: Some(stored.client_id.clone()),
+        username,
+        token_type: Some("refresh_token".to_string()),
+        exp: Some(expires_at.timestamp() as usize),
+        iat: Some(stored.created_at.timestamp() as usize),
+        sub: Some(subject),
+        aud: Some(stored.client_id),
+        iss: Some(issuer),
+        jti: None,
+        act: None,
+        cnf: stored.dpop_jkt.map(|jkt| ConfirmationClaim { jkt }),
+    }))
+}
+
+/// The username is the same for every application, so it's only disclosed when the `profile`
+/// scope was granted, and never next to a pairwise subject.
+fn introspected_username(
+    caller: &Application,
+    scope: &str,
+    public_subject: bool,
+    user: User,
+) -> Option<String> {
+    let profile = scope.split_whitespace().any(|scope| scope == "profile");
+
+    (profile && public_subject && caller.subject_type == SubjectType::Public)
+        .then_some(user.preferred_username)
+}
+
+fn server_error(description: &str) -> axum::response::Response {
+    token_error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "server_error",
+        description,
+    )
+}
@@ -0,0 +1,163 @@
+use axum::{
+    Extension, Json,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use tower_governor::{GovernorLayer, governor::GovernorConfigBuilder};
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    oidc::{
+        par::{PUSHED_REQUEST_LIFETIME_SECS, store_pushed_request},
+        request_object::{merge_request_object, verify_request_object},
+    },
+    state::AppState,
+};
+
+use super::{
+    AuthorizeQuery, authenticate_client, extract_client_credentials, token_error,
+    validate_authorization_request,
+};
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    // Public clients push without authenticating, so the endpoint is rate-limited per IP.
+    // The burst leaves room for confidential clients pushing on behalf of several users at once.
+    let rate_limit_conf = GovernorConfigBuilder::default()
+        .per_second(2)
+        .burst_size(20)
+        .finish()
+        .unwrap();
+
+    OpenApiRouter::new()
+        .routes(routes!(pushed_authorization_request))
+        .layer(GovernorLayer::new(rate_limit_conf))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PushedAuthorizationResponse {
+    /// Passed to the authorization endpoint together with the `client_id`.
+    pub request_uri: String,
+    pub expires_in: i64,
+}
+
+/// Form parameters a client authenticates with.
+const CLIENT_AUTHENTICATION_PARAMETERS: &[&str] = &[
+    "client_id",
+    "client_secret",
+    "client_assertion_type",
+    "client_assertion",
+];
+
+fn invalid_request(description: &str) -> Response {
+    token_error(StatusCode::BAD_REQUEST, "invalid_request", description)
+}
+
+/// Pushed authorization request endpoint (RFC 9126)
+///
+/// Stores the authorization parameters of a client and returns a `request_uri`
+/// that stands for them at the authorization endpoint. The parameters may be sent as a signed `request` object.
+///
+/// Confidential clients have to authenticate. Public clients only send their `client_id`, as
+/// RFC 9126 §2 allows: they have no credentials, and a pushed request carries nothing an
+/// authorization URL couldn't. It still goes through the same validation, including PKCE.
+#[utoipa::path(
+    method(post),
+    path = "/",
+    request_body(content = AuthorizeQuery, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = CREATED, description = "Request stored", body = PushedAuthorizationResponse),
+        (status = BAD_REQUEST, description = "Invalid authorization request"),
+        (status = UNAUTHORIZED, description = "Client authentication failed"),
+    ),
+    tag = "OIDC"
+)]
+async fn pushed_authorization_request(
+    Extension(state): Extension<AppState>,
+    headers: HeaderMap,
+    axum::Form(form): axum::Form<Vec<(String, String)>>,
+) -> Result<Response, Response> {
+    let form_value = |name: &str| {
+        form.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    let (client_id, credential) = extract_client_credentials(
+        &headers,
+        form_value("client_id"),
+        form_value("client_secret"),
+        form_value("client_assertion_type"),
+        form_value("client_assertion"),
+    );
+    let client_id = client_id.ok_or_else(|| {
+        token_error(
+            StatusCode::UNAUTHORIZED,
+            "invalid_client",
+            "Client authentication required",
+        )
+    })?;
+    let app = authenticate_client(&state, &client_id, &credential).await?;
+
+    if form_value("client_id").is_some_and(|id| id != app.client_id) {
+        return Err(invalid_request(
+            "client_id doesn't match the authenticated client",
+        ));
+    }
+    if form_value("request_uri").is_some() {
+        return Err(invalid_request("request_uri can't be pushed"));
+    }
+
+    // Client credentials aren't part of the authorization request
+    let mut parameters = form
+        .iter()
+        .filter(|(key, _)| !CLIENT_AUTHENTICATION_PARAMETERS.contains(&key.as_str()))
+        .cloned()
+        .collect::<Vec<_>>();
+    parameters.push(("client_id".to_string(), app.client_id.clone()));
+
+    if let Some(request_object) = form_value("request") {
+        let public_url = state.settings.general.public_url.to_string();
+        let claims = verify_request_object(
+            &state,
+            &app,
+            public_url.trim_end_matches('/'),
+            request_object,
+        )
+        .await
+        .map_err(|error| {
+            token_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_object",
+                &error.to_string(),
+            )
+        })?;
+        parameters = merge_request_object(parameters, claims);
+    }
+
+    let parameters = serde_urlencoded::to_string(&parameters)
+        .map_err(|_| invalid_request("Invalid authorization parameters"))?;
+    let request: AuthorizeQuery = serde_urlencoded::from_str(&parameters)
+        .map_err(|error| invalid_request(&error.to_string()))?;
+
+    validate_authorization_request(&app, &request)
+        .map_err(|error| invalid_request(&error.report.to_string()))?;
+
+    let request_uri = store_pushed_request(&state.database, &app.client_id, parameters)
+        .await
+        .map_err(|_| {
+            token_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                "Database error",
+            )
+        })?;
+
+    let response = PushedAuthorizationResponse {
+        request_uri,
+        expires_in: PUSHED_REQUEST_LIFETIME_SECS,
+    };
+
+    Ok((StatusCode::CREATED, Json(response)).into_response())
+}
@@ -0,0 +1,458 @@
+use axum::{
+    Extension, Json,
+    extract::Path,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use mongodb::bson::{self, doc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    database::{Application, ClientType, PartialApplication},
+    oidc::{
+        RefreshToken,
+        client_secrets::{
+            ClientSecret, client_secret_expires_at, generate_client_secret, keeps_encrypted_secrets,
+        },
+        lifetimes::TokenLifetimeOverrides,
+        registration::{ClientMetadata, ClientSettings, InitialAccessToken, MetadataError},
+        subject::verify_sector_identifier_uri,
+    },
+    state::AppState,
+    utils::{generate_client_id, generate_reset_token, hash_token},
+};
+
+use super::token_error;
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(register_client))
+        .routes(routes!(read_client, update_client, delete_client))
+}
+
+/// Registered client as returned by the registration and management endpoints.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ClientInformation {
+    pub client_id: String,
+    /// Only returned when a secret was issued, it can't be read again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    pub client_id_issued_at: i64,
+    /// `0` means the secret doesn't expire.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret_expires_at: Option<i64>,
+    /// Only returned on registration.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registration_access_token: Option<String>,
+    pub registration_client_uri: String,
+    #[serde(flatten)]
+    pub metadata: ClientMetadata,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ClientUpdateRequest {
+    pub client_id: String,
+    #[serde(flatten)]
+    pub metadata: ClientMetadata,
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+fn missing_token() -> Response {
+    token_error(
+        StatusCode::UNAUTHORIZED,
+        "invalid_token",
+        "Missing or invalid Bearer token",
+    )
+}
+
+fn database_error() -> Response {
+    token_error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "server_error",
+        "Database error",
+    )
+}
+
+fn secret_error() -> Response {
+    token_error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "server_error",
+        "Failed to generate client secret",
+    )
+}
+
+fn metadata_error(error: MetadataError) -> Response {
+    token_error(StatusCode::BAD_REQUEST, error.error, &error.description)
+}
+
+fn client_information(
+    state: &AppState,
+    app: &Application,
+    client_secret: Option<String>,
+    registration_access_token: Option<String>,
+) -> ClientInformation {
+    let public_url = state.settings.general.public_url.to_string();
+    let issuer = public_url.trim_end_matches('/');
+
+    ClientInformation {
+        client_id: app.client_id.clone(),
+        client_secret,
+        client_id_issued_at: app.id.timestamp().timestamp_millis() / 1000,
+        client_secret_expires_at: client_secret_expires_at(&app.client_secrets),
+        registration_access_token,
+        registration_client_uri: format!("{issuer}/api/oidc/register/{}", app.client_id),
+        metadata: ClientMetadata::from_application(app),
+    }
+}
+
+/// Keeps the current secrets while the client authenticates with one, and issues one when it starts to.
+/// Returns the issued secret.
+fn issue_client_secret(
+    state: &AppState,
+    settings: &ClientSettings,
+    secrets: &mut Vec<ClientSecret>,
+) -> color_eyre::Result<Option<String>> {
+    let method = settings.token_endpoint_auth_method;
+    if !method.uses_secret() {
+        secrets.clear();
+        return Ok(None);
+    }
+
+    // Secrets stored for another method lack, or no longer need, their encrypted copy
+    let encrypted = keeps_encrypted_secrets(Some(method));
+    if !encrypted {
+        for secret in secrets.iter_mut() {
+            secret.encrypted_secret = None;
+        }
+    }
+
+    if secrets
+        .iter()
+        .any(|secret| !secret.is_expired() && (!encrypted || secret.encrypted_secret.is_some()))
+    {
+        return Ok(None);
+    }
+
+    let (secret, client_secret) =
+        generate_client_secret(&state.encryption_key, Some(method), None)?;
+    secrets.push(client_secret);
+
+    Ok(Some(secret))
+}
+
+/// Checks that the `sector_identifier_uri` lists the client's redirect URIs.
+async fn verify_sector(state: &AppState, settings: &ClientSettings) -> Result<(), Response> {
+    let Some(uri) = &settings.sector_identifier_uri else {
+        return Ok(());
+    };
+
+    verify_sector_identifier_uri(state, uri, &settings.redirect_uris)
+        .await
+        .map_err(|error| {
+            token_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_client_metadata",
+                &error.to_string(),
+            )
+        })
+}
+
+/// Finds the application managed by the registration access token in the request.
+async fn authenticate_registration(
+    state: &AppState,
+    headers: &HeaderMap,
+    client_id: &str,
+) -> Result<Application, Response> {
+    let token = bearer_token(headers).ok_or_else(missing_token)?;
+
+    state
+        .database
+        .collection::<Application>("applications")
+        .find_one(doc! {
+            "client_id": client_id,
+            "registration_access_token_hash": hash_token(token),
+        })
+        .await
+        .map_err(|_| database_error())?
+        .ok_or_else(|| {
+            token_error(
+                StatusCode::UNAUTHORIZED,
+                "invalid_token",
+                "Invalid registration access token",
+            )
+        })
+}
+
+/// Dynamic client registration endpoint (RFC 7591)
+///
+/// Registers a new application from the client metadata. Requires an initial access token issued by an administrator.
+#[utoipa::path(
+    method(post),
+    path = "/",
+    request_body = ClientMetadata,
+    responses(
+        (status = CREATED, description = "Client registered", body = ClientInformation),
+        (status = BAD_REQUEST, description = "Invalid client metadata"),
+        (status = UNAUTHORIZED, description = "Invalid initial access token"),
+    ),
+    tag = "OIDC"
+)]
+async fn register_client(
+    Extension(state): Extension<AppState>,
+    headers: HeaderMap,
+    Json(metadata): Json<ClientMetadata>,
+) -> Result<Response, Response> {
+    let token = bearer_token(&headers).ok_or_else(missing_token)?;
+
+    state
+        .database
+        .collection::<InitialAccessToken>("initial_access_tokens")
+        .find_one(doc! {
+            "token_hash": hash_token(token),
+            "$or": [
+                { "expires_at": null },
+                { "expires_at": { "$gt": bson::DateTime::now() } },
+            ],
+        })
+        .await
+        .map_err(|_| database_error())?
+        .ok_or_else(|| {
+            token_error(
+                StatusCode::UNAUTHORIZED,
+                "invalid_token",
+                "Invalid initial access token",
+            )
+        })?;
+
+    let client_id = generate_client_id();
+    let settings = metadata.to_settings(&client_id).map_err(metadata_error)?;
+    verify_sector(&state, &settings).await?;
+    let registration_access_token = generate_reset_token();
+    let mut client_secrets = Vec::new();
+    let client_secret =
+        issue_client_secret(&state, &settings, &mut client_secrets).map_err(|_| secret_error())?;
+
+    let app = PartialApplication {
+        name: settings.name.clone(),
+        slug: client_id.to_lowercase(),
+        icon: settings.icon.clone(),
+        client_type: settings.client_type.clone(),
+        client_id: client_id.clone(),
+        client_secrets,
+        redirect_uris: settings.redirect_uris,
+        allowed_groups: Vec::new(),
+        require_pkce: matches!(settings.client_type, ClientType::Public),
+        post_logout_redirect_uris: settings.post_logout_redirect_uris,
+        backchannel_logout_uri: None,
+        frontchannel_logout_uri: None,
+        frontchannel_logout_session_required: false,
+        id_token_signed_response_alg: None,
+        allow_client_credentials: settings.allow_client_credentials,
+        client_credentials_scopes: Vec::new(),
+        client_credentials_audiences: Vec::new(),
+        allow_device_authorization: settings.allow_device_authorization,
+        exposed_groups: Vec::new(),
+        trusted: false,
+        custom_scopes: Vec::new(),
+        claim_mappings: Vec::new(),
+        min_security_level: None,
+        require_pushed_authorization_requests: false,
+        jwks: settings.jwks,
+        jwks_uri: settings.jwks_uri,
+        request_uris: settings.request_uris,
+        token_endpoint_auth_method: Some(settings.token_endpoint_auth_method),
+        token_exchange_policies: Vec::new(),
+        subject_type: settings.subject_type,
+        sector_identifier_uri: settings.sector_identifier_uri,
+        token_lifetimes: TokenLifetimeOverrides::default(),
+        registration_access_token_hash: Some(hash_token(&registration_access_token)),
+    };
+
+    state
+        .database
+        .collection::<PartialApplication>("applications")
+        .insert_one(app)
+        .await
+        .map_err(|_| database_error())?;
+
+    let app = state
+        .database
+        .collection::<Application>("applications")
+        .find_one(doc! { "client_id": &client_id })
+        .await
+        .map_err(|_| database_error())?
+        .ok_or_else(database_error)?;
+
+    let information =
+        client_information(&state, &app, client_secret, Some(registration_access_token));
+
+    Ok((StatusCode::CREATED, Json(information)).into_response())
+}
+
+/// Read a registered client (RFC 7592)
+#[utoipa::path(
+    method(get),
+    path = "/{client_id}",
+    params(
+        ("client_id" = String, Path, description = "Client ID of the registered application")
+    ),
+    responses(
+        (status = OK, description = "Client information", body = ClientInformation),
+        (status = UNAUTHORIZED, description = "Invalid registration access token"),
+    ),
+    tag = "OIDC"
+)]
+async fn read_client(
+    Extension(state): Extension<AppState>,
+    headers: HeaderMap,
+    Path(client_id): Path<String>,
+) -> Result<Json<ClientInformation>, Response> {
+    let app = authenticate_registration(&state, &headers, &client_id).await?;
+
+    Ok(Json(client_information(&state, &app, None, None)))
+}
+
+/// Update a registered client (RFC 7592)
+///
+/// Replaces the client metadata. Fields left out of the request are reset to their defaults.
+#[utoipa::path(
+    method(put),
+    path = "/{client_id}",
+    params(
+        ("client_id" = String, Path, description = "Client ID of the registered application")
+    ),
+    request_body = ClientUpdateRequest,
+    responses(
+        (status = OK, description = "Client updated", body = ClientInformation),
+        (status = BAD_REQUEST, description = "Invalid client metadata"),
+        (status = UNAUTHORIZED, description = "Invalid registration access token"),
+    ),
+    tag = "OIDC"
+)]
+async fn update_client(
+    Extension(state): Extension<AppState>,
+    headers: HeaderMap,
+    Path(client_id): Path<String>,
+    Json(body): Json<ClientUpdateRequest>,
+) -> Result<Json<ClientInformation>, Response> {
+    let mut app = authenticate_registration(&state, &headers, &client_id).await?;
+
+    if body.client_id != app.client_id {
+        return Err(token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_client_metadata",
+            "client_id doesn't match the registered client",
+        ));
+    }
+
+    let settings = body
+        .metadata
+        .to_settings(&app.client_id)
+        .map_err(metadata_error)?;
+    verify_sector(&state, &settings).await?;
+
+    let client_secret = issue_client_secret(&state, &settings, &mut app.client_secrets)
+        .map_err(|_| secret_error())?;
+    app.name = settings.name;
+    app.icon = settings.icon;
+    app.client_type = settings.client_type;
+    // Public clients can't keep their codes from being redeemed without PKCE
+    app.require_pkce |= matches!(app.client_type, ClientType::Public);
+    app.redirect_uris = settings.redirect_uris;
+    app.post_logout_redirect_uris = settings.post_logout_redirect_uris;
+    app.allow_client_credentials = settings.allow_client_credentials;
+    app.allow_device_authorization = settings.allow_device_authorization;
+    app.jwks = settings.jwks;
+    app.jwks_uri = settings.jwks_uri;
+    app.request_uris = settings.request_uris;
+    app.token_endpoint_auth_method = Some(settings.token_endpoint_auth_method);
+    app.subject_type = settings.subject_type;
+    app.sector_identifier_uri = settings.sector_identifier_uri;
+
+    let client_type = bson::to_bson(&app.client_type).map_err(|_| database_error())?;
+    let client_secrets = bson::to_bson(&app.client_secrets).map_err(|_| database_error())?;
+    let jwks = bson::to_bson(&app.jwks).map_err(|_| database_error())?;
+    let token_endpoint_auth_method =
+        bson::to_bson(&app.token_endpoint_auth_method).map_err(|_| database_error())?;
+    let subject_type = bson::to_bson(&app.subject_type).map_err(|_| database_error())?;
+
+    state
+        .database
+        .collection::<Application>("applications")
+        .update_one(
+            doc! { "_id": app.id },
+            doc! {
+                "$set": {
+                    "name": &app.name,
+                    "icon": &app.icon,
+                    "client_type": client_type,
+                    "require_pkce": app.require_pkce,
+                    "client_secrets": client_secrets,
+                    "redirect_uris": &app.redirect_uris,
+                    "post_logout_redirect_uris": &app.post_logout_redirect_uris,
+                    "allow_client_credentials": app.allow_client_credentials,
+                    "allow_device_authorization": app.allow_device_authorization,
+                    "jwks": jwks,
+                    "jwks_uri": &app.jwks_uri,
+                    "request_uris": &app.request_uris,
+                    "token_endpoint_auth_method": token_endpoint_auth_method,
+                    "subject_type": subject_type,
+                    "sector_identifier_uri": &app.sector_identifier_uri,
+                }
+            },
+        )
+        .await
+        .map_err(|_| database_error())?;
+
+    Ok(Json(client_information(&state, &app, client_secret, None)))
+}
+
+/// Delete a registered client (RFC 7592)
+///
+/// Removes the application and revokes the refresh tokens issued to it.
+#[utoipa::path(
+    method(delete),
+    path = "/{client_id}",
+    params(
+        ("client_id" = String, Path, description = "Client ID of the registered application")
+    ),
+    responses(
+        (status = NO_CONTENT, description = "Client deleted"),
+        (status = UNAUTHORIZED, description = "Invalid registration access token"),
+    ),
+    tag = "OIDC"
+)]
+async fn delete_client(
+    Extension(state): Extension<AppState>,
+    headers: HeaderMap,
+    Path(client_id): Path<String>,
+) -> Result<StatusCode, Response> {
+    let app = authenticate_registration(&state, &headers, &client_id).await?;
+
+    state
+        .database
+        .collection::<Application>("applications")
+        .delete_one(doc! { "_id": app.id })
+        .await
+        .map_err(|_| database_error())?;
+
+    state
+        .database
+        .collection::<RefreshToken>("refresh_tokens")
+        .update_many(
+            doc! { "client_id": &app.client_id },
+            doc! { "$set": { "revoked": true } },
+        )
+        .await
+        .map_err(|_| database_error())?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,159 @@
+use axum::{
+    Extension,
+    http::{HeaderMap, StatusCode},
+};
+use mongodb::bson::doc;
+use serde::Deserialize;
+use tracing::warn;
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    oidc::{
+        RefreshToken,
+        revocation::{revoke_access_token, revoke_refresh_token_chain},
+    },
+    state::AppState,
+    utils::hash_token,
+};
+
+use super::{authenticate_client, extract_client_credentials, token_error};
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new().routes(routes!(revoke))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RevocationRequest {
+    pub token: String,
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub client_assertion_type: Option<String>,
+    pub client_assertion: Option<String>,
+}
+
+/// OAuth2 Token Revocation endpoint (RFC 7009)
+///
+/// Revokes an access token or a refresh token. Revoking a refresh token also revokes every token rotated from the same grant.
+/// Unknown or already invalid tokens are not treated as an error.
+#[utoipa::path(
+    method(post),
+    path = "/",
+    request_body(content = RevocationRequest, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = OK, description = "Token revoked or already invalid"),
+        (status = BAD_REQUEST, description = "Invalid request"),
+        (status = UNAUTHORIZED, description = "Client authentication failed"),
+    ),
+    tag = "OIDC"
+)]
+async fn revoke(
+    Extension(state): Extension<AppState>,
+    headers: HeaderMap,
+    axum::Form(body): axum::Form<RevocationRequest>,
+) -> Result<StatusCode, axum::response::Response> {
+    let (client_id, credential) = extract_client_credentials(
+        &headers,
+        body.client_id.as_deref(),
+        body.client_secret.as_deref(),
+        body.client_assertion_type.as_deref(),
+        body.client_assertion.as_deref(),
+    );
+    let client_id = client_id.ok_or_else(|| {
+        token_error(
+            StatusCode::UNAUTHORIZED,
+            "invalid_client",
+            "Client authentication required",
+        )
+    })?;
+    authenticate_client(&state, &client_id, &credential).await?;
+
+    // The hint only decides which lookup runs first (RFC 7009, section 2.1)
+    if body.token_type_hint.as_deref() == Some("access_token") {
+        if !try_revoke_access_token(&state, &client_id, &body.token).await? {
+            try_revoke_refresh_token(&state, &client_id, &body.token).await?;
+        }
+    } else if !try_revoke_refresh_token(&state, &client_id, &body.token).await? {
+        try_revoke_access_token(&state, &client_id, &body.token).await?;
+    }
+
+    Ok(StatusCode::OK)
+}
+
+/// Returns `Ok(true)` if the token was a refresh token issued to this client.
+async fn try_revoke_refresh_token(
+    state: &AppState,
+    client_id: &str,
+    token: &str,
+) -> Result<bool, axum::response::Response> {
+    let stored = state
+        .database
+        .collection::<RefreshToken>("refresh_tokens")
+        .find_one(doc! { "token_hash": hash_token(token) })
+        .await
+        .map_err(|_| {
+            token_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                "Database error",
+            )
+        })?;
+
+    let Some(stored) = stored else {
+        return Ok(false);
+    };
+
+    if stored.client_id != client_id {
+        return Err(unauthorized_client());
+    }
+
+    revoke_refresh_token_chain(&state.database, &stored)
+        .await
+        .map_err(|error| {
+            warn!(error = ?error, "Failed to revoke refresh token chain");
+            token_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "temporarily_unavailable",
+                "Failed to revoke token",
+            )
+        })?;
+
+    Ok(true)
+}
+
+/// Returns `Ok(true)` if the token was a valid access token issued to this client.
+async fn try_revoke_access_token(
+    state: &AppState,
+    client_id: &str,
+    token: &str,
+) -> Result<bool, axum::response::Response> {
+    let Ok(claims) = state.oidc_keys.verify_access_token(token) else {
+        return Ok(false);
+    };
+
+    if claims.client_id != client_id {
+        return Err(unauthorized_client());
+    }
+
+    revoke_access_token(&state.redis_pool, &claims.jti, claims.exp)
+        .await
+        .map_err(|error| {
+            warn!(error = ?error, "Failed to revoke access token");
+            token_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "temporarily_unavailable",
+                "Failed to revoke token",
+            )
+        })?;
+
+    Ok(true)
+}
+
+fn unauthorized_client() -> axum::response::Response {
+    token_error(
+        StatusCode::BAD_REQUEST,
+        "unauthorized_client",
+        "Token was not issued to this client",
+    )
+}
@@ -19,7 +19,7 @@ pub fn routes() -> OpenApiRouter<AppState> {
 async fn openid_configuration(Extension(state): Extension<AppState>) -> impl IntoResponse {
     let issuer = state.settings.general.public_url.to_string();
     let issuer = issuer.trim_end_matches('/');
-    match build_provider_metadata(issuer) {
+    match build_provider_metadata(issuer, &state.settings.oidc) {
         Ok(metadata) => Json(metadata).into_response(),
         Err(_) => (
             StatusCode::INTERNAL_SERVER_ERROR,
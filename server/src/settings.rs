@@ -6,6 +6,9 @@ use http::Uri;
 use serde::{Deserialize, Serialize};
 use strum::{AsRefStr, Display, EnumString, IntoStaticStr};
 use tracing::warn;
+use validator::Validate;
+
+use crate::oidc::{keys::SigningAlgorithm, lifetimes::TokenLifetimes};
 
 const ENV_PREFIX: &str = "AGINAUTH";
 const ENV_SEPARATOR: &str = "_";
@@ -77,15 +80,62 @@ pub struct WebAuthn {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Oidc {
+    /// PEM key imported as the first active signing key when the key ring is empty.
     pub signing_key_file: String,
+
+    /// Key that encrypts client secrets and signing keys in the database, generated on first start.
+    /// Every instance has to use the same one.
+    #[serde(default = "Oidc::default_encryption_key_file")]
+    pub encryption_key_file: String,
+
+    /// Algorithm for access tokens, and for ID tokens of applications that don't set
+    /// `id_token_signed_response_alg`.
+    #[serde(default)]
+    pub signing_algorithm: SigningAlgorithm,
+
+    /// Rotate the active signing key automatically after this many days.
+    /// Keys are only rotated through the admin API when unset.
+    #[serde(default)]
+    pub key_rotation_interval_days: Option<u32>,
+
+    /// How long a retired signing key stays in the JWKS, so tokens signed with it remain verifiable.
+    /// Extended to the longest access or ID token lifetime, including application overrides.
+    #[serde(default = "Oidc::default_retired_key_lifetime_days")]
+    pub retired_key_lifetime_days: u32,
+
+    /// Name of the claim that lists the user's groups when the `groups` scope is granted.
+    #[serde(default = "Oidc::default_groups_claim")]
+    pub groups_claim: String,
+
+    /// Lifetimes of codes and tokens. Applications can override each of them.
+    #[serde(default)]
+    pub token_lifetimes: TokenLifetimes,
 }
 
 impl Oidc {
     pub fn default() -> Self {
         Self {
             signing_key_file: "oidc-signing-key.pem".to_string(),
+            encryption_key_file: Self::default_encryption_key_file(),
+            signing_algorithm: SigningAlgorithm::default(),
+            key_rotation_interval_days: None,
+            retired_key_lifetime_days: Self::default_retired_key_lifetime_days(),
+            groups_claim: Self::default_groups_claim(),
+            token_lifetimes: TokenLifetimes::default(),
         }
     }
+
+    fn default_encryption_key_file() -> String {
+        "oidc-encryption-key".to_string()
+    }
+
+    fn default_retired_key_lifetime_days() -> u32 {
+        7
+    }
+
+    fn default_groups_claim() -> String {
+        "groups".to_string()
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -121,7 +171,15 @@ impl Settings {
             .add_source(File::with_name("config-local").required(false))
             .add_source(Environment::with_prefix(ENV_PREFIX).separator(ENV_SEPARATOR));
 
-        settings.build()?.try_deserialize()
+        let settings: Self = settings.build()?.try_deserialize()?;
+
+        settings
+            .oidc
+            .token_lifetimes
+            .validate()
+            .map_err(|e| ConfigError::Message(format!("Invalid oidc.token_lifetimes: {e}")))?;
+
+        Ok(settings)
     }
 
     pub fn try_load() -> color_eyre::Result<Self> {
@@ -152,8 +210,11 @@ impl Settings {
 
             // Generate OIDC signing key alongside the example config
             crate::oidc::generate_oidc_key_file(&example_settings.oidc.signing_key_file)?;
+            crate::oidc::encryption::generate_encryption_key_file(
+                &example_settings.oidc.encryption_key_file,
+            )?;
 
-            res = res.suggestion("An example configuration file has been created at `config.toml` in the current directory. An OIDC signing key has also been generated.");
+            res = res.suggestion("An example configuration file has been created at `config.toml` in the current directory. An OIDC signing key and an encryption key have also been generated.");
         }
 
         res
@@ -5,7 +5,10 @@ use mail::MailService;
 use mongodb::Database;
 use webauthn_rs::Webauthn;
 
-use crate::{oidc::OidcKeys, settings::Settings};
+use crate::{
+    oidc::{OidcKeys, encryption::EncryptionKey},
+    settings::Settings,
+};
 
 #[derive(Clone)]
 pub struct AppState {
@@ -15,4 +18,9 @@ pub struct AppState {
     pub mail_service: Option<Arc<MailService>>,
     pub oidc_keys: Arc<OidcKeys>,
     pub redis_pool: Pool,
+    pub http_client: openidconnect::reqwest::Client,
+    /// Salt of pairwise subject identifiers, see [`crate::oidc::subject`].
+    pub pairwise_salt: Arc<str>,
+    /// Encrypts secrets stored in the database, see [`crate::oidc::encryption`].
+    pub encryption_key: Arc<EncryptionKey>,
 }
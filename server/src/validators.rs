@@ -1,5 +1,9 @@
+use jsonwebtoken::jwk::JwkSet;
+use url::{Host, Url};
 use validator::ValidationError;
 
+use crate::oidc::claims::{ClaimMapping, CustomScope, RESERVED_CLAIMS, STANDARD_SCOPES};
+
 pub fn is_valid_username(s: &str) -> bool {
     s.chars()
         .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
@@ -31,3 +35,96 @@ pub fn slug_validator(s: &str) -> Result<(), ValidationError> {
 
     Ok(())
 }
+
+/// Scope tokens as defined in RFC 6749, section 3.3.
+fn is_valid_scope(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c == '!' || ('#'..='[').contains(&c) || (']'..='~').contains(&c))
+}
+
+pub fn custom_scopes_validator(scopes: &[CustomScope]) -> Result<(), ValidationError> {
+    for (i, scope) in scopes.iter().enumerate() {
+        if !is_valid_scope(&scope.name) {
+            return Err(ValidationError::new("invalid_format"));
+        }
+
+        if STANDARD_SCOPES.contains(&scope.name.as_str()) {
+            return Err(ValidationError::new("reserved_word"));
+        }
+
+        if scopes[..i].iter().any(|other| other.name == scope.name) {
+            return Err(ValidationError::new("duplicate"));
+        }
+    }
+
+    Ok(())
+}
+
+/// URLs the server fetches from have to use `https`, so they can't point it at plain-text services.
+pub fn is_https_url(s: &str) -> bool {
+    Url::parse(s).is_ok_and(|url| url.scheme() == "https" && url.fragment().is_none())
+}
+
+pub fn https_url_validator(s: &str) -> Result<(), ValidationError> {
+    if !is_https_url(s) {
+        return Err(ValidationError::new("invalid_format"));
+    }
+
+    Ok(())
+}
+
+pub fn https_urls_validator(urls: &[String]) -> Result<(), ValidationError> {
+    urls.iter().try_for_each(|url| https_url_validator(url))
+}
+
+/// Redirect URIs the server may send users to (RFC 8252, sections 7.1 and 7.3): `https`, `http`
+/// on a loopback host for native apps, or a private-use scheme in reverse domain name form.
+/// Anything else, like `javascript:` or `data:`, could run in the server's origin.
+pub fn is_valid_redirect_uri(uri: &str) -> bool {
+    let Ok(url) = Url::parse(uri) else {
+        return false;
+    };
+    if url.fragment().is_some() {
+        return false;
+    }
+
+    match url.scheme() {
+        "https" => url.host().is_some(),
+        "http" => match url.host() {
+            Some(Host::Domain(domain)) => domain == "localhost",
+            Some(Host::Ipv4(ip)) => ip.is_loopback(),
+            Some(Host::Ipv6(ip)) => ip.is_loopback(),
+            None => false,
+        },
+        scheme => scheme.contains('.'),
+    }
+}
+
+pub fn redirect_uris_validator(uris: &[String]) -> Result<(), ValidationError> {
+    if !uris.iter().all(|uri| is_valid_redirect_uri(uri)) {
+        return Err(ValidationError::new("invalid_format"));
+    }
+
+    Ok(())
+}
+
+pub fn jwks_validator(jwks: &serde_json::Value) -> Result<(), ValidationError> {
+    serde_json::from_value::<JwkSet>(jwks.clone())
+        .map(|_| ())
+        .map_err(|_| ValidationError::new("invalid_format"))
+}
+
+pub fn claim_mappings_validator(mappings: &[ClaimMapping]) -> Result<(), ValidationError> {
+    for mapping in mappings {
+        if mapping.claim.is_empty() || !is_valid_scope(&mapping.scope) {
+            return Err(ValidationError::new("invalid_format"));
+        }
+
+        if RESERVED_CLAIMS.contains(&mapping.claim.as_str()) {
+            return Err(ValidationError::new("reserved_word"));
+        }
+    }
+
+    Ok(())
+}